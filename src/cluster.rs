@@ -1,14 +1,17 @@
-use crate::Error;
-use poise::serenity_prelude::{self as serenity, ChannelId};
+use crate::{Context, Error};
+use crate::transport::ClusterTransport;
+use poise::serenity_prelude as serenity;
 use serde::{Serialize, Deserialize};
 use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 use tokio::time::{sleep, Duration};
 
 const HEARTBEAT_INTERVAL: u64 = 10;
 const LEADER_TIMEOUT: u64 = 60;
+pub const SHUTDOWN_ACK_TIMEOUT: u64 = 5;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InstanceInfo {
@@ -16,18 +19,109 @@ pub struct InstanceInfo {
     pub priority: i32,
     pub last_seen: u64,
     pub is_leader: bool,
+    #[serde(default)]
+    pub term: u64,
+    #[serde(default)]
+    pub started_at: u64,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub guild_count: usize,
+    /// Set after the instance stepped down, so it does not immediately preempt its successor.
+    #[serde(default)]
+    pub yielded: bool,
+}
+
+/// Issued by the leader for every leader-only side effect. Tokens compare by term
+/// first, so anything stamped by a leader from an older term sorts below the
+/// current one and can be rejected by followers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FencingToken {
+    pub term: u64,
+    pub sequence: u64,
+}
+
+impl fmt::Display for FencingToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.term, self.sequence)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClusterMessage {
     Heartbeat(InstanceInfo),
-    ConfigUpdate,
-    LeaderAnnouncement(String),
+    ConfigChange {
+        change: crate::utils::ConfigChange,
+        token: FencingToken,
+    },
+    LeaderAnnouncement {
+        instance_id: String,
+        term: u64,
+    },
     TicketTemplateUpdate {
         guild_id: u64,
         content: String,
+        token: FencingToken,
+    },
+    PollUpdate {
+        poll: crate::storage::Poll,
+        token: FencingToken,
+    },
+    CountdownUpdate {
+        countdown: crate::storage::Countdown,
+        token: FencingToken,
     },
     ConfigRequest,
+    ConfigSnapshot {
+        content: String,
+        token: FencingToken,
+    },
+    JoinRequest {
+        instance_id: String,
+        priority: i32,
+        nonce: u64,
+    },
+    JoinConflict {
+        nonce: u64,
+        instance_id: String,
+        priority: i32,
+    },
+    StepDown {
+        instance_id: String,
+        successor: String,
+        term: u64,
+    },
+    Resign {
+        instance_id: String,
+        term: u64,
+    },
+    Shutdown {
+        request_id: u64,
+        target: Option<String>,
+        requested_by: String,
+    },
+    ShutdownAck {
+        request_id: u64,
+        instance_id: String,
+        error: Option<String>,
+    },
+}
+
+impl ClusterMessage {
+    /// The instance a message claims to come from, for messages that name one.
+    pub fn sender(&self) -> Option<&str> {
+        match self {
+            ClusterMessage::Heartbeat(info) => Some(&info.instance_id),
+            ClusterMessage::LeaderAnnouncement { instance_id, .. }
+            | ClusterMessage::JoinRequest { instance_id, .. }
+            | ClusterMessage::JoinConflict { instance_id, .. }
+            | ClusterMessage::StepDown { instance_id, .. }
+            | ClusterMessage::Resign { instance_id, .. }
+            | ClusterMessage::ShutdownAck { instance_id, .. } => Some(instance_id),
+            ClusterMessage::Shutdown { requested_by, .. } => Some(requested_by),
+            _ => None,
+        }
+    }
 }
 
 pub struct ClusterState {
@@ -36,174 +130,813 @@ pub struct ClusterState {
     pub my_instance_id: String,
     pub my_priority: i32,
     pub is_leader: bool,
-    pub coordination_channel_id: u64,
+    pub term: u64,
+    fencing_sequence: u64,
+    /// The newest token accepted for each replicated entity, so updates that arrive
+    /// out of order can't overwrite newer state.
+    accepted: HashMap<String, FencingToken>,
+    pub started_at: u64,
+    pub yielded: bool,
+    pub resigned: bool,
+    /// Acknowledgements for shutdown requests this instance sent, keyed by request id.
+    pub shutdown_acks: HashMap<u64, Vec<(String, Option<String>)>>,
+    pub joined: bool,
+    join_nonce: u64,
+    join_conflict: Option<String>,
 }
 
 impl ClusterState {
-    pub fn new(instance_id: String, priority: i32, coordination_channel_id: u64) -> Self {
+    pub fn new(instance_id: String, priority: i32) -> Self {
         ClusterState {
             instances: HashMap::new(),
             current_leader: None,
             my_instance_id: instance_id,
             my_priority: priority,
             is_leader: false,
-            coordination_channel_id,
+            term: 0,
+            fencing_sequence: 0,
+            accepted: HashMap::new(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            yielded: false,
+            resigned: false,
+            shutdown_acks: HashMap::new(),
+            joined: false,
+            join_nonce: rand::random::<u64>(),
+            join_conflict: None,
         }
     }
 
     pub fn update_instance(&mut self, info: InstanceInfo) {
+        let announced_leader = info.is_leader.then(|| (info.instance_id.clone(), info.term));
         self.instances.insert(info.instance_id.clone(), info);
+        if let Some((instance_id, term)) = announced_leader {
+            self.observe_leader(instance_id, term);
+        }
         self.check_leader();
     }
 
+    // Lower priority wins, ties go to the lexically smallest instance id so every
+    // instance ranks the same pair the same way.
+    fn election_rank(&self, instance_id: &str) -> (std::cmp::Reverse<i32>, std::cmp::Reverse<String>) {
+        let priority = self.instances.get(instance_id)
+            .map(|info| info.priority)
+            .unwrap_or(i32::MAX);
+        (std::cmp::Reverse(priority), std::cmp::Reverse(instance_id.to_owned()))
+    }
+
     pub fn check_leader(&mut self) -> bool {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        self.instances.retain(|_, info| now - info.last_seen <= LEADER_TIMEOUT);
+        self.instances.retain(|_, info| now.saturating_sub(info.last_seen) <= LEADER_TIMEOUT);
+        // Instances that stepped down only win again once nobody else is left.
         let best_candidate = self.instances.values()
-            .filter(|info| now - info.last_seen <= LEADER_TIMEOUT)
-            .max_by_key(|info| (
-                std::cmp::Reverse(info.priority),
-                info.last_seen
-            ));
-        let new_leader_id = best_candidate.map(|info| info.instance_id.clone());
+            .filter(|info| !info.yielded)
+            .map(|info| &info.instance_id)
+            .max_by_key(|id| self.election_rank(id))
+            .or_else(|| self.instances.keys().max_by_key(|id| self.election_rank(id)))
+            .cloned();
+        let current_alive = self.current_leader.as_ref()
+            .filter(|id| self.instances.contains_key(*id))
+            .cloned();
+        // A live leader keeps its term unless a strictly better candidate shows up.
+        let new_leader_id = match (current_alive, best_candidate) {
+            (Some(current), Some(best)) if self.election_rank(&best) > self.election_rank(&current) => Some(best),
+            (Some(current), _) => Some(current),
+            (None, best) => best,
+        };
         let leadership_changed = new_leader_id != self.current_leader;
         if leadership_changed {
+            let was_leader = self.is_leader;
             self.current_leader = new_leader_id.clone();
             self.is_leader = new_leader_id.as_deref() == Some(&self.my_instance_id);
+            if self.is_leader && !was_leader {
+                let highest_seen = self.instances.values().map(|info| info.term).max().unwrap_or(0);
+                self.term = self.term.max(highest_seen) + 1;
+                self.fencing_sequence = 0;
+                self.yielded = false;
+            }
         }
         leadership_changed
     }
+
+    /// Applies a leadership claim from another instance. Returns false if the claim
+    /// is from an older term, or loses the tie-break against the leader we already
+    /// follow in the same term.
+    pub fn observe_leader(&mut self, instance_id: String, term: u64) -> bool {
+        if term < self.term {
+            return false;
+        }
+        if term == self.term {
+            if let Some(current) = &self.current_leader {
+                if *current != instance_id && self.election_rank(current) > self.election_rank(&instance_id) {
+                    return false;
+                }
+            }
+        }
+        if self.is_leader && instance_id != self.my_instance_id {
+            println!("Stepping down: instance {instance_id} leads term {term}");
+        }
+        self.term = term;
+        self.is_leader = instance_id == self.my_instance_id;
+        if self.is_leader {
+            self.yielded = false;
+        }
+        if let Some(info) = self.instances.get_mut(&instance_id) {
+            info.is_leader = true;
+            info.term = term;
+        }
+        self.current_leader = Some(instance_id);
+        true
+    }
+
+    /// Hands leadership to the next-best live instance in a new term. Returns the
+    /// successor and the new term, or None if this instance isn't leading or is alone.
+    pub fn step_down(&mut self) -> Option<(String, u64)> {
+        if !self.is_leader {
+            return None;
+        }
+        let successor = self.instances.keys()
+            .filter(|id| **id != self.my_instance_id)
+            .max_by_key(|id| self.election_rank(id))
+            .cloned()?;
+        let term = self.term + 1;
+        self.yielded = true;
+        if let Some(info) = self.instances.get_mut(&self.my_instance_id) {
+            info.yielded = true;
+            info.is_leader = false;
+        }
+        self.observe_leader(successor.clone(), term);
+        Some((successor, term))
+    }
+
+    /// Forgets an instance that announced it is leaving and re-runs the election
+    /// right away instead of waiting for its heartbeats to time out.
+    pub fn remove_instance(&mut self, instance_id: &str) -> bool {
+        self.instances.remove(instance_id);
+        if self.current_leader.as_deref() == Some(instance_id) {
+            self.current_leader = None;
+        }
+        self.check_leader()
+    }
+
+    /// Returns a fresh fencing token if this instance is still the leader.
+    pub fn issue_token(&mut self) -> Option<FencingToken> {
+        if !self.is_leader {
+            return None;
+        }
+        self.fencing_sequence += 1;
+        Some(FencingToken {
+            term: self.term,
+            sequence: self.fencing_sequence,
+        })
+    }
+
+    /// Checks a token attached to a replicated side effect. Tokens from an older
+    /// term are rejected; a newer term means another leader exists, so we step down.
+    pub fn accept_token(&mut self, token: FencingToken) -> bool {
+        if token.term < self.term {
+            return false;
+        }
+        if token.term > self.term {
+            if self.is_leader {
+                println!("Stepping down: saw fencing token {token} from a newer term");
+                self.is_leader = false;
+                self.current_leader = None;
+            }
+            self.term = token.term;
+        }
+        true
+    }
+
+    /// Like `accept_token`, but also rejects a token that is not newer than the last
+    /// one accepted for `entity`.
+    pub fn accept_update(&mut self, entity: String, token: FencingToken) -> bool {
+        if !self.accept_token(token) {
+            return false;
+        }
+        if self.accepted.get(&entity).is_some_and(|last| *last >= token) {
+            return false;
+        }
+        self.accepted.insert(entity, token);
+        true
+    }
+}
+
+/// How a command behaves when every instance receives the same invocation. Set per
+/// command with `custom_data = "InstancePolicy::..."`; commands without one are leader-only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstancePolicy {
+    LeaderOnly,
+    /// For read-only commands. A slash command runs on every instance and the first
+    /// reply wins, so it still answers while the leader is down; a prefix command
+    /// would be answered once per instance, so only the leader takes those.
+    AnyInstance,
+    /// Runs on the instance named by the command's `instance_id` argument, or on every
+    /// instance when it is omitted.
+    SpecificInstance,
+}
+
+fn requested_instance(ctx: Context<'_>) -> Option<String> {
+    match ctx {
+        poise::Context::Prefix(prefix) => prefix.args.split_whitespace().next().map(|s| s.to_owned()),
+        poise::Context::Application(app) => app.args.iter()
+            .find(|option| option.name == "instance_id")
+            .and_then(|option| match &option.value {
+                poise::serenity_prelude::ResolvedValue::String(s) => Some((*s).to_owned()),
+                _ => None,
+            }),
+    }
+}
+
+pub async fn instance_check(ctx: Context<'_>) -> Result<bool, Error> {
+    let policy = ctx.command().custom_data
+        .downcast_ref::<InstancePolicy>()
+        .copied()
+        .unwrap_or(InstancePolicy::LeaderOnly);
+    match policy {
+        InstancePolicy::AnyInstance => match ctx {
+            poise::Context::Application(_) => Ok(true),
+            poise::Context::Prefix(_) => Ok(ctx.data().cluster_state.lock().await.is_leader),
+        },
+        InstancePolicy::SpecificInstance => {
+            let state = ctx.data().cluster_state.lock().await;
+            Ok(requested_instance(ctx).is_none_or(|id| id == state.my_instance_id))
+        }
+        InstancePolicy::LeaderOnly => {
+            let token = ctx.data().cluster_state.lock().await.issue_token();
+            match token {
+                Some(token) => {
+                    ctx.set_invocation_data(token).await;
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+    }
+}
+
+/// The fencing token `instance_check` issued for this leader-only invocation.
+pub async fn leader_token(ctx: Context<'_>) -> Result<FencingToken, Error> {
+    ctx.invocation_data::<FencingToken>().await
+        .map(|token| *token)
+        .ok_or_else(|| "Leader-only command ran without a fencing token".into())
+}
+
+/// Re-checks, right before a leader-only side effect, that this instance still leads
+/// the term the command was admitted in, and returns a fresh token for the effect.
+pub async fn confirm_leader(ctx: Context<'_>) -> Result<FencingToken, Error> {
+    let admitted = leader_token(ctx).await?;
+    let token = ctx.data().cluster_state.lock().await.issue_token();
+    match token {
+        Some(token) if token.term == admitted.term => Ok(token),
+        _ => {
+            ctx.say("⚠️ Another instance took over before this could run. Please try again.").await?;
+            Err(format!("Lost leadership of term {} before acting", admitted.term).into())
+        }
+    }
+}
+
+/// Tells the rest of the cluster this instance is going away so a successor can
+/// take over without waiting for `LEADER_TIMEOUT`.
+pub async fn resign(transport: &dyn ClusterTransport, cluster_state: &Mutex<ClusterState>) {
+    let resignation = {
+        let mut state = cluster_state.lock().await;
+        let my_instance_id = state.my_instance_id.clone();
+        state.resigned = true;
+        state.is_leader = false;
+        state.remove_instance(&my_instance_id);
+        ClusterMessage::Resign {
+            instance_id: my_instance_id,
+            term: state.term,
+        }
+    };
+    if let Err(e) = transport.send(&resignation).await {
+        println!("Failed to send resignation: {e}");
+    }
+}
+
+/// Posts the BootQuit log in every guild, resigns from the cluster and stops the
+/// shards. Config should already be saved.
+pub async fn finish_shutdown(
+    ctx: &serenity::Context,
+    transport: &dyn ClusterTransport,
+    cluster_state: &Mutex<ClusterState>,
+    shard_manager: &serenity::ShardManager,
+) {
+    let my_instance_id = cluster_state.lock().await.my_instance_id.clone();
+    for guild_id in ctx.cache.guilds() {
+        if let Some(log_channel) = crate::utils::get_logging_channel(guild_id.into(), crate::utils::LogEventType::BootQuit).await {
+            let embed = serenity::CreateEmbed::new()
+                .title("Instance Shutting Down")
+                .description(format!("{my_instance_id} is being shut down!"))
+                .color(serenity::Colour::DARK_RED);
+            if let Err(e) = log_channel.send_message(&ctx.http, serenity::CreateMessage::new().embed(embed)).await {
+                println!("Failed to send shutdown announcement to guild {guild_id}: {e}");
+            }
+        }
+    }
+    resign(transport, cluster_state).await;
+    shard_manager.shutdown_all().await;
+}
+
+/// Saves config and resigns from the cluster once the process receives SIGINT or
+/// SIGTERM, then shuts the shards down.
+pub async fn handoff_on_signal(
+    transport: Arc<dyn ClusterTransport>,
+    cluster_state: Arc<Mutex<ClusterState>>,
+    shard_manager: Arc<serenity::ShardManager>,
+) {
+    #[cfg(unix)]
+    {
+        let mut sigterm = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(sigterm) => sigterm,
+            Err(e) => {
+                println!("Failed to listen for SIGTERM: {e}");
+                return;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
+    println!("Received shutdown signal, handing off leadership...");
+    if let Err(e) = crate::utils::save_config_to_disk().await {
+        println!("!! FAILED to save config during shutdown: {e}");
+    }
+    resign(transport.as_ref(), &cluster_state).await;
+    shard_manager.shutdown_all().await;
 }
 
 pub async fn start_cluster_loop(
-    ctx: serenity::Context,
-    _data: Arc<Mutex<crate::Data>>,
+    transport: Arc<dyn ClusterTransport>,
     cluster_state: Arc<Mutex<ClusterState>>,
+    cache: Arc<serenity::Cache>,
 ) {
-    let coordination_channel_id = {
+    let join_request = {
         let state = cluster_state.lock().await;
-        state.coordination_channel_id
+        ClusterMessage::JoinRequest {
+            instance_id: state.my_instance_id.clone(),
+            priority: state.my_priority,
+            nonce: state.join_nonce,
+        }
     };
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    if let Err(e) = cluster_channel.send_message(&ctx.http,
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigRequest).unwrap())
-    ).await {
+    if let Err(e) = transport.send(&join_request).await {
+        println!("Failed to send join request: {e}");
+    }
+    if let Err(e) = transport.send(&ClusterMessage::ConfigRequest).await {
         println!("Failed to send config request: {e}");
     }
     sleep(Duration::from_secs(5)).await;
+    {
+        let mut state = cluster_state.lock().await;
+        if let Some(conflict) = &state.join_conflict {
+            println!("!! Refusing to join cluster: {conflict}. Change instance_id/priority in {} and restart.", crate::utils::CLUSTER_CONFIG_PATH);
+            std::process::exit(1);
+        }
+        state.joined = true;
+    }
+    let mut announced_term = None;
     loop {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        let became_leader = {
+        let (announcement, snapshot_token, heartbeat) = {
             let mut state = cluster_state.lock().await;
-            let leadership_changed = state.check_leader();
-            leadership_changed && state.is_leader
-        };
-        let heartbeat = {
-            let state = cluster_state.lock().await;
-            ClusterMessage::Heartbeat(InstanceInfo {
+            if state.resigned {
+                break;
+            }
+            let my_info = InstanceInfo {
                 instance_id: state.my_instance_id.clone(),
                 priority: state.my_priority,
                 last_seen: now,
                 is_leader: state.is_leader,
-            })
+                term: state.term,
+                started_at: state.started_at,
+                version: env!("CARGO_PKG_VERSION").to_owned(),
+                guild_count: cache.guilds().len(),
+                yielded: state.yielded,
+            };
+            // Not every transport echoes our own messages back, so record ourselves directly.
+            state.instances.insert(my_info.instance_id.clone(), my_info.clone());
+            state.check_leader();
+            let announcement = (state.is_leader && announced_term != Some(state.term)).then(|| {
+                announced_term = Some(state.term);
+                ClusterMessage::LeaderAnnouncement {
+                    instance_id: state.my_instance_id.clone(),
+                    term: state.term,
+                }
+            });
+            // A new leader pushes its state so followers that drifted from it converge
+            // instead of dropping its changes as duplicates.
+            let snapshot_token = announcement.is_some().then(|| state.issue_token()).flatten();
+            (announcement, snapshot_token, ClusterMessage::Heartbeat(InstanceInfo {
+                is_leader: state.is_leader,
+                term: state.term,
+                ..my_info
+            }))
         };
-        if let Err(e) = cluster_channel.send_message(&ctx.http,
-            serenity::CreateMessage::new()
-                .content(serde_json::to_string(&heartbeat).unwrap())
-        ).await {
+        if let Err(e) = transport.send(&heartbeat).await {
             println!("Failed to send heartbeat: {e}");
         }
-        if became_leader {
-            let announcement = ClusterMessage::LeaderAnnouncement(
-                cluster_state.lock().await.my_instance_id.clone()
-            );
-            if let Err(e) = cluster_channel.send_message(
-                &ctx.http,
-                serenity::CreateMessage::new()
-                    .content(serde_json::to_string(&announcement).unwrap())
-            ).await {
+        if let Some(announcement) = announcement {
+            if let Err(e) = transport.send(&announcement).await {
                 println!("Failed to send leader announcement: {e}");
             }
         }
+        if let Some(token) = snapshot_token {
+            if let Err(e) = send_snapshot(transport.as_ref(), token).await {
+                println!("Failed to send leader snapshot: {e}");
+            }
+        }
         sleep(Duration::from_secs(HEARTBEAT_INTERVAL)).await;
     }
 }
 
+pub async fn start_inbox_loop(
+    mut inbox: mpsc::UnboundedReceiver<crate::signing::Envelope>,
+    signer: Arc<crate::signing::Signer>,
+    transport: Arc<dyn ClusterTransport>,
+    cluster_state: Arc<Mutex<ClusterState>>,
+    ctx: serenity::Context,
+    shard_manager: Arc<serenity::ShardManager>,
+) {
+    while let Some(envelope) = inbox.recv().await {
+        let cluster_msg = match signer.open(envelope) {
+            Ok(msg) => msg,
+            Err(reason) => {
+                println!("Dropping cluster message: {reason}");
+                continue;
+            }
+        };
+        if let Err(e) = handle_cluster_message(cluster_msg, transport.as_ref(), cluster_state.clone(), &ctx, &shard_manager).await {
+            println!("Failed to handle cluster message: {e}");
+        }
+    }
+}
+
+/// Sends the leader's config and everything still running (polls, countdowns), which
+/// a follower may have missed while it was down or out of touch. Finished ones were
+/// replicated as they changed.
+async fn send_snapshot(transport: &dyn ClusterTransport, token: FencingToken) -> Result<(), Error> {
+    let content = crate::utils::get_config_as_string().await?;
+    transport.send(&ClusterMessage::ConfigSnapshot { content, token }).await?;
+    for poll in crate::storage::get().open_polls(None)? {
+        transport.send(&ClusterMessage::PollUpdate { poll, token }).await?;
+    }
+    for countdown in crate::storage::get().active_countdowns(None)? {
+        transport.send(&ClusterMessage::CountdownUpdate { countdown, token }).await?;
+    }
+    Ok(())
+}
+
 pub async fn handle_cluster_message(
-    ctx: &serenity::Context,
-    message: &serenity::Message,
+    cluster_msg: ClusterMessage,
+    transport: &dyn ClusterTransport,
     cluster_state: Arc<Mutex<ClusterState>>,
+    ctx: &serenity::Context,
+    shard_manager: &serenity::ShardManager,
 ) -> Result<(), Error> {
-    let coordination_channel_id = {
-        let state = cluster_state.lock().await;
-        state.coordination_channel_id
-    };
-    if message.channel_id != coordination_channel_id {
-        return Ok(());
-    }
-    let cluster_msg: ClusterMessage = match serde_json::from_str(&message.content) {
-        Ok(msg) => msg,
-        Err(_) => return Ok(()),
-    };
     match cluster_msg {
         ClusterMessage::ConfigRequest => {
-            let state = cluster_state.lock().await;
-            if state.is_leader {
-                let config_str = crate::utils::get_config_as_string().await?;
-                let cluster_channel = ChannelId::new(coordination_channel_id);
-                cluster_channel.send_files(
-                    &ctx.http,
-                    vec![serenity::CreateAttachment::bytes(config_str.as_bytes(), "config.toml")],
-                    serenity::CreateMessage::new().content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-                ).await?;
+            let token = cluster_state.lock().await.issue_token();
+            if let Some(token) = token {
+                send_snapshot(transport, token).await?;
+            }
+        }
+        ClusterMessage::JoinRequest { instance_id, priority, nonce } => {
+            let reply = {
+                let state = cluster_state.lock().await;
+                let clashes = instance_id == state.my_instance_id || priority == state.my_priority;
+                (state.joined && nonce != state.join_nonce && clashes).then(|| ClusterMessage::JoinConflict {
+                    nonce,
+                    instance_id: state.my_instance_id.clone(),
+                    priority: state.my_priority,
+                })
+            };
+            if let Some(reply) = reply {
+                println!("Rejecting join of instance {instance_id} (priority {priority}): clashes with this instance");
+                transport.send(&reply).await?;
+            }
+        }
+        ClusterMessage::JoinConflict { nonce, instance_id, priority } => {
+            let mut state = cluster_state.lock().await;
+            if !state.joined && nonce == state.join_nonce {
+                let reason = if instance_id == state.my_instance_id {
+                    format!("instance ID '{instance_id}' is already used by a running instance")
+                } else {
+                    format!("priority {priority} is already used by instance '{instance_id}'")
+                };
+                state.join_conflict = Some(reason);
             }
         }
         ClusterMessage::Heartbeat(info) => {
             let mut state = cluster_state.lock().await;
             state.update_instance(info);
-            state.check_leader();
         }
-        ClusterMessage::ConfigUpdate => {
-            let state = cluster_state.lock().await;
-            if state.is_leader {
-                return Ok(());
+        ClusterMessage::ConfigChange { change, token } => {
+            {
+                let mut state = cluster_state.lock().await;
+                if !state.accept_token(token) {
+                    println!("Rejected config change with stale fencing token {token}");
+                    return Ok(());
+                }
+                if state.is_leader {
+                    return Ok(());
+                }
             }
-            if let Some(attachment) = message.attachments.first() {
-                match attachment.download().await {
-                    Ok(bytes) => {
-                        if let Ok(config_str) = String::from_utf8(bytes) {
-                            crate::utils::update_config_from_str(&config_str).await?;
-                            crate::utils::save_config_to_disk().await?;
-                            println!("Successfully synced config via attachment.");
-                        }
-                    },
-                    Err(e) => println!("Failed to download config attachment: {e}"),
+            match crate::utils::apply_config_change(&change).await? {
+                crate::utils::ChangeOutcome::Applied => {
+                    crate::utils::save_config_to_disk().await?;
+                }
+                crate::utils::ChangeOutcome::Duplicate => {
+                    println!("Ignoring config change {} for guild {}: already applied", change.version, change.guild_id);
+                }
+                crate::utils::ChangeOutcome::Gap { expected } => {
+                    println!(
+                        "Missed config changes for guild {} (expected version {expected}, got {}). Requesting snapshot.",
+                        change.guild_id, change.version
+                    );
+                    transport.send(&ClusterMessage::ConfigRequest).await?;
+                }
+                crate::utils::ChangeOutcome::Diverged => {
+                    println!(
+                        "Config for guild {} differs from the leader's at version {}. Requesting snapshot.",
+                        change.guild_id, change.version
+                    );
+                    transport.send(&ClusterMessage::ConfigRequest).await?;
                 }
             }
-        },
-        ClusterMessage::LeaderAnnouncement(instance_id) => {
+        }
+        ClusterMessage::ConfigSnapshot { content, token } => {
+            {
+                let mut state = cluster_state.lock().await;
+                if !state.accept_update("config".to_owned(), token) {
+                    println!("Rejected config snapshot with stale fencing token {token}");
+                    return Ok(());
+                }
+                if state.is_leader {
+                    return Ok(());
+                }
+            }
+            crate::utils::update_config_from_str(&content).await?;
+            crate::utils::save_config_to_disk().await?;
+            println!("Successfully synced config from leader snapshot.");
+        }
+        ClusterMessage::StepDown { instance_id, successor, term } => {
             let mut state = cluster_state.lock().await;
             if let Some(info) = state.instances.get_mut(&instance_id) {
-                info.is_leader = true;
+                info.yielded = true;
+                info.is_leader = false;
+            }
+            if state.observe_leader(successor.clone(), term) {
+                println!("Instance {instance_id} stepped down; {successor} leads term {term}");
             }
-            state.current_leader = Some(instance_id);
-            state.is_leader = state.current_leader.as_deref() == Some(&state.my_instance_id);
         }
-        ClusterMessage::TicketTemplateUpdate { guild_id, content } => {
-            let path = crate::utils::get_ticket_template_path(guild_id);
-            if let Err(e) = std::fs::create_dir_all("./ticket_templates")
-                .and_then(|_| std::fs::write(path, content))
-            {
+        ClusterMessage::Resign { instance_id, term } => {
+            let announcement = {
+                let mut state = cluster_state.lock().await;
+                if instance_id == state.my_instance_id {
+                    return Ok(());
+                }
+                println!("Instance {instance_id} resigned from the cluster in term {term}");
+                (state.remove_instance(&instance_id) && state.is_leader).then(|| ClusterMessage::LeaderAnnouncement {
+                    instance_id: state.my_instance_id.clone(),
+                    term: state.term,
+                })
+            };
+            if let Some(announcement) = announcement {
+                transport.send(&announcement).await?;
+            }
+        }
+        ClusterMessage::Shutdown { request_id, target, requested_by } => {
+            let my_instance_id = cluster_state.lock().await.my_instance_id.clone();
+            if requested_by == my_instance_id || target.as_ref().is_some_and(|target| *target != my_instance_id) {
+                return Ok(());
+            }
+            println!("Shutdown requested by instance {requested_by}");
+            let error = crate::utils::save_config_to_disk().await.err().map(|e| e.to_string());
+            transport.send(&ClusterMessage::ShutdownAck {
+                request_id,
+                instance_id: my_instance_id,
+                error: error.clone(),
+            }).await?;
+            match error {
+                None => finish_shutdown(ctx, transport, &cluster_state, shard_manager).await,
+                Some(e) => println!("!! Not shutting down, failed to save config: {e}"),
+            }
+        }
+        ClusterMessage::ShutdownAck { request_id, instance_id, error } => {
+            let mut state = cluster_state.lock().await;
+            if let Some(acks) = state.shutdown_acks.get_mut(&request_id) {
+                acks.push((instance_id, error));
+            }
+        }
+        ClusterMessage::LeaderAnnouncement { instance_id, term } => {
+            let mut state = cluster_state.lock().await;
+            if !state.observe_leader(instance_id.clone(), term) {
+                println!("Ignored leader announcement from {instance_id} for term {term}");
+            }
+        }
+        ClusterMessage::TicketTemplateUpdate { guild_id, content, token } => {
+            if !cluster_state.lock().await.accept_update(format!("template:{guild_id}"), token) {
+                println!("Rejected ticket template update with stale fencing token {token}");
+                return Ok(());
+            }
+            if let Err(e) = crate::storage::get().set_ticket_template(guild_id, &content) {
                 println!("Failed to save ticket template for guild {guild_id}: {e}");
             }
         }
+        ClusterMessage::PollUpdate { poll, token } => {
+            if !cluster_state.lock().await.accept_update(format!("poll:{}:{}", poll.guild_id, poll.poll_id), token) {
+                println!("Rejected poll update with stale fencing token {token}");
+                return Ok(());
+            }
+            if let Err(e) = crate::storage::get().save_poll(&poll) {
+                println!("Failed to save poll #{} for guild {}: {e}", poll.poll_id, poll.guild_id);
+            }
+        }
+        ClusterMessage::CountdownUpdate { countdown, token } => {
+            if !cluster_state.lock().await.accept_update(format!("countdown:{}:{}", countdown.guild_id, countdown.countdown_id), token) {
+                println!("Rejected countdown update with stale fencing token {token}");
+                return Ok(());
+            }
+            if let Err(e) = crate::storage::get().save_countdown(&countdown) {
+                println!("Failed to save countdown #{} for guild {}: {e}", countdown.countdown_id, countdown.guild_id);
+            }
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::{Envelope, Signer};
+    use crate::transport::{MemoryHub, MemoryTransport};
+    use crate::utils::{ChangeOutcome, GuildConfig};
+
+    const SECRET: &str = "test secret";
+
+    struct Instance {
+        state: ClusterState,
+        transport: MemoryTransport,
+        signer: Arc<Signer>,
+        inbox: mpsc::UnboundedReceiver<Envelope>,
+    }
+
+    impl Instance {
+        fn join(hub: &MemoryHub, instance_id: &str, priority: i32) -> Self {
+            let signer = Arc::new(Signer::new(instance_id.to_owned(), SECRET));
+            let (inbox_tx, inbox) = mpsc::unbounded_channel();
+            Instance {
+                state: ClusterState::new(instance_id.to_owned(), priority),
+                transport: hub.attach(signer.clone(), inbox_tx),
+                signer,
+                inbox,
+            }
+        }
+
+        /// What one round of `start_cluster_loop` does before sending its heartbeat.
+        fn heartbeat(&mut self) -> ClusterMessage {
+            let info = InstanceInfo {
+                instance_id: self.state.my_instance_id.clone(),
+                priority: self.state.my_priority,
+                last_seen: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+                is_leader: self.state.is_leader,
+                term: self.state.term,
+                started_at: self.state.started_at,
+                version: String::new(),
+                guild_count: 0,
+                yielded: self.state.yielded,
+            };
+            self.state.instances.insert(info.instance_id.clone(), info.clone());
+            self.state.check_leader();
+            ClusterMessage::Heartbeat(InstanceInfo {
+                is_leader: self.state.is_leader,
+                term: self.state.term,
+                ..info
+            })
+        }
+
+        async fn receive(&mut self) -> ClusterMessage {
+            let envelope = tokio::time::timeout(Duration::from_secs(1), self.inbox.recv()).await
+                .expect("no message arrived")
+                .expect("hub closed");
+            self.signer.open(envelope).unwrap()
+        }
+    }
+
+    async fn exchange_heartbeats(a: &mut Instance, b: &mut Instance) {
+        for instance in [&mut *a, &mut *b] {
+            let heartbeat = instance.heartbeat();
+            instance.transport.send(&heartbeat).await.unwrap();
+        }
+        for instance in [a, b] {
+            let ClusterMessage::Heartbeat(info) = instance.receive().await else {
+                panic!("expected a heartbeat");
+            };
+            instance.state.update_instance(info);
+        }
+    }
+
+    #[tokio::test]
+    async fn elects_one_leader() {
+        let hub = MemoryHub::new();
+        let mut a = Instance::join(&hub, "a", 1);
+        let mut b = Instance::join(&hub, "b", 2);
+        exchange_heartbeats(&mut a, &mut b).await;
+        assert!(a.state.is_leader);
+        assert!(!b.state.is_leader);
+        assert_eq!(a.state.current_leader.as_deref(), Some("a"));
+        assert_eq!(b.state.current_leader.as_deref(), Some("a"));
+        assert_eq!(a.state.term, b.state.term);
+        // The hub never hands an instance its own messages.
+        assert!(a.inbox.try_recv().is_err());
+        assert!(b.inbox.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_stale_fencing_tokens() {
+        let hub = MemoryHub::new();
+        let mut a = Instance::join(&hub, "a", 1);
+        let mut b = Instance::join(&hub, "b", 2);
+        exchange_heartbeats(&mut a, &mut b).await;
+        let stale = a.state.issue_token().unwrap();
+        let (successor, term) = a.state.step_down().unwrap();
+        a.transport.send(&ClusterMessage::StepDown { instance_id: "a".to_owned(), successor, term }).await.unwrap();
+        let ClusterMessage::StepDown { successor, term, .. } = b.receive().await else {
+            panic!("expected a step-down");
+        };
+        assert!(b.state.observe_leader(successor, term));
+        assert!(b.state.is_leader);
+
+        // An update the old leader stamped before stepping down arrives late.
+        let template = |token| ClusterMessage::TicketTemplateUpdate { guild_id: 1, content: String::new(), token };
+        a.transport.send(&template(stale)).await.unwrap();
+        let ClusterMessage::TicketTemplateUpdate { token, .. } = b.receive().await else {
+            panic!("expected a template update");
+        };
+        assert!(!b.state.accept_update("template:1".to_owned(), token));
+
+        // Within the new term, an older update can't overwrite a newer one.
+        let first = b.state.issue_token().unwrap();
+        let second = b.state.issue_token().unwrap();
+        b.transport.send(&template(second)).await.unwrap();
+        b.transport.send(&template(first)).await.unwrap();
+        for expected in [true, false] {
+            let ClusterMessage::TicketTemplateUpdate { token, .. } = a.receive().await else {
+                panic!("expected a template update");
+            };
+            assert_eq!(a.state.accept_update("template:1".to_owned(), token), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn replicates_config_changes() {
+        let hub = MemoryHub::new();
+        let mut a = Instance::join(&hub, "a", 1);
+        let mut b = Instance::join(&hub, "b", 2);
+        exchange_heartbeats(&mut a, &mut b).await;
+        let mut leader_config = GuildConfig::default();
+        let mut follower_config = GuildConfig::default();
+
+        let mut replicate = async |leader_config: &mut GuildConfig, max_open| {
+            let (_, change) = leader_config.update(1, &["tickets", "max_open"], |guild| {
+                guild.tickets.max_open = Some(max_open);
+            }).unwrap();
+            let token = a.state.issue_token().unwrap();
+            a.transport.send(&ClusterMessage::ConfigChange { change: change.unwrap(), token }).await.unwrap();
+            let ClusterMessage::ConfigChange { change, token } = b.receive().await else {
+                panic!("expected a config change");
+            };
+            assert!(b.state.accept_token(token));
+            change
+        };
+
+        let change = replicate(&mut leader_config, 3).await;
+        assert_eq!(follower_config.apply_change(&change).unwrap(), ChangeOutcome::Applied);
+        assert_eq!(follower_config, leader_config);
+        assert_eq!(follower_config.apply_change(&change).unwrap(), ChangeOutcome::Duplicate);
+
+        let (_, unchanged) = leader_config.update(1, &["tickets", "max_open"], |guild| {
+            guild.tickets.max_open = Some(3);
+        }).unwrap();
+        assert!(unchanged.is_none());
+
+        let _missed = replicate(&mut leader_config, 4).await;
+        let change = replicate(&mut leader_config, 5).await;
+        assert_eq!(follower_config.apply_change(&change).unwrap(), ChangeOutcome::Gap { expected: 2 });
+        assert_eq!(follower_config.tickets.max_open, Some(3));
+    }
+}
@@ -1,9 +1,6 @@
 use crate::{Context, Error};
 use poise::serenity_prelude::{self as serenity};
-use poise::serenity_prelude::ChannelId;
 use poise::serenity_prelude::parse_emoji;
-use toml::Value;
-use std::fs;
 use crate::cluster::ClusterMessage;
 use crate::utils::*;
 
@@ -27,6 +24,8 @@ use crate::utils::*;
         "cleanreactroles",
         "add_stat",
         "remove_stat",
+        "stat_rules",
+        "stat_milestone",
         "add_ending",
         "remove_ending",
         "list_endings",
@@ -35,11 +34,6 @@ use crate::utils::*;
     )
 )]
 pub async fn config(ctx: Context<'_>) -> Result<(), Error> {
-    let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
     poise::builtins::help(
         ctx,
         None,
@@ -66,6 +60,8 @@ pub enum LogChannelType {
     Mod,
     #[name = "message"]
     Message,
+    #[name = "milestone"]
+    Milestone,
     #[name = "default"]
     Default,
 }
@@ -80,29 +76,23 @@ pub async fn set_log_channel(
     channel: serenity::GuildChannel,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     let channel_key = match event_type {
-        LogChannelType::Boot => "boot_quit_channel",
-        LogChannelType::Member => "member_log_channel",
-        LogChannelType::Ticket => "ticket_log_channel",
-        LogChannelType::Announcement => "announcement_channel",
-        LogChannelType::Mod => "mod_log_channel",
-        LogChannelType::Message => "message_log_channel",
-        LogChannelType::Default => "logging_channel",
+        LogChannelType::Boot => "boot_quit",
+        LogChannelType::Member => "member",
+        LogChannelType::Ticket => "ticket",
+        LogChannelType::Announcement => "announcement",
+        LogChannelType::Mod => "moderation",
+        LogChannelType::Message => "message",
+        LogChannelType::Milestone => "milestone",
+        LogChannelType::Default => "default",
     };
-    let _ = set_specific_logging_channel(guild_id.into(), channel_key, channel.id.into()).await;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = set_specific_logging_channel(guild_id.into(), channel_key, channel.id.into()).await?;
     ctx.say(format!("Updated {} channel to {}", channel_key, channel.name)).await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -114,24 +104,17 @@ pub async fn ticket_category(
     channel: serenity::GuildChannel,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     if channel.kind != serenity::ChannelType::Category {
         ctx.say("Please select a category channel, not a regular channel").await?;
         return Ok(());
     }
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    let _ = set_ticket_category(guild_id.into(), channel.id.into()).await;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = set_ticket_category(guild_id.into(), channel.id.into()).await?;
     ctx.say(format!("Updated ticket category to {}", channel.name)).await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -141,20 +124,13 @@ pub async fn add_ticket_role(
     #[description = "Role to add to ticket access"] role: serenity::Role,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    let _ = add_ticrole(guild_id.into(), role.id.into()).await;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = add_ticrole(guild_id.into(), role.id.into()).await?;
     ctx.say(format!("Added {} to ticket access roles", role.name)).await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -164,20 +140,13 @@ pub async fn remove_ticket_role(
     #[description = "Role to remove from ticket access"] role: serenity::Role,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    let _ = remove_ticrole(guild_id.into(), role.id.into()).await;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = remove_ticrole(guild_id.into(), role.id.into()).await?;
     ctx.say(format!("Removed {} from ticket access roles", role.name)).await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -188,11 +157,6 @@ pub async fn ticket_message(
     file: serenity::Attachment,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     if !file.filename.ends_with(".txt") {
         ctx.say("Please upload a .txt file").await?;
@@ -200,19 +164,14 @@ pub async fn ticket_message(
     }
     let content = file.download().await?;
     let content = String::from_utf8(content)?;
-    std::fs::create_dir_all("./ticket_templates")?;
-    let path = get_ticket_template_path(guild_id.into());
-    std::fs::write(path, &content)?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    crate::storage::get().set_ticket_template(guild_id.into(), &content)?;
     let message = ClusterMessage::TicketTemplateUpdate {
         guild_id: guild_id.into(),
         content: content.clone(),
+        token,
     };
-    if let Err(e) = cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&message)?)
-    ).await {
+    if let Err(e) = data.cluster_transport.send(&message).await {
         println!("Failed to send template update: {e}");
     }
     ctx.say("Ticket message template updated and synced across instances!").await?;
@@ -226,20 +185,13 @@ pub async fn ticket_exempt_role(
     role: serenity::Role,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    let _ = set_ticket_exempt_role(guild_id.into(), role.id.into()).await;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = set_ticket_exempt_role(guild_id.into(), role.id.into()).await?;
     ctx.say(format!("Set {} as the ticket exempt role", role.name)).await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -248,31 +200,14 @@ pub async fn remove_ticket_exempt_role(
     ctx: Context<'_>,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    let toml_content = fs::read_to_string(CONFIG_PATH)?;
-    let mut value = toml_content.parse::<Value>().expect("Failed to parse TOML");
-    if let Some(guild_table) = value
-        .as_table_mut()
-        .expect("Root should be a table")
-        .get_mut(&guild_id.to_string())
-        .and_then(|v| v.as_table_mut())
-    {
-        guild_table.remove("ticket_exempt_role");
-    }
-    let new_toml = toml::to_string_pretty(&value)?;
-    fs::write(CONFIG_PATH, new_toml)?;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = crate::utils::remove_ticket_exempt_role(guild_id.into()).await?;
+    save_config_to_disk().await?;
     ctx.say("Removed ticket exempt role").await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -290,12 +225,6 @@ pub async fn reactrole(
 ) -> Result<(), Error> {
     ctx.defer().await?;
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        ctx.say("This command can only be run by the leader instance.").await?;
-        return Ok(());
-    }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     let message_id_u64 = match message_id.parse::<u64>() {
         Ok(id) => id,
@@ -320,7 +249,8 @@ pub async fn reactrole(
         ctx.say("❌ Failed to react to the message. Do I have 'Add Reactions' permissions in that channel? Also, ensure the emoji is correct and I have access to it if it's a custom emoji from another server.").await?;
         return Ok(());
     }
-    add_react_role(
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = add_react_role(
         guild_id.into(),
         channel.id.into(),
         message_id_u64,
@@ -332,12 +262,9 @@ pub async fn reactrole(
         emoji,
         role.name
     )).await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -358,12 +285,6 @@ pub async fn removereactrole(
 ) -> Result<(), Error> {
     ctx.defer().await?;
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        ctx.say("This command can only be run by the leader instance.").await?;
-        return Ok(());
-    }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     let message_id_u64 = match message_id.parse::<u64>() {
         Ok(id) => id,
@@ -372,8 +293,9 @@ pub async fn removereactrole(
             return Ok(());
         }
     };
+    let token = crate::cluster::confirm_leader(ctx).await?;
     match remove_react_role(guild_id.into(), message_id_u64, &emoji).await? {
-        Some(_removed_role_id) => {
+        Some((_removed_role_id, change)) => {
             let message = match channel.id.message(&ctx.http(), message_id_u64).await {
                 Ok(msg) => msg,
                 Err(_) => {
@@ -387,13 +309,8 @@ pub async fn removereactrole(
             };
             let bot_id = ctx.framework().bot_id;
             let _ = message.delete_reaction(&ctx.http(), Some(bot_id), reaction_emoji).await;
-            ctx.say(format!("✅ Successfully removed the react-role for {}.", &emoji)).await?;
-            let cluster_channel = ChannelId::new(coordination_channel_id);
-            cluster_channel.send_message(
-                &ctx.http(),
-                serenity::CreateMessage::new()
-                    .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-            ).await?;
+            ctx.say(format!("✅ Successfully removed the react-role for {}.", emoji)).await?;
+            data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
         }
         None => {
             ctx.say("❌ That emoji was not configured as a react-role on that message.").await?;
@@ -410,32 +327,24 @@ pub async fn removereactrole(
 )]
 pub async fn cleanreactroles(ctx: Context<'_>) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        ctx.say("This command can only be run by the leader instance.").await?;
-        return Ok(());
-    }
     ctx.defer_ephemeral().await?;
     let guild_id = ctx.guild_id().ok_or("Not in a guild")?;
+    let token = crate::cluster::confirm_leader(ctx).await?;
     match prune_dead_react_roles(ctx.http(), guild_id.into()).await {
-        Ok(pruned_ids) => {
+        Ok((pruned_ids, change)) => {
             if pruned_ids.is_empty() {
                 ctx.say("✅ No dead react-role configurations found.").await?;
             } else {
                 let ids_str = pruned_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ");
                 ctx.say(format!("🧹 Cleaned up {} dead react-role configuration(s) for message(s): {}", pruned_ids.len(), ids_str)).await?;
                 save_config_to_disk().await?;
-                let coordination_channel_id = cluster_state.coordination_channel_id;
-                let cluster_channel = ChannelId::new(coordination_channel_id);
-                cluster_channel.send_message(
-                    &ctx.http(),
-                    serenity::CreateMessage::new()
-                        .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-                ).await?;
+                if let Some(change) = change {
+                    data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+                }
             }
         },
         Err(e) => {
-            ctx.say(format!("❌ An error occurred while cleaning config: {}", &e)).await?;
+            ctx.say(format!("❌ An error occurred while cleaning config: {}", e)).await?;
         }
     }
     Ok(())
@@ -452,17 +361,19 @@ pub async fn add_stat(
     #[description = "Name of the stat to track (e.g., 'solves')"] stat_name: String,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader { return Ok(()); }
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     let clean_name: String = stat_name.chars().filter(|c| c.is_alphanumeric()).collect();
     if clean_name.is_empty() {
         ctx.say("Stat names must contain alphanumeric characters.").await?;
         return Ok(());
     }
-    crate::utils::add_custom_stat(guild_id.into(), &clean_name).await?;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = crate::utils::add_custom_stat(guild_id.into(), &clean_name).await?;
     crate::utils::save_config_to_disk().await?;
     ctx.say(format!("✅ Added custom stat tracker: `{}`", clean_name)).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -475,14 +386,155 @@ pub async fn add_stat(
 pub async fn remove_stat(
     ctx: Context<'_>,
     #[description = "Name of the stat to remove"] stat_name: String,
+    #[description = "Move everyone's values and history into this stat instead of leaving them behind"]
+    merge_into: Option<String>,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader { return Ok(()); }
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    crate::utils::remove_custom_stat(guild_id.into(), &stat_name).await?;
+    let stat_name = stat_name.to_lowercase();
+    let mut changes = Vec::new();
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let response = match merge_into {
+        Some(target) => {
+            let target: String = target.to_lowercase().chars().filter(|c| c.is_alphanumeric()).collect();
+            if target.is_empty() || target == stat_name {
+                ctx.say("Pick a different stat name with alphanumeric characters to merge into.").await?;
+                return Ok(());
+            }
+            let moved = match crate::storage::get().rename_stat(guild_id.into(), &stat_name, &target) {
+                Ok(moved) => moved,
+                Err(e) => {
+                    ctx.say(format!("❌ Couldn't merge `{}` into `{}`: {}", stat_name, target, e)).await?;
+                    return Ok(());
+                }
+            };
+            if !get_custom_stats(guild_id.into()).await.contains(&target) {
+                changes.push(crate::utils::add_custom_stat(guild_id.into(), &target).await?);
+            }
+            format!("🔀 Merged `{}` into `{}` for {} members and stopped tracking `{}`", stat_name, target, moved, stat_name)
+        }
+        None => format!("🗑️ Removed custom stat tracker: `{}`", stat_name),
+    };
+    changes.push(crate::utils::remove_custom_stat(guild_id.into(), &stat_name).await?);
     crate::utils::save_config_to_disk().await?;
-    ctx.say(format!("🗑️ Removed custom stat tracker: `{}`", stat_name)).await?;
+    ctx.say(response).await?;
+    for change in changes.into_iter().flatten() {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
+    Ok(())
+}
+
+#[poise::command(
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    guild_only
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn stat_rules(
+    ctx: Context<'_>,
+    #[description = "Stat to configure"] stat_name: String,
+    #[description = "Name shown to members instead of the stat's ID"] display_name: Option<String>,
+    #[description = "Emoji shown next to the stat"] emoji: Option<String>,
+    #[description = "Smallest amount a single change may add (can be negative)"] min_delta: Option<i64>,
+    #[description = "Largest amount a single change may add"] max_delta: Option<i64>,
+    #[description = "Seconds a member must wait between changes (0 to remove)"] cooldown: Option<u64>,
+    #[description = "Who may add to the stat"] awarded_by: Option<StatAwarders>,
+    #[description = "Allow this role to change the stat (restricts it to listed roles)"] allow_role: Option<serenity::Role>,
+    #[description = "Remove a role from the allowed roles"] disallow_role: Option<serenity::Role>,
+    #[description = "Clear every rule before applying the options above (milestones are kept)"] reset: Option<bool>,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let stat_name = stat_name.to_lowercase();
+    let Some(mut definition) = get_stat_definition(guild_id.into(), &stat_name).await else {
+        ctx.say(format!("❌ `{}` is not a tracked stat. Add it with `config add_stat` first.", stat_name)).await?;
+        return Ok(());
+    };
+    if reset.unwrap_or(false) {
+        // Milestones have their own commands, so a rules reset leaves them alone.
+        definition = StatDefinition { milestones: definition.milestones, ..StatDefinition::default() };
+    }
+    definition.display_name = display_name.or(definition.display_name);
+    definition.emoji = emoji.or(definition.emoji);
+    definition.min_delta = min_delta.or(definition.min_delta);
+    definition.max_delta = max_delta.or(definition.max_delta);
+    if let Some(seconds) = cooldown {
+        definition.cooldown = (seconds > 0).then_some(seconds);
+    }
+    definition.awarded_by = awarded_by.unwrap_or(definition.awarded_by);
+    if let Some(role) = allow_role {
+        if !definition.allowed_roles.contains(&role.id.get()) {
+            definition.allowed_roles.push(role.id.get());
+        }
+    }
+    if let Some(role) = disallow_role {
+        definition.allowed_roles.retain(|id| *id != role.id.get());
+    }
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = match set_stat_definition(guild_id.into(), &stat_name, definition.clone()).await {
+        Ok(change) => change,
+        Err(e) => {
+            ctx.say(format!("❌ {e}")).await?;
+            return Ok(());
+        }
+    };
+    crate::utils::save_config_to_disk().await?;
+    let format_limit = |limit: Option<i64>| limit.map(|v| v.to_string()).unwrap_or_else(|| "none".to_owned());
+    let allowed_roles = match definition.allowed_roles.len() {
+        0 => "everyone".to_owned(),
+        _ => definition.allowed_roles.iter().map(|id| format!("<@&{id}>")).collect::<Vec<_>>().join(", "),
+    };
+    ctx.say(format!(
+        "✅ Rules for {}:\n**Awarded by:** {}\n**Per change:** min {}, max {}\n**Cooldown:** {}\n**Allowed roles:** {}",
+        definition.label(&stat_name),
+        poise::ChoiceParameter::name(&definition.awarded_by),
+        format_limit(definition.min_delta),
+        format_limit(definition.max_delta),
+        definition.cooldown.map(|s| format!("{s} seconds")).unwrap_or_else(|| "none".to_owned()),
+        allowed_roles,
+    )).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
+    Ok(())
+}
+
+#[poise::command(
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    guild_only
+)]
+pub async fn stat_milestone(
+    ctx: Context<'_>,
+    #[description = "Stat the milestone belongs to"] stat_name: String,
+    #[description = "Total a member needs to reach"] threshold: i64,
+    #[description = "Role to grant at the threshold (leave out to remove the milestone)"] role: Option<serenity::Role>,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let stat_name = stat_name.to_lowercase();
+    let Some(mut definition) = get_stat_definition(guild_id.into(), &stat_name).await else {
+        ctx.say(format!("❌ `{}` is not a tracked stat. Add it with `config add_stat` first.", stat_name)).await?;
+        return Ok(());
+    };
+    definition.milestones.retain(|milestone| milestone.threshold != threshold);
+    let response = match &role {
+        Some(role) => {
+            definition.milestones.push(StatMilestone { threshold, role: role.id.get() });
+            definition.milestones.sort_by_key(|milestone| milestone.threshold);
+            format!("🏅 Members reaching {} {} will now get the **{}** role", threshold, definition.label(&stat_name), role.name)
+        }
+        None => format!("🗑️ Removed the {} milestone at {}", definition.label(&stat_name), threshold),
+    };
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = set_stat_definition(guild_id.into(), &stat_name, definition).await?;
+    crate::utils::save_config_to_disk().await?;
+    ctx.say(response).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -493,19 +545,14 @@ pub async fn add_ending(
     #[description = "Message for the ending"] message: String,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader { return Ok(()); }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    crate::utils::add_countdown_ending(guild_id.into(), &name, &message).await?;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = crate::utils::add_countdown_ending(guild_id.into(), &name, &message).await?;
     crate::utils::save_config_to_disk().await?;
     ctx.say(format!("✅ Added countdown ending `{}`", name)).await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -515,23 +562,18 @@ pub async fn remove_ending(
     #[description = "Name of the ending to remove"] name: String,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader { return Ok(()); }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    crate::utils::remove_countdown_ending(guild_id.into(), &name).await?;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = crate::utils::remove_countdown_ending(guild_id.into(), &name).await?;
     crate::utils::save_config_to_disk().await?;
     ctx.say(format!("🗑️ Removed countdown ending `{}`", name)).await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
-#[poise::command(prefix_command, slash_command)]
+#[poise::command(prefix_command, slash_command, custom_data = "crate::cluster::InstancePolicy::AnyInstance")]
 pub async fn list_endings(ctx: Context<'_>) -> Result<(), Error> {
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     let endings = crate::utils::get_countdown_endings(guild_id.into()).await;
@@ -550,22 +592,19 @@ pub async fn list_endings(ctx: Context<'_>) -> Result<(), Error> {
 #[poise::command(prefix_command, slash_command)]
 pub async fn set_max_tickets(
     ctx: Context<'_>,
-    #[description = "Maximum number of active tickets a user can have"] limit: u64,
+    #[description = "Maximum number of active tickets a user can have"]
+    #[min = 1]
+    limit: u64,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader { return Ok(()); }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    crate::utils::set_max_open_tickets(guild_id.into(), limit).await?;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = crate::utils::set_max_open_tickets(guild_id.into(), limit).await?;
     crate::utils::save_config_to_disk().await?;
     ctx.say(format!("✅ Max open tickets per user set to {}", limit)).await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
@@ -575,105 +614,85 @@ pub async fn set_ticket_cooldown(
     #[description = "Cooldown time in seconds between opening tickets"] seconds: u64,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader { return Ok(()); }
-    let coordination_channel_id = cluster_state.coordination_channel_id;
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    crate::utils::set_ticket_cooldown(guild_id.into(), seconds).await?;
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let change = crate::utils::set_ticket_cooldown(guild_id.into(), seconds).await?;
     crate::utils::save_config_to_disk().await?;
     ctx.say(format!("✅ Ticket creation cooldown set to {} seconds", seconds)).await?;
-    let cluster_channel = ChannelId::new(coordination_channel_id);
-    cluster_channel.send_message(
-        &ctx.http(),
-        serenity::CreateMessage::new()
-            .content(serde_json::to_string(&ClusterMessage::ConfigUpdate)?)
-    ).await?;
+    if let Some(change) = change {
+        data.cluster_transport.send(&ClusterMessage::ConfigChange { change, token }).await?;
+    }
     Ok(())
 }
 
 #[poise::command(prefix_command, slash_command)]
 pub async fn view(ctx: Context<'_>) -> Result<(), Error> {
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
-    // Read the raw TOML directly so we can inspect exactly what is explicitly set
-    let toml_content = fs::read_to_string(CONFIG_PATH).unwrap_or_default();
-    let value = toml_content.parse::<Value>().unwrap_or(Value::Table(toml::value::Table::new()));
-    let empty_table = toml::value::Table::new();
-    let guild_table = value
-        .get(&guild_id.to_string())
-        .and_then(|v| v.as_table())
-        .unwrap_or(&empty_table);
+    let guild_config = guild_config(guild_id.into()).await;
     let mut embed = serenity::CreateEmbed::new()
         .title("⚙️ Server Configuration")
         .color(serenity::Colour::BLURPLE);
 
     // Helpers to easily format values or return the fallback string
-    let format_channel = |key: &str| -> String {
-        guild_table.get(key)
-            .and_then(|v| v.as_integer())
+    let format_channel = |channel: Option<u64>| -> String {
+        channel
             .map(|id| format!("<#{}>", id))
             .unwrap_or_else(|| "❌ Not set".to_string())
     };
-    let format_role = |key: &str| -> String {
-        guild_table.get(key)
-            .and_then(|v| v.as_integer())
+    let format_role = |role: Option<u64>| -> String {
+        role
             .map(|id| format!("<@&{}>", id))
             .unwrap_or_else(|| "❌ Not set".to_string())
     };
-    let format_int = |key: &str, suffix: &str| -> String {
-        guild_table.get(key)
-            .and_then(|v| v.as_integer())
+    let format_int = |value: Option<u64>, suffix: &str| -> String {
+        value
             .map(|val| format!("{}{}", val, suffix))
             .unwrap_or_else(|| "❌ Not set".to_string())
     };
     // --- CHANNELS ---
     let channels_desc = format!(
-        "**Default Logging:** {}\n**Boot/Quit Logging:** {}\n**Member Logging:** {}\n**Ticket Logging:** {}\n**Mod Logging:** {}\n**Message Logging:** {}\n**Announcements:** {}",
-        format_channel("logging_channel"),
-        format_channel("boot_quit_channel"),
-        format_channel("member_log_channel"),
-        format_channel("ticket_log_channel"),
-        format_channel("mod_log_channel"),
-        format_channel("message_log_channel"),
-        format_channel("announcement_channel")
+        "**Default Logging:** {}\n**Boot/Quit Logging:** {}\n**Member Logging:** {}\n**Ticket Logging:** {}\n**Mod Logging:** {}\n**Message Logging:** {}\n**Announcements:** {}\n**Stat Milestones:** {}",
+        format_channel(guild_config.channels.default),
+        format_channel(guild_config.channels.boot_quit),
+        format_channel(guild_config.channels.member),
+        format_channel(guild_config.channels.ticket),
+        format_channel(guild_config.channels.moderation),
+        format_channel(guild_config.channels.message),
+        format_channel(guild_config.channels.announcement),
+        format_channel(guild_config.channels.milestone)
     );
     embed = embed.field("📁 Channels", channels_desc, false);
     // --- TICKET SYSTEM & ROLES ---
-    let ticket_roles = guild_table.get("ticket_roles")
-        .and_then(|v| v.as_array())
-        .map(|arr| {
-            if arr.is_empty() {
-                "❌ Not set".to_string()
-            } else {
-                arr.iter()
-                   .filter_map(|v| v.as_integer())
-                   .map(|id| format!("<@&{}>", id))
-                   .collect::<Vec<_>>()
-                   .join(", ")
-            }
-        })
-        .unwrap_or_else(|| "❌ Not set".to_string());
+    let ticket_roles = if guild_config.tickets.roles.is_empty() {
+        "❌ Not set".to_string()
+    } else {
+        guild_config.tickets.roles.iter()
+            .map(|id| format!("<@&{}>", id))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
     let tickets_desc = format!(
         "**Ticket Category:** {}\n**Ticket Roles:** {}\n**Exempt Role:** {}\n**Max Open Tickets:** {}\n**Ticket Cooldown:** {}",
-        format_channel("ticket_category"),
+        format_channel(guild_config.tickets.category),
         ticket_roles,
-        format_role("ticket_exempt_role"),
-        format_int("max_open_tickets", " tickets"),
-        format_int("ticket_cooldown", " seconds")
+        format_role(guild_config.tickets.exempt_role),
+        format_int(guild_config.tickets.max_open, " tickets"),
+        format_int(guild_config.tickets.cooldown, " seconds")
     );
     embed = embed.field("🎫 Ticket System", tickets_desc, false);
     // --- MISC / MODULES ---
-    let custom_stats = guild_table.get("custom_stats")
-        .and_then(|v| v.as_array())
-        .map(|arr| format!("✅ {} tracked", arr.len()))
-        .unwrap_or_else(|| "❌ Not set".to_string());
-    let countdown_endings = guild_table.get("countdown_endings")
-        .and_then(|v| v.as_table())
-        .map(|table| format!("✅ {} configured", table.len()))
-        .unwrap_or_else(|| "❌ Not set".to_string());
-    let react_roles = guild_table.get("react_roles")
-        .and_then(|v| v.as_table())
-        .map(|table| format!("✅ Configured on {} message(s)", table.len()))
-        .unwrap_or_else(|| "❌ Not set".to_string());
+    let custom_stats = match guild_config.custom_stats.len() {
+        0 => "❌ Not set".to_string(),
+        count => format!("✅ {} tracked", count),
+    };
+    let countdown_endings = match guild_config.countdown_endings.len() {
+        0 => "❌ Not set".to_string(),
+        count => format!("✅ {} configured", count),
+    };
+    let react_roles = match guild_config.react_roles.len() {
+        0 => "❌ Not set".to_string(),
+        count => format!("✅ Configured on {} message(s)", count),
+    };
     let misc_desc = format!(
         "**React Roles:** {}\n**Custom Stats:** {}\n**Countdown Endings:** {}",
         react_roles,
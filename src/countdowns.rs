@@ -0,0 +1,344 @@
+use crate::{Context, Error};
+use crate::cluster::{ClusterMessage, ClusterState, FencingToken};
+use crate::storage::Countdown;
+use crate::transport::ClusterTransport;
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use rand::Rng;
+use rand::seq::IndexedRandom;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const MAX_COUNTDOWN_START: u32 = 100;
+const MAX_COUNTDOWNS_PER_CHANNEL: usize = 2;
+/// How often the leader looks for countdowns nobody is running, e.g. after a failover.
+const COUNTDOWN_RESUME_INTERVAL: u64 = 10;
+
+#[derive(poise::ChoiceParameter)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+/// Runs countdowns on the leader. Each countdown ticks in its own task, keyed by
+/// guild and countdown number so it can be cancelled.
+#[derive(Clone)]
+pub struct CountdownRunner {
+    http: Arc<serenity::Http>,
+    transport: Arc<dyn ClusterTransport>,
+    cluster_state: Arc<Mutex<ClusterState>>,
+    tasks: Arc<Mutex<HashMap<(u64, u64), tokio::task::AbortHandle>>>,
+}
+
+impl CountdownRunner {
+    pub fn new(
+        http: Arc<serenity::Http>,
+        transport: Arc<dyn ClusterTransport>,
+        cluster_state: Arc<Mutex<ClusterState>>,
+    ) -> Self {
+        Self { http, transport, cluster_state, tasks: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Starts ticking a countdown unless this instance is already running it.
+    async fn spawn(&self, countdown: Countdown) {
+        let key = (countdown.guild_id, countdown.countdown_id);
+        let mut tasks = self.tasks.lock().await;
+        if tasks.contains_key(&key) {
+            return;
+        }
+        let runner = self.clone();
+        let handle = tokio::spawn(async move {
+            if let Err(e) = runner.run(countdown).await {
+                println!("Countdown #{} in guild {} failed: {e}", key.1, key.0);
+            }
+            runner.tasks.lock().await.remove(&key);
+        });
+        tasks.insert(key, handle.abort_handle());
+    }
+
+    /// Stops the task for a countdown, if this instance is running it.
+    async fn abort(&self, guild_id: u64, countdown_id: u64) {
+        if let Some(handle) = self.tasks.lock().await.remove(&(guild_id, countdown_id)) {
+            handle.abort();
+        }
+    }
+
+    async fn run(&self, mut countdown: Countdown) -> Result<(), Error> {
+        let channel = serenity::ChannelId::new(countdown.channel_id);
+        while countdown.current > 0 {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            // The countdown stays active, so whichever instance leads next resumes it.
+            if !self.cluster_state.lock().await.is_leader {
+                return Ok(());
+            }
+            countdown.current = next_value(&countdown);
+            let edit = serenity::EditMessage::new().content(format!("⏱️ {}", countdown.current));
+            if let Err(e) = channel.edit_message(&self.http, countdown.message_id, edit).await {
+                // The message is gone or unreachable, so end the countdown without announcing it.
+                println!("Failed to edit countdown message, stopping countdown #{}: {}", countdown.countdown_id, e);
+                self.finish(&mut countdown).await?;
+                return Ok(());
+            }
+        }
+        if !self.finish(&mut countdown).await? {
+            return Ok(());
+        }
+        channel.say(&self.http, &countdown.ending).await?;
+        Ok(())
+    }
+
+    /// Marks a countdown finished across the cluster. Returns false if this instance
+    /// is no longer the leader, in which case the countdown is left for the next one.
+    async fn finish(&self, countdown: &mut Countdown) -> Result<bool, Error> {
+        let Some(token) = self.cluster_state.lock().await.issue_token() else {
+            return Ok(false);
+        };
+        countdown.finished = true;
+        save_and_replicate(self.transport.as_ref(), countdown, token).await?;
+        Ok(true)
+    }
+}
+
+/// Counts down, but the closer the countdown gets to 0 the likelier it is to count back up.
+fn next_value(countdown: &Countdown) -> i32 {
+    let start = countdown.start as f32;
+    let progress = if countdown.current <= countdown.start as i32 {
+        (start - countdown.current as f32) / start
+    } else {
+        0.0
+    };
+    let roll: f32 = rand::rng().random();
+    if roll < countdown.max_chance * progress {
+        countdown.current + 1
+    } else {
+        countdown.current - 1
+    }
+}
+
+async fn save_and_replicate(transport: &dyn ClusterTransport, countdown: &Countdown, token: FencingToken) -> Result<(), Error> {
+    crate::storage::get().save_countdown(countdown)?;
+    transport.send(&ClusterMessage::CountdownUpdate { countdown: countdown.clone(), token }).await
+}
+
+/// Reads the number a countdown's message last showed, since only starts, cancels and
+/// finishes are replicated.
+async fn shown_value(http: &serenity::Http, countdown: &Countdown) -> Option<i32> {
+    let message = serenity::ChannelId::new(countdown.channel_id)
+        .message(http, countdown.message_id)
+        .await
+        .ok()?;
+    message.content.trim_start_matches("⏱️").trim().parse().ok()
+}
+
+/// Resumes active countdowns that no task is running. Every instance runs this, but
+/// only the leader acts, so a new leader picks up countdowns its predecessor left.
+pub async fn start_countdown_resumer(runner: CountdownRunner) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(COUNTDOWN_RESUME_INTERVAL));
+    loop {
+        interval.tick().await;
+        if !runner.cluster_state.lock().await.is_leader {
+            continue;
+        }
+        let active = match crate::storage::get().active_countdowns(None) {
+            Ok(active) => active,
+            Err(e) => {
+                println!("Failed to read active countdowns: {e}");
+                continue;
+            }
+        };
+        for mut countdown in active {
+            if runner.tasks.lock().await.contains_key(&(countdown.guild_id, countdown.countdown_id)) {
+                continue;
+            }
+            if let Some(current) = shown_value(&runner.http, &countdown).await {
+                countdown.current = current;
+            }
+            println!("Resuming countdown #{} in guild {} at {}", countdown.countdown_id, countdown.guild_id, countdown.current);
+            runner.spawn(countdown).await;
+        }
+    }
+}
+
+async fn autocomplete_active_countdowns<'a>(
+    ctx: Context<'a>,
+    partial: &'a str,
+) -> impl Iterator<Item = serenity::AutocompleteChoice> + 'a {
+    let countdowns = match ctx.guild_id() {
+        Some(guild_id) => crate::storage::get().active_countdowns(Some(guild_id.into())).unwrap_or_default(),
+        None => Vec::new(),
+    };
+    countdowns.into_iter()
+        .filter(move |countdown| countdown.countdown_id.to_string().starts_with(partial))
+        .map(|countdown| serenity::AutocompleteChoice::new(
+            format!("#{} (from {})", countdown.countdown_id, countdown.start),
+            countdown.countdown_id,
+        ))
+}
+
+/// Start a probabilistic countdown; `~countdown [start] [difficulty] [ending]` also works
+#[poise::command(slash_command, prefix_command, guild_only, category = "Fun", subcommands("start", "cancel", "list"))]
+pub async fn countdown(
+    ctx: Context<'_>,
+    #[description = "Starting number (default 10)"] start: Option<u32>,
+    #[description = "Difficulty (easy, medium, hard)"] difficulty: Option<Difficulty>,
+    #[description = "Ending name (or 'random')"] ending: Option<String>,
+    #[description = "User to mention in the ending (defaults to you)"] target: Option<serenity::User>,
+) -> Result<(), Error> {
+    start_countdown(ctx, start, difficulty, ending, target).await
+}
+
+/// Start a probabilistic countdown in this channel
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn start(
+    ctx: Context<'_>,
+    #[description = "Starting number (default 10)"] start: Option<u32>,
+    #[description = "Difficulty (easy, medium, hard)"] difficulty: Option<Difficulty>,
+    #[description = "Ending name (or 'random')"] ending: Option<String>,
+    #[description = "User to mention in the ending (defaults to you)"] target: Option<serenity::User>,
+) -> Result<(), Error> {
+    start_countdown(ctx, start, difficulty, ending, target).await
+}
+
+async fn start_countdown(
+    ctx: Context<'_>,
+    start: Option<u32>,
+    difficulty: Option<Difficulty>,
+    ending: Option<String>,
+    target: Option<serenity::User>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let start_val = start.unwrap_or(10);
+    if start_val == 0 || start_val > MAX_COUNTDOWN_START {
+        ctx.say(format!("❌ Starting number must be between 1 and {}.", MAX_COUNTDOWN_START)).await?;
+        return Ok(());
+    }
+    let storage = crate::storage::get();
+    let in_channel = storage.active_countdowns(Some(guild_id.into()))?
+        .into_iter()
+        .filter(|countdown| countdown.channel_id == u64::from(ctx.channel_id()))
+        .count();
+    if in_channel >= MAX_COUNTDOWNS_PER_CHANNEL {
+        ctx.say(format!(
+            "❌ This channel already has {} countdowns running. Wait for one to finish or stop it with `/countdown cancel`.",
+            MAX_COUNTDOWNS_PER_CHANNEL
+        )).await?;
+        return Ok(());
+    }
+    let max_chance = match difficulty.unwrap_or(Difficulty::Easy) {
+        Difficulty::Easy => 0.0,
+        Difficulty::Medium => 0.25,
+        Difficulty::Hard => 0.50,
+    };
+    let endings = crate::utils::get_countdown_endings(guild_id.into()).await;
+    let final_message = if let Some(ref end_name) = ending {
+        if end_name.to_lowercase() == "random" && !endings.is_empty() {
+            let mut rng = rand::rng();
+            let values: Vec<&String> = endings.values().collect();
+            values.choose(&mut rng).map(|s| s.to_string()).unwrap_or_else(|| "0".to_owned())
+        } else if let Some(msg) = endings.get(&end_name.to_lowercase()) {
+            msg.clone()
+        } else {
+            "0".to_owned()
+        }
+    } else {
+        "0".to_owned()
+    };
+    let mention_str = target.as_ref().unwrap_or(ctx.author()).mention().to_string();
+    let formatted_ending = if final_message == "0" {
+        format!("0 {}", mention_str)
+    } else if final_message.contains("{user}") {
+        final_message.replace("{user}", &mention_str)
+    } else {
+        format!("{} {}", final_message, mention_str)
+    };
+
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    let reply = ctx.say(format!("⏱️ {}", start_val)).await?;
+    let message = reply.message().await?;
+    let mut countdown = Countdown {
+        guild_id: guild_id.into(),
+        countdown_id: 0,
+        channel_id: ctx.channel_id().into(),
+        message_id: message.id.into(),
+        started_by: ctx.author().id.into(),
+        start: start_val,
+        current: start_val as i32,
+        max_chance,
+        ending: formatted_ending,
+        started_at: chrono::Utc::now().timestamp(),
+        finished: false,
+    };
+    countdown.countdown_id = storage.add_countdown(&countdown)?;
+    ctx.data().cluster_transport.send(&ClusterMessage::CountdownUpdate { countdown: countdown.clone(), token }).await?;
+    ctx.data().countdowns.spawn(countdown).await;
+    Ok(())
+}
+
+/// Stop a running countdown
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn cancel(
+    ctx: Context<'_>,
+    #[description = "Countdown number (defaults to the one running in this channel)"]
+    #[autocomplete = "autocomplete_active_countdowns"]
+    countdown_id: Option<u64>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let active = crate::storage::get().active_countdowns(Some(guild_id.into()))?;
+    let countdown = match countdown_id {
+        Some(countdown_id) => active.into_iter().find(|countdown| countdown.countdown_id == countdown_id),
+        None => {
+            let mut in_channel: Vec<Countdown> = active.into_iter()
+                .filter(|countdown| countdown.channel_id == u64::from(ctx.channel_id()))
+                .collect();
+            if in_channel.len() > 1 {
+                ctx.say("❌ More than one countdown is running here. Pick one by number; see `/countdown list`.").await?;
+                return Ok(());
+            }
+            in_channel.pop()
+        }
+    };
+    let Some(mut countdown) = countdown else {
+        ctx.say("❌ No such countdown is running. See `/countdown list`.").await?;
+        return Ok(());
+    };
+    let (_, is_staff) = crate::stats::member_standing(ctx.serenity_context(), guild_id, ctx.author().id).await?;
+    if countdown.started_by != u64::from(ctx.author().id) && !is_staff {
+        ctx.say("❌ Only whoever started the countdown or an admin can cancel it.").await?;
+        return Ok(());
+    }
+    let token = crate::cluster::confirm_leader(ctx).await?;
+    ctx.data().countdowns.abort(countdown.guild_id, countdown.countdown_id).await;
+    countdown.finished = true;
+    save_and_replicate(ctx.data().cluster_transport.as_ref(), &countdown, token).await?;
+    let edit = serenity::EditMessage::new().content("⏹️ Countdown cancelled");
+    if let Err(e) = serenity::ChannelId::new(countdown.channel_id).edit_message(ctx.http(), countdown.message_id, edit).await {
+        println!("Failed to edit countdown message: {}", e);
+    }
+    ctx.say(format!("✅ Cancelled countdown #{}.", countdown.countdown_id)).await?;
+    Ok(())
+}
+
+/// List the countdowns running on this server
+#[poise::command(slash_command, prefix_command, guild_only, custom_data = "crate::cluster::InstancePolicy::AnyInstance")]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let countdowns = crate::storage::get().active_countdowns(Some(guild_id.into()))?;
+    if countdowns.is_empty() {
+        ctx.say("There are no countdowns running. Start one with `/countdown start`.").await?;
+        return Ok(());
+    }
+    let mut response = String::new();
+    for countdown in countdowns {
+        response.push_str(&format!(
+            "**#{}** from {} in <#{}>, started by <@{}> <t:{}:R>\n",
+            countdown.countdown_id, countdown.start, countdown.channel_id, countdown.started_by, countdown.started_at
+        ));
+    }
+    let embed = serenity::CreateEmbed::new()
+        .title("⏱️ Running countdowns")
+        .description(response)
+        .color(serenity::Colour::BLUE);
+    ctx.send(poise::CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
@@ -0,0 +1,680 @@
+use rand::Rng;
+use std::fmt;
+
+const MAX_INPUT_LENGTH: usize = 200;
+const MAX_DICE_PER_TERM: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+/// Covers every die rolled by one expression, including rerolls and explosions.
+const MAX_TOTAL_DICE: usize = 1000;
+const MAX_CHAIN: usize = 100;
+/// Every word the grammar knows, longest first so `4dfkh2` splits into `df` and `kh`.
+const WORDS: [&str; 11] = ["adv", "dis", "df", "dh", "dl", "kh", "kl", "ro", "d", "k", "r"];
+
+/// A malformed or unrollable expression. `position` is the character index the
+/// problem starts at, so the reply can point at it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl DiceError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        DiceError { position, message: message.into() }
+    }
+
+    /// The input with a caret under the offending character.
+    pub fn pointer(&self, input: &str) -> String {
+        format!("{}\n{}^", input, " ".repeat(self.position))
+    }
+}
+
+impl fmt::Display for DiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at character {})", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for DiceError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(u64),
+    Word(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Bang,
+    Less,
+    Greater,
+    Equals,
+    OpenParen,
+    CloseParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Word(word) => write!(f, "{word}"),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
+            Token::Bang => write!(f, "!"),
+            Token::Less => write!(f, "<"),
+            Token::Greater => write!(f, ">"),
+            Token::Equals => write!(f, "="),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+        }
+    }
+}
+
+/// Splits the input into tokens with their starting character index. Runs of letters
+/// are split into known words (`kh`, `ro`, `adv`), so `4d6kh3` is `4`, `d`, `6`, `kh`, `3`
+/// and `4dfkh2` is `4`, `df`, `kh`, `2`.
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, DiceError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_digit() {
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let digits: String = chars[start..i].iter().collect();
+            let number = digits.parse().map_err(|_| DiceError::new(start, "Number is too large"))?;
+            tokens.push((start, Token::Number(number)));
+            continue;
+        }
+        if c.is_ascii_alphabetic() {
+            let rest: String = chars[i..].iter().take_while(|c| c.is_ascii_alphabetic()).collect::<String>().to_lowercase();
+            let word = WORDS.iter()
+                .find(|word| rest.starts_with(*word))
+                .ok_or_else(|| DiceError::new(start, format!("Unexpected `{rest}`")))?;
+            tokens.push((start, Token::Word((*word).to_owned())));
+            i += word.len();
+            continue;
+        }
+        let token = match c {
+            '+' => Token::Plus,
+            '-' | '−' => Token::Minus,
+            '*' | '×' => Token::Star,
+            '/' | '÷' => Token::Slash,
+            '%' => Token::Percent,
+            '!' => Token::Bang,
+            '<' => Token::Less,
+            '>' => Token::Greater,
+            '=' => Token::Equals,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            _ => return Err(DiceError::new(start, format!("Unexpected character `{c}`"))),
+        };
+        tokens.push((start, token));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "×",
+            BinaryOp::Divide => "÷",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sides {
+    Number(u32),
+    /// Fate/Fudge dice: -1, 0 or +1.
+    Fate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Equal(i64),
+    Below(i64),
+    Above(i64),
+}
+
+impl Compare {
+    fn matches(self, value: i64) -> bool {
+        match self {
+            Compare::Equal(target) => value == target,
+            Compare::Below(target) => value < target,
+            Compare::Above(target) => value > target,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    KeepHighest(u32),
+    KeepLowest(u32),
+    DropHighest(u32),
+    DropLowest(u32),
+    /// Roll another die whenever one shows its highest face.
+    Explode,
+    /// Reroll dice matching the comparison, until they don't or just once.
+    Reroll { compare: Compare, once: bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiceTerm {
+    pub count: u32,
+    pub sides: Sides,
+    pub modifiers: Vec<Modifier>,
+    /// The term as written, for the breakdown.
+    pub source: String,
+    pub position: usize,
+}
+
+impl DiceTerm {
+    fn faces(&self) -> (i64, i64) {
+        match self.sides {
+            Sides::Number(sides) => (1, sides as i64),
+            Sides::Fate => (-1, 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Dice(DiceTerm),
+    Negate {
+        inner: Box<Expr>,
+        position: usize,
+    },
+    Group(Box<Expr>),
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+        position: usize,
+    },
+}
+
+/// Recursive descent over the token list:
+///
+/// ```text
+/// expr   = term (("+" | "-") term)*
+/// term   = unary (("*" | "/") unary)*
+/// unary  = "-" unary | atom
+/// atom   = number | dice | "adv" | "dis" | "(" expr ")"
+/// dice   = [number] "d" (number | "%" | "f") modifier*
+/// modifier = ("kh" | "k" | "kl" | "dh" | "dl" | "d") number | "!" | ("r" | "ro") [("<" | ">" | "=")] number
+/// ```
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, token)| token)
+    }
+
+    /// Where the next token starts, or the end of the input.
+    fn position(&self) -> usize {
+        self.tokens.get(self.index).map(|(position, _)| *position).unwrap_or(self.input.chars().count())
+    }
+
+    fn next(&mut self) -> Option<(usize, Token)> {
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+        token
+    }
+
+    fn expect_number(&mut self, after: &str) -> Result<u64, DiceError> {
+        match self.peek() {
+            Some(Token::Number(n)) => {
+                let n = *n;
+                self.index += 1;
+                Ok(n)
+            }
+            _ => Err(DiceError::new(self.position(), format!("Expected a number after `{after}`"))),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, DiceError> {
+        let mut left = self.term()?;
+        while let Some(op) = match self.peek() {
+            Some(Token::Plus) => Some(BinaryOp::Add),
+            Some(Token::Minus) => Some(BinaryOp::Subtract),
+            _ => None,
+        } {
+            let position = self.position();
+            self.index += 1;
+            let right = self.term()?;
+            left = Expr::Binary { op, left: Box::new(left), right: Box::new(right), position };
+        }
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<Expr, DiceError> {
+        let mut left = self.unary()?;
+        while let Some(op) = match self.peek() {
+            Some(Token::Star) => Some(BinaryOp::Multiply),
+            Some(Token::Slash) => Some(BinaryOp::Divide),
+            _ => None,
+        } {
+            let position = self.position();
+            self.index += 1;
+            let right = self.unary()?;
+            left = Expr::Binary { op, left: Box::new(left), right: Box::new(right), position };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, DiceError> {
+        if self.peek() == Some(&Token::Minus) {
+            let position = self.position();
+            self.index += 1;
+            return Ok(Expr::Negate { inner: Box::new(self.unary()?), position });
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr, DiceError> {
+        let position = self.position();
+        match self.next() {
+            Some((_, Token::Number(count))) => {
+                if matches!(self.peek(), Some(Token::Word(word)) if word == "d" || word == "df") {
+                    self.dice(Some(count), position)
+                } else {
+                    i64::try_from(count)
+                        .map(Expr::Number)
+                        .map_err(|_| DiceError::new(position, "Number is too large"))
+                }
+            }
+            Some((_, Token::Word(word))) if word == "d" || word == "df" => {
+                self.index -= 1;
+                self.dice(None, position)
+            }
+            Some((_, Token::Word(word))) if word == "adv" || word == "dis" => {
+                let modifier = if word == "adv" { Modifier::KeepHighest(1) } else { Modifier::KeepLowest(1) };
+                Ok(Expr::Dice(DiceTerm {
+                    count: 2,
+                    sides: Sides::Number(20),
+                    modifiers: vec![modifier],
+                    source: word,
+                    position,
+                }))
+            }
+            Some((_, Token::OpenParen)) => {
+                let inner = self.expr()?;
+                let close_position = self.position();
+                match self.next() {
+                    Some((_, Token::CloseParen)) => Ok(Expr::Group(Box::new(inner))),
+                    _ => Err(DiceError::new(close_position, format!("Missing `)` for the `(` at character {}", position + 1))),
+                }
+            }
+            Some((_, token)) => Err(DiceError::new(position, format!("Expected a number or dice, found `{token}`"))),
+            None => Err(DiceError::new(position, "Expected a number or dice")),
+        }
+    }
+
+    fn dice(&mut self, count: Option<u64>, position: usize) -> Result<Expr, DiceError> {
+        let count = count.unwrap_or(1);
+        if count == 0 || count > MAX_DICE_PER_TERM as u64 {
+            return Err(DiceError::new(position, format!("Roll between 1 and {MAX_DICE_PER_TERM} dice")));
+        }
+        let sides_position = self.position();
+        let sides = match self.next() {
+            Some((_, Token::Word(word))) if word == "df" => Sides::Fate,
+            Some((_, Token::Word(_))) => match self.next() {
+                Some((_, Token::Number(sides))) if (1..=MAX_SIDES as u64).contains(&sides) => Sides::Number(sides as u32),
+                Some((at, Token::Number(_))) => {
+                    return Err(DiceError::new(at, format!("Dice need between 1 and {MAX_SIDES} sides")));
+                }
+                Some((_, Token::Percent)) => Sides::Number(100),
+                _ => return Err(DiceError::new(sides_position + 1, "Expected the number of sides, `%` or `F` after `d`")),
+            },
+            _ => unreachable!("dice() is only called before a `d` word"),
+        };
+        let mut term = DiceTerm { count: count as u32, sides, modifiers: Vec::new(), source: String::new(), position };
+        while let Some(modifier_position) = self.tokens.get(self.index).map(|(p, _)| *p) {
+            let modifier = match self.peek().cloned() {
+                Some(Token::Bang) => {
+                    self.index += 1;
+                    if term.faces().0 == term.faces().1 || term.sides == Sides::Fate {
+                        return Err(DiceError::new(modifier_position, "These dice can't explode"));
+                    }
+                    if term.modifiers.contains(&Modifier::Explode) {
+                        return Err(DiceError::new(modifier_position, "These dice already explode"));
+                    }
+                    Modifier::Explode
+                }
+                Some(Token::Word(word)) if ["k", "kh", "kl", "d", "dh", "dl"].contains(&word.as_str()) => {
+                    self.index += 1;
+                    let amount = self.expect_number(&word)?;
+                    if amount == 0 || amount > term.count as u64 {
+                        return Err(DiceError::new(modifier_position, format!("Can only {} between 1 and {} dice", if word.starts_with('k') { "keep" } else { "drop" }, term.count)));
+                    }
+                    let amount = amount as u32;
+                    match word.as_str() {
+                        "k" | "kh" => Modifier::KeepHighest(amount),
+                        "kl" => Modifier::KeepLowest(amount),
+                        "dh" => Modifier::DropHighest(amount),
+                        _ => Modifier::DropLowest(amount),
+                    }
+                }
+                Some(Token::Word(word)) if word == "r" || word == "ro" => {
+                    if term.modifiers.iter().any(|modifier| matches!(modifier, Modifier::Reroll { .. })) {
+                        return Err(DiceError::new(modifier_position, "Only one reroll rule per dice term"));
+                    }
+                    self.index += 1;
+                    let comparison = self.peek().cloned();
+                    if matches!(comparison, Some(Token::Less | Token::Greater | Token::Equals)) {
+                        self.index += 1;
+                    }
+                    let target = self.expect_number(&word)? as i64;
+                    let compare = match comparison {
+                        Some(Token::Less) => Compare::Below(target),
+                        Some(Token::Greater) => Compare::Above(target),
+                        _ => Compare::Equal(target),
+                    };
+                    let (lowest, highest) = term.faces();
+                    if (lowest..=highest).all(|face| compare.matches(face)) {
+                        return Err(DiceError::new(modifier_position, "That reroll would match every face"));
+                    }
+                    Modifier::Reroll { compare, once: word == "ro" }
+                }
+                _ => break,
+            };
+            term.modifiers.push(modifier);
+        }
+        let end = self.position();
+        term.source = self.input.chars().skip(position).take(end - position).collect::<String>().trim().to_owned();
+        Ok(Expr::Dice(term))
+    }
+}
+
+pub fn parse(input: &str) -> Result<Expr, DiceError> {
+    if input.chars().count() > MAX_INPUT_LENGTH {
+        return Err(DiceError::new(MAX_INPUT_LENGTH, format!("Expressions can be at most {MAX_INPUT_LENGTH} characters")));
+    }
+    let mut parser = Parser { input, tokens: tokenize(input)?, index: 0 };
+    if parser.tokens.is_empty() {
+        return Err(DiceError::new(0, "Expected a number or dice"));
+    }
+    let expr = parser.expr()?;
+    if let Some((position, token)) = parser.tokens.get(parser.index) {
+        return Err(DiceError::new(*position, format!("Unexpected `{token}`")));
+    }
+    Ok(expr)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Die {
+    pub value: i64,
+    /// Not counted: removed by keep/drop or replaced by a reroll.
+    pub dropped: bool,
+    pub rerolled: bool,
+    pub exploded: bool,
+}
+
+/// The outcome of rolling an expression.
+#[derive(Debug, Clone)]
+pub struct Roll {
+    pub total: i64,
+    /// The expression with each dice term's individual results filled in.
+    pub breakdown: String,
+    /// Every die rolled, including dropped ones, with the kind of die it was.
+    pub dice: Vec<(Sides, Die)>,
+}
+
+struct Roller<R: Rng> {
+    rng: R,
+    rolled: usize,
+    dice: Vec<(Sides, Die)>,
+}
+
+impl<R: Rng> Roller<R> {
+    fn roll_die(&mut self, term: &DiceTerm) -> Result<i64, DiceError> {
+        self.rolled += 1;
+        if self.rolled > MAX_TOTAL_DICE {
+            return Err(DiceError::new(term.position, format!("That would roll more than {MAX_TOTAL_DICE} dice")));
+        }
+        let (lowest, highest) = term.faces();
+        Ok(self.rng.random_range(lowest..=highest))
+    }
+
+    fn roll_term(&mut self, term: &DiceTerm) -> Result<Vec<Die>, DiceError> {
+        let reroll = term.modifiers.iter().find_map(|modifier| match modifier {
+            Modifier::Reroll { compare, once } => Some((*compare, *once)),
+            _ => None,
+        });
+        let explode = term.modifiers.contains(&Modifier::Explode);
+        let (_, highest) = term.faces();
+        let mut dice = Vec::new();
+        let mut pending = term.count as usize;
+        while pending > 0 {
+            pending -= 1;
+            let mut value = self.roll_die(term)?;
+            if let Some((compare, once)) = reroll {
+                let mut rerolls = 0;
+                while compare.matches(value) && rerolls < MAX_CHAIN {
+                    dice.push(Die { value, dropped: true, rerolled: true, exploded: false });
+                    value = self.roll_die(term)?;
+                    rerolls += 1;
+                    if once {
+                        break;
+                    }
+                }
+            }
+            let exploded = explode && value == highest;
+            dice.push(Die { value, dropped: false, rerolled: false, exploded });
+            if exploded && dice.len() < MAX_CHAIN * term.count as usize {
+                pending += 1;
+            }
+        }
+        for modifier in &term.modifiers {
+            let mut kept: Vec<usize> = (0..dice.len()).filter(|i| !dice[*i].dropped).collect();
+            kept.sort_by_key(|i| dice[*i].value);
+            let (drop_low, drop_high) = match *modifier {
+                Modifier::KeepHighest(n) => (kept.len().saturating_sub(n as usize), 0),
+                Modifier::KeepLowest(n) => (0, kept.len().saturating_sub(n as usize)),
+                Modifier::DropLowest(n) => (n as usize, 0),
+                Modifier::DropHighest(n) => (0, n as usize),
+                _ => continue,
+            };
+            let dropped = kept.iter().take(drop_low).chain(kept.iter().rev().take(drop_high));
+            for i in dropped.copied().collect::<Vec<_>>() {
+                dice[i].dropped = true;
+            }
+        }
+        Ok(dice)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<(i64, String), DiceError> {
+        match expr {
+            Expr::Number(n) => Ok((*n, n.to_string())),
+            Expr::Dice(term) => {
+                let dice = self.roll_term(term)?;
+                self.dice.extend(dice.iter().map(|die| (term.sides, *die)));
+                let total = dice.iter().filter(|die| !die.dropped).map(|die| die.value).sum();
+                let faces: Vec<String> = dice.iter().map(|die| {
+                    let face = match term.sides {
+                        Sides::Fate => match die.value {
+                            1 => "+".to_owned(),
+                            -1 => "-".to_owned(),
+                            _ => "0".to_owned(),
+                        },
+                        Sides::Number(_) => die.value.to_string(),
+                    };
+                    let face = if die.exploded { format!("{face}!") } else { face };
+                    if die.dropped { format!("~~{face}~~") } else { face }
+                }).collect();
+                Ok((total, format!("{} [{}]", term.source, faces.join(", "))))
+            }
+            Expr::Negate { inner, position } => {
+                let (value, text) = self.eval(inner)?;
+                let value = value.checked_neg().ok_or_else(|| DiceError::new(*position, "The result is too large"))?;
+                Ok((value, format!("-{text}")))
+            }
+            Expr::Group(inner) => {
+                let (value, text) = self.eval(inner)?;
+                Ok((value, format!("({text})")))
+            }
+            Expr::Binary { op, left, right, position } => {
+                let (left, left_text) = self.eval(left)?;
+                let (right, right_text) = self.eval(right)?;
+                let value = match op {
+                    BinaryOp::Add => left.checked_add(right),
+                    BinaryOp::Subtract => left.checked_sub(right),
+                    BinaryOp::Multiply => left.checked_mul(right),
+                    BinaryOp::Divide if right == 0 => return Err(DiceError::new(*position, "Division by zero")),
+                    BinaryOp::Divide => left.checked_div(right),
+                };
+                let value = value.ok_or_else(|| DiceError::new(*position, "The result is too large"))?;
+                Ok((value, format!("{} {} {}", left_text, op.symbol(), right_text)))
+            }
+        }
+    }
+}
+
+/// Parses and rolls an expression such as `2d6+1d4`, `4d6kh3`, `adv+5` or `(2d8!+3)*2`.
+pub fn roll(input: &str) -> Result<Roll, DiceError> {
+    let expr = parse(input)?;
+    let mut roller = Roller { rng: rand::rng(), rolled: 0, dice: Vec::new() };
+    let (total, breakdown) = roller.eval(&expr)?;
+    Ok(Roll { total, breakdown, dice: roller.dice })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(input: &str) -> i64 {
+        roll(input).unwrap_or_else(|e| panic!("`{input}` failed: {e}")).total
+    }
+
+    fn error(input: &str) -> DiceError {
+        parse(input).and_then(|_| roll(input)).expect_err(input)
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(total("2+3*4"), 14);
+        assert_eq!(total("(2+3)*4"), 20);
+        assert_eq!(total("10-2-3"), 5);
+        assert_eq!(total("20/2/5"), 2);
+        assert_eq!(total("-2*3"), -6);
+        assert_eq!(total("--4"), 4);
+        assert_eq!(total("7/2"), 3);
+    }
+
+    #[test]
+    fn tokens_split_into_known_words() {
+        let words = |input: &str| tokenize(input).unwrap().into_iter().map(|(_, token)| token).collect::<Vec<_>>();
+        assert_eq!(
+            words("4dfkh2"),
+            vec![Token::Number(4), Token::Word("df".to_owned()), Token::Word("kh".to_owned()), Token::Number(2)],
+        );
+        assert_eq!(
+            words("4d6DL1"),
+            vec![Token::Number(4), Token::Word("d".to_owned()), Token::Number(6), Token::Word("dl".to_owned()), Token::Number(1)],
+        );
+        assert_eq!(words("adv"), vec![Token::Word("adv".to_owned())]);
+        assert_eq!(words("2 d 8 − 1"), vec![
+            Token::Number(2), Token::Word("d".to_owned()), Token::Number(8), Token::Minus, Token::Number(1),
+        ]);
+    }
+
+    #[test]
+    fn parses_dice_terms() {
+        let Ok(Expr::Dice(term)) = parse("4dFkh2") else { panic!("expected a dice term") };
+        assert_eq!((term.count, term.sides), (4, Sides::Fate));
+        assert_eq!(term.modifiers, vec![Modifier::KeepHighest(2)]);
+        let Ok(Expr::Dice(term)) = parse("d%") else { panic!("expected a dice term") };
+        assert_eq!((term.count, term.sides), (1, Sides::Number(100)));
+        let Ok(Expr::Dice(term)) = parse("10d6r<3!") else { panic!("expected a dice term") };
+        assert_eq!(term.modifiers, vec![Modifier::Reroll { compare: Compare::Below(3), once: false }, Modifier::Explode]);
+        let Ok(Expr::Dice(term)) = parse("dis") else { panic!("expected a dice term") };
+        assert_eq!((term.count, term.modifiers), (2, vec![Modifier::KeepLowest(1)]));
+    }
+
+    #[test]
+    fn keep_and_drop() {
+        let cases = [
+            ("4d6kh3", 3, true),
+            ("4d6k3", 3, true),
+            ("4d6dl1", 3, true),
+            ("4d6d1", 3, true),
+            ("adv", 1, true),
+            ("4d6kl1", 1, false),
+            ("4d6dh2", 2, false),
+        ];
+        for (input, kept_count, keeps_highest) in cases {
+            let roll = roll(input).unwrap();
+            let (kept, dropped): (Vec<Die>, Vec<Die>) = roll.dice.iter().map(|(_, die)| *die).partition(|die| !die.dropped);
+            assert_eq!(kept.len(), kept_count, "{input}");
+            assert_eq!(kept.iter().map(|die| die.value).sum::<i64>(), roll.total, "{input}");
+            for (kept, dropped) in kept.iter().flat_map(|kept| dropped.iter().map(move |dropped| (kept, dropped))) {
+                if keeps_highest {
+                    assert!(kept.value >= dropped.value, "{input}: {:?}", roll.dice);
+                } else {
+                    assert!(kept.value <= dropped.value, "{input}: {:?}", roll.dice);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reroll_validation() {
+        assert_eq!(error("1d6r<7"), DiceError::new(3, "That reroll would match every face"));
+        assert_eq!(error("1d6r1r2"), DiceError::new(5, "Only one reroll rule per dice term"));
+        assert_eq!(error("4d6!!"), DiceError::new(4, "These dice already explode"));
+        assert_eq!(error("4dF!"), DiceError::new(3, "These dice can't explode"));
+        for _ in 0..20 {
+            let roll = roll("10d6r1").unwrap();
+            assert!(roll.dice.iter().filter(|(_, die)| !die.dropped).all(|(_, die)| die.value != 1));
+        }
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error("2d6+").position, 4);
+        assert_eq!(error("2d6 + foo").position, 6);
+        assert_eq!(error("(1d4+2").message, "Missing `)` for the `(` at character 1");
+        assert_eq!(error("(1d4+2").position, 6);
+        assert_eq!(error("0d6").position, 0);
+        assert_eq!(error("2d0").position, 2);
+        assert_eq!(error("4d6kh5").position, 3);
+        assert_eq!(error("2d6 3").position, 4);
+        assert_eq!(error("1/0").position, 1);
+        assert_eq!(error("2d6 + foo").pointer("2d6 + foo"), "2d6 + foo\n      ^");
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        assert_eq!(error("-(-9223372036854775807-1)"), DiceError::new(0, "The result is too large"));
+        assert_eq!(error("9223372036854775807+1").message, "The result is too large");
+        assert_eq!(error("-9223372036854775808").message, "Number is too large");
+    }
+}
@@ -4,9 +4,16 @@ mod cluster;
 mod commands;
 mod utils;
 mod config;
+mod countdowns;
+mod dice;
+mod polls;
+mod rolls;
 mod tickets;
 mod staff;
 mod stats;
+mod signing;
+mod storage;
+mod transport;
 
 use poise::serenity_prelude as serenity;
 use poise::serenity_prelude::ChannelId;
@@ -21,6 +28,7 @@ use std::{
 use clap::Parser;
 use crate::utils::get_logging_channels;
 use crate::cluster::ClusterState;
+use crate::transport::ClusterTransport;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -29,6 +37,9 @@ struct Args {
     dauth: Option<String>,
     #[arg(short, long)]
     coordination: Option<u64>,
+    /// Copy config.toml and the per-guild files (stats, tickets, cases, polls) into the SQLite database and exit
+    #[arg(long)]
+    import_files: bool,
 }
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
@@ -36,9 +47,12 @@ type Context<'a> = poise::Context<'a, Data, Error>;
 
 #[derive(Clone)]
 pub struct Data {
-    votes: Arc<Mutex<HashMap<String, u32>>>,
     cluster_state: Arc<Mutex<ClusterState>>,
+    cluster_transport: Arc<dyn ClusterTransport>,
     ticket_cooldowns: Arc<Mutex<HashMap<(u64, u64), std::time::Instant>>>,
+    stat_cooldowns: Arc<Mutex<stats::StatCooldowns>>,
+    poll_locks: Arc<Mutex<polls::PollLocks>>,
+    countdowns: countdowns::CountdownRunner,
 }
 
 async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
@@ -47,6 +61,8 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
         poise::FrameworkError::Command { error, ctx, .. } => {
             println!("Error in command `{}`: {:?}", ctx.command().name, error,);
         }
+        // Raised on every instance that isn't meant to answer; another instance handles it.
+        poise::FrameworkError::CommandCheckFailed { error: None, .. } => {}
         error => {
             if let Err(e) = poise::builtins::on_error(error).await {
                 println!("Error while handling error: {e}")
@@ -79,7 +95,7 @@ async fn event_handler(
                 ).await {
                     let member = guild_id.member(&ctx.http, user_id).await?;
                     if let Err(e) = member.add_role(&ctx.http, role_id).await {
-                        println!("Failed to add role {} to user {}: {}", &role_id, &user_id, e);
+                        println!("Failed to add role {} to user {}: {}", role_id, user_id, e);
                     }
                 }
             }
@@ -100,28 +116,36 @@ async fn event_handler(
                 ).await {
                     let member = guild_id.member(&ctx.http, user_id).await?;
                     if let Err(e) = member.remove_role(&ctx.http, role_id).await {
-                         println!("Failed to remove role {} from user {}: {}", &role_id, &user_id, &e);
+                         println!("Failed to remove role {} from user {}: {}", role_id, user_id, e);
                     }
                 }
             }
         }
         serenity::FullEvent::Message { new_message } => {
-            cluster::handle_cluster_message(ctx, new_message, data.cluster_state.clone()).await?;
+            data.cluster_transport.ingest(new_message).await;
             if let Some(guild_id) = new_message.guild_id {
                 let prefix = "~";
-                if new_message.content.starts_with(prefix) && !new_message.author.bot {
+                if is_leader && new_message.content.starts_with(prefix) && !new_message.author.bot {
                     let content = &new_message.content[prefix.len()..];
                     let mut parts = content.split_whitespace();
                     if let Some(cmd_name) = parts.next() {
                         let cmd_lower = cmd_name.to_lowercase();
-                        let allowed_stats = crate::utils::get_custom_stats(guild_id.into()).await;
-                        if allowed_stats.contains(&cmd_lower) {
+                        if let Some(definition) = crate::utils::get_stat_definition(guild_id.into(), &cmd_lower).await {
                             let amount = parts.next()
                                 .and_then(|s| s.parse::<i64>().ok())
                                 .unwrap_or(1);
-                            match crate::stats::modify_user_stat(guild_id.into(), new_message.author.id.into(), &cmd_lower, amount).await {
-                                Ok(new_total) => {
-                                    let _ = new_message.reply(&ctx.http, format!("📈 Added {} to your `{}` stat! New total: **{}**", amount, cmd_lower, new_total)).await;
+                            match crate::stats::record_own_stat(
+                                ctx,
+                                data,
+                                guild_id,
+                                new_message.author.id,
+                                &cmd_lower,
+                                &definition,
+                                amount,
+                                &format!("~{cmd_lower}"),
+                            ).await {
+                                Ok(reply) => {
+                                    let _ = new_message.reply(&ctx.http, reply).await;
                                 }
                                 Err(e) => {
                                     println!("Failed to modify stat: {}", e);
@@ -133,6 +157,9 @@ async fn event_handler(
             }
         }
         serenity::FullEvent::GuildMemberAddition { new_member } => {
+            if !is_leader {
+                return Ok(());
+            }
             let guild_id = new_member.guild_id;
             if let Some(log_channel) = crate::utils::get_logging_channel(
                 guild_id.into(),
@@ -159,43 +186,44 @@ async fn event_handler(
             }
         },
         serenity::FullEvent::GuildMemberRemoval { guild_id, user, .. } => {
+            if !is_leader {
+                return Ok(());
+            }
             let guild_id_u64 = <poise::serenity_prelude::GuildId as std::convert::Into<u64>>::into(*guild_id);
-            if is_leader {
-                if let Ok(channels) = guild_id.channels(&ctx.http).await {
-                    for (channel_id, channel) in channels {
-                        if channel.name.starts_with("ticket-") {
-                            let mut is_ticket_owner = false;
-                            if let Some(topic) = &channel.topic {
-                                if topic.contains(&user.id.to_string()) {
-                                    is_ticket_owner = true;
-                                }
+            if let Ok(channels) = guild_id.channels(&ctx.http).await {
+                for (channel_id, channel) in channels {
+                    if channel.name.starts_with("ticket-") {
+                        let mut is_ticket_owner = false;
+                        if let Some(topic) = &channel.topic {
+                            if topic.contains(&user.id.to_string()) {
+                                is_ticket_owner = true;
                             }
-                            for overwrite in &channel.permission_overwrites {
-                                if let serenity::PermissionOverwriteType::Member(user_id) = overwrite.kind {
-                                    if user_id == user.id && overwrite.allow.contains(serenity::Permissions::VIEW_CHANNEL) {
-                                        is_ticket_owner = true;
-                                        break;
-                                    }
+                        }
+                        for overwrite in &channel.permission_overwrites {
+                            if let serenity::PermissionOverwriteType::Member(user_id) = overwrite.kind {
+                                if user_id == user.id && overwrite.allow.contains(serenity::Permissions::VIEW_CHANNEL) {
+                                    is_ticket_owner = true;
+                                    break;
                                 }
                             }
-                            if is_ticket_owner {
-                                let ctx_clone = ctx.clone();
-                                let user_clone = user.clone();
-                                let channel_name = channel.name.clone();
-                                let guild_id_obj = *guild_id;
-                                tokio::spawn(async move {
-                                    println!("Auto-closing ticket {} because owner {} ({}) left", channel_name, user_clone.tag(), user_clone.id);
-                                    let _ = crate::tickets::close_ticket_routine(
-                                        &ctx_clone,
-                                        guild_id_obj,
-                                        channel_id,
-                                        channel_name,
-                                        "Ticket owner left the server (verified via permission scan)".to_string(),
-                                        "Automated System".to_string(),
-                                        None
-                                    ).await;
-                                });
-                            }
+                        }
+                        if is_ticket_owner {
+                            let ctx_clone = ctx.clone();
+                            let user_clone = user.clone();
+                            let channel_name = channel.name.clone();
+                            let guild_id_obj = *guild_id;
+                            tokio::spawn(async move {
+                                println!("Auto-closing ticket {} because owner {} ({}) left", channel_name, user_clone.tag(), user_clone.id);
+                                let _ = crate::tickets::close_ticket_routine(
+                                    &ctx_clone,
+                                    guild_id_obj,
+                                    channel_id,
+                                    channel_name,
+                                    "Ticket owner left the server (verified via permission scan)".to_owned(),
+                                    "Automated System".to_owned(),
+                                    None
+                                ).await;
+                            });
                         }
                     }
                 }
@@ -226,6 +254,9 @@ async fn event_handler(
             }
         },
         serenity::FullEvent::MessageDelete { channel_id, deleted_message_id, guild_id: Some(guild_id) } => {
+            if !is_leader {
+                return Ok(());
+            }
             let guild_id_u64 = <poise::serenity_prelude::GuildId as std::convert::Into<u64>>::into(*guild_id);
             if let Some(log_channel) = crate::utils::get_logging_channel(
                 guild_id_u64,
@@ -255,6 +286,9 @@ async fn event_handler(
             }
         },
         serenity::FullEvent::MessageDeleteBulk { channel_id, multiple_deleted_messages_ids, guild_id: Some(guild_id) } => {
+            if !is_leader {
+                return Ok(());
+            }
             let guild_id_u64 = <poise::serenity_prelude::GuildId as std::convert::Into<u64>>::into(*guild_id);
             if let Some(log_channel) = crate::utils::get_logging_channel(
                 guild_id_u64,
@@ -271,6 +305,11 @@ async fn event_handler(
                 log_channel.send_message(ctx, serenity::CreateMessage::new().embed(embed)).await?;
             }
         },
+        serenity::FullEvent::InteractionCreate { interaction: serenity::Interaction::Component(component) } => {
+            if is_leader {
+                crate::polls::handle_component(ctx, data, component).await?;
+            }
+        },
         serenity::FullEvent::Ready { data_about_bot, .. } => {
             println!("Bot has started as {}", data_about_bot.user.name);
         },
@@ -279,28 +318,124 @@ async fn event_handler(
     Ok(())
 }
 
+/// Drops react-roles whose message is gone, once this instance first leads. Followers
+/// leave their config alone and get the cleanup through replication.
+async fn prune_dead_react_roles_as_leader(
+    http: Arc<serenity::Http>,
+    cache: Arc<serenity::Cache>,
+    transport: Arc<dyn ClusterTransport>,
+    cluster_state: Arc<Mutex<ClusterState>>,
+) {
+    while !cluster_state.lock().await.is_leader {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    println!("Performing startup cleanup of dead react-roles...");
+    let mut total_pruned = 0;
+    for guild_id in cache.guilds() {
+        let Some(token) = cluster_state.lock().await.issue_token() else {
+            println!("Lost leadership during react-role cleanup; leaving the rest to the new leader");
+            break;
+        };
+        match crate::utils::prune_dead_react_roles(&http, guild_id.into()).await {
+            Ok((pruned_ids, change)) => {
+                if !pruned_ids.is_empty() {
+                    println!("- Pruned {} entry/entries for guild {}", pruned_ids.len(), guild_id);
+                    total_pruned += pruned_ids.len();
+                }
+                if let Some(change) = change {
+                    if let Err(e) = transport.send(&cluster::ClusterMessage::ConfigChange { change, token }).await {
+                        println!("- Failed to replicate pruned react-roles for guild {}: {}", guild_id, e);
+                    }
+                }
+            }
+            Err(e) => println!("- Error pruning for guild {}: {}", guild_id, e),
+        }
+    }
+    if total_pruned > 0 {
+        println!("Total dead react-roles pruned: {}. Saving updated config to disk.", total_pruned);
+        if let Err(e) = crate::utils::save_config_to_disk().await {
+            println!("!! FAILED to save pruned config to disk: {}", e);
+        }
+    } else {
+        println!("No dead react-roles found.");
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    let mut standalone = false;
+    let mut cluster_config = match crate::utils::load_cluster_config() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Failed to load cluster config: {e}. Using random instance ID and default priority");
+            standalone = true;
+            crate::utils::ClusterConfig {
+                cluster: crate::utils::ClusterInfo {
+                    instance_id: format!("instance-{}", rand::random::<u64>()),
+                    priority: 1,
+                    secret: None,
+                },
+                transport: Default::default(),
+                storage: Default::default(),
+            }
+        }
+    };
+    if args.import_files {
+        match crate::storage::import_files(&cluster_config.storage.path) {
+            Ok(()) => println!("Import into {} complete", cluster_config.storage.path),
+            Err(e) => {
+                println!("!! Import failed: {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Err(e) = crate::storage::init(&cluster_config.storage) {
+        println!("!! Refusing to start: {e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = crate::utils::load_config_from_disk().await {
+        println!("!! Refusing to start: {e}");
+        std::process::exit(1);
+    }
+    crate::utils::start_config_writer();
     let token = args.dauth
         .or_else(|| env::var("DISCORD_TOKEN").ok())
         .expect("Missing Discord token. Please set either DISCORD_TOKEN environment variable or use --dauth argument");
     let coordination_channel_id = args.coordination
-        .or_else(|| env::var("COORDINATION_CHANNEL_ID").ok().and_then(|s| s.parse().ok()))
-        .expect("Missing coordination channel ID. Please set either COORDINATION_CHANNEL_ID environment variable or use --coordination argument");
+        .or_else(|| env::var("COORDINATION_CHANNEL_ID").ok().and_then(|s| s.parse().ok()));
+    let cluster_secret = match env::var("CLUSTER_SECRET").ok().or_else(|| cluster_config.cluster.secret.clone()) {
+        Some(secret) => secret,
+        // Without a cluster.toml there are no peers to authenticate, so run alone.
+        None if standalone => {
+            println!("No cluster secret configured; running as a standalone instance");
+            cluster_config.transport.kind = crate::transport::TransportKind::Memory;
+            format!("{:032x}", rand::random::<u128>())
+        }
+        None => {
+            println!("!! Refusing to start: missing cluster secret. Please set either CLUSTER_SECRET environment variable or `secret` under [cluster] in cluster.toml");
+            std::process::exit(1);
+        }
+    };
+    if cluster_config.transport.kind == crate::transport::TransportKind::Discord && coordination_channel_id.is_none() {
+        println!("!! Refusing to start: missing coordination channel ID. Please set either COORDINATION_CHANNEL_ID environment variable or use --coordination argument");
+        std::process::exit(1);
+    }
     let options = poise::FrameworkOptions {
         commands: vec![
             staff::register(),
             staff::quit(),
             staff::writeconfig(),
+            staff::cluster(),
             staff::ban(),
             staff::kick(),
             commands::help(),
             commands::announce(),
-            commands::vote(),
-            commands::getvotes(),
+            polls::poll(),
             commands::diceroll(),
-            commands::countdown(),
+            rolls::roll(),
+            countdowns::countdown(),
             commands::reddit(),
             commands::tumblr(),
             config::config(),
@@ -309,6 +444,7 @@ async fn main() {
             tickets::scantickets(),
             stats::stat(),
             stats::viewstat(),
+            stats::leaderboard(),
         ],
         prefix_options: poise::PrefixFrameworkOptions {
             prefix: Some("~".into()),
@@ -322,6 +458,7 @@ async fn main() {
             ..Default::default()
         },
         on_error: |error| Box::pin(on_error(error)),
+        command_check: Some(|ctx| Box::pin(cluster::instance_check(ctx))),
         pre_command: |ctx| {
             Box::pin(async move {
                 println!("Executing command {}...", ctx.command().qualified_name);
@@ -343,71 +480,76 @@ async fn main() {
             Box::pin(async move {
                 println!("Logged in as {}", _ready.user.name);
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                let cluster_config = match crate::utils::load_cluster_config() {
-                    Ok(config) => config,
-                    Err(e) => {
-                        println!("Failed to load cluster config: {e}. Using random instance ID and default priority");
-                        crate::utils::ClusterConfig {
-                            cluster: crate::utils::ClusterInfo {
-                                instance_id: format!("instance-{}", rand::random::<u64>()),
-                                priority: 1,
-                            }
-                        }
-                    }
-                };
                 let cluster_state = Arc::new(Mutex::new(ClusterState::new(
-                    cluster_config.cluster.instance_id,
+                    cluster_config.cluster.instance_id.clone(),
                     cluster_config.cluster.priority,
-                    coordination_channel_id,
                 )));
-                println!("Performing startup cleanup of dead react-roles...");
-                let guilds = ctx.cache.guilds();
-                let mut total_pruned = 0;
-                for guild_id in guilds {
-                    match crate::utils::prune_dead_react_roles(&ctx.http, guild_id.into()).await {
-                        Ok(pruned_ids) if !pruned_ids.is_empty() => {
-                            println!("- Pruned {} entry/entries for guild {}", pruned_ids.len(), guild_id);
-                            total_pruned += pruned_ids.len();
-                        },
-                        Err(e) => println!("- Error pruning for guild {}: {}", &guild_id, &e),
-                        _ => {}
-                    }
-                }
-                if total_pruned > 0 {
-                    println!("Total dead react-roles pruned: {}. Saving updated config to disk.", &total_pruned);
-                    if let Err(e) = crate::utils::save_config_to_disk().await {
-                        println!("!! FAILED to save pruned config to disk: {}", &e);
-                    }
-                } else {
-                    println!("No dead react-roles found.");
-                }
+                let signer = Arc::new(crate::signing::Signer::new(
+                    cluster_config.cluster.instance_id.clone(),
+                    &cluster_secret,
+                ));
+                let (inbox_tx, inbox_rx) = tokio::sync::mpsc::unbounded_channel();
+                let cluster_transport = crate::transport::connect(
+                    &cluster_config.transport,
+                    ctx.http.clone(),
+                    coordination_channel_id,
+                    signer.clone(),
+                    inbox_tx,
+                ).await?;
                 let data = Data {
-                    votes: Arc::new(Mutex::new(HashMap::new())),
                     cluster_state: cluster_state.clone(),
+                    cluster_transport: cluster_transport.clone(),
                     ticket_cooldowns: Arc::new(Mutex::new(HashMap::new())),
+                    stat_cooldowns: Arc::new(Mutex::new(HashMap::new())),
+                    poll_locks: Arc::new(Mutex::new(HashMap::new())),
+                    countdowns: countdowns::CountdownRunner::new(
+                        ctx.http.clone(),
+                        cluster_transport.clone(),
+                        cluster_state.clone(),
+                    ),
                 };
-                let ctx_for_cluster = ctx.clone();
-                let data_for_cluster = Arc::new(Mutex::new(data.clone()));
-                tokio::spawn(async move {
-                    cluster::start_cluster_loop(
-                        ctx_for_cluster,
-                        data_for_cluster,
-                        cluster_state.clone()
-                    ).await;
-                });
+                tokio::spawn(cluster::start_inbox_loop(
+                    inbox_rx,
+                    signer,
+                    cluster_transport.clone(),
+                    cluster_state.clone(),
+                    ctx.clone(),
+                    framework.shard_manager().clone(),
+                ));
+                tokio::spawn(cluster::handoff_on_signal(
+                    cluster_transport.clone(),
+                    cluster_state.clone(),
+                    framework.shard_manager().clone(),
+                ));
+                tokio::spawn(polls::start_poll_closer(
+                    ctx.clone(),
+                    cluster_transport.clone(),
+                    cluster_state.clone(),
+                    data.poll_locks.clone(),
+                ));
+                tokio::spawn(countdowns::start_countdown_resumer(data.countdowns.clone()));
+                tokio::spawn(prune_dead_react_roles_as_leader(
+                    ctx.http.clone(),
+                    ctx.cache.clone(),
+                    cluster_transport.clone(),
+                    cluster_state.clone(),
+                ));
+                tokio::spawn(cluster::start_cluster_loop(
+                    cluster_transport,
+                    cluster_state,
+                    ctx.cache.clone(),
+                ));
                 let logging_channels = get_logging_channels().await;
-                for (guild_id_str, channel_id) in logging_channels {
-                    if let Ok(guild_id) = guild_id_str.parse::<u64>() {
-                        let channel = ChannelId::new(channel_id as u64);
-                        let cluster_state = data.cluster_state.lock().await;
-                        let something = format!("Instance {} has started successfully!", cluster_state.my_instance_id);
-                        let embed = serenity::CreateEmbed::new()
-                            .title("Instance Online")
-                            .description(something)
-                            .color(serenity::Colour::DARK_GREEN);
-                        if let Err(e) = channel.send_message(ctx, serenity::CreateMessage::new().embed(embed)).await {
-                            println!("Failed to send boot announcement to guild {guild_id}: {e}");
-                        }
+                for (guild_id, channel_id) in logging_channels {
+                    let channel = ChannelId::new(channel_id);
+                    let cluster_state = data.cluster_state.lock().await;
+                    let something = format!("Instance {} has started successfully!", cluster_state.my_instance_id);
+                    let embed = serenity::CreateEmbed::new()
+                        .title("Instance Online")
+                        .description(something)
+                        .color(serenity::Colour::DARK_GREEN);
+                    if let Err(e) = channel.send_message(ctx, serenity::CreateMessage::new().embed(embed)).await {
+                        println!("Failed to send boot announcement to guild {guild_id}: {e}");
                     }
                 }
                 Ok(data)
@@ -0,0 +1,735 @@
+use crate::{Context, Data, Error};
+use crate::cluster::{ClusterMessage, ClusterState, FencingToken};
+use crate::storage::{Poll, PollKind, PollVote};
+use crate::transport::ClusterTransport;
+use poise::serenity_prelude as serenity;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const MAX_POLL_OPTIONS: usize = 10;
+const MAX_POLL_DURATION: i64 = 30 * 24 * 60 * 60;
+const POLL_CHECK_INTERVAL: u64 = 30;
+const RESULT_BAR_WIDTH: usize = 20;
+const BUTTONS_PER_ROW: usize = 5;
+/// One select menu per rank; the fifth action row holds the clear button.
+const MAX_RANK_MENUS: usize = 4;
+const VOTERS_SHOWN: usize = 10;
+const COMPONENT_PREFIX: &str = "poll:";
+
+/// One lock per (guild, poll), held from loading a poll until it is saved so
+/// concurrent votes and closes don't overwrite each other's changes.
+pub type PollLocks = std::collections::HashMap<(u64, u64), Arc<Mutex<()>>>;
+
+/// Parses durations like `90s`, `45m`, `2h` or `1d12h` into seconds.
+fn parse_duration(input: &str) -> Option<i64> {
+    let mut total = 0i64;
+    let mut number = String::new();
+    for c in input.trim().to_lowercase().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        total = total.checked_add(number.parse::<i64>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    (number.is_empty() && total > 0).then_some(total)
+}
+
+fn is_open(poll: &Poll) -> bool {
+    !poll.closed && poll.ends_at > chrono::Utc::now().timestamp()
+}
+
+/// Votes per option. Multiple-choice ballots count once for every pick; ranked
+/// ballots only count their first choice.
+fn tally(poll: &Poll) -> Vec<usize> {
+    let mut counts = vec![0; poll.options.len()];
+    for vote in &poll.votes {
+        let counted = match poll.kind {
+            PollKind::Ranked => &vote.choices[..vote.choices.len().min(1)],
+            PollKind::Single | PollKind::Multi => &vote.choices[..],
+        };
+        for choice in counted {
+            if let Some(count) = counts.get_mut(*choice) {
+                *count += 1;
+            }
+        }
+    }
+    counts
+}
+
+/// Instant-runoff counting: each round counts every ballot for its highest-ranked
+/// option still in the race, then drops the last-placed options until one has a
+/// majority. Rounds hold None for eliminated options; a tie for the win has no winner.
+fn instant_runoff(poll: &Poll) -> (Vec<Vec<Option<usize>>>, Option<usize>) {
+    let mut remaining: Vec<bool> = vec![true; poll.options.len()];
+    let mut rounds = Vec::new();
+    loop {
+        let mut counts: Vec<Option<usize>> = remaining.iter().map(|&r| r.then_some(0)).collect();
+        let mut active = 0;
+        for vote in &poll.votes {
+            if let Some(choice) = vote.choices.iter().find(|c| remaining.get(**c).copied().unwrap_or(false)) {
+                if let Some(Some(count)) = counts.get_mut(*choice) {
+                    *count += 1;
+                    active += 1;
+                }
+            }
+        }
+        rounds.push(counts.clone());
+        let in_race: Vec<(usize, usize)> = counts.iter().enumerate().filter_map(|(i, c)| c.map(|c| (i, c))).collect();
+        if active == 0 {
+            return (rounds, None);
+        }
+        if let Some((leader, _)) = in_race.iter().find(|(_, count)| count * 2 > active) {
+            return (rounds, Some(*leader));
+        }
+        let lowest = in_race.iter().map(|(_, count)| *count).min().unwrap_or(0);
+        let eliminated: Vec<usize> = in_race.iter().filter(|(_, count)| *count == lowest).map(|(i, _)| *i).collect();
+        if eliminated.len() == in_race.len() {
+            return (rounds, None);
+        }
+        for option in eliminated {
+            remaining[option] = false;
+        }
+    }
+}
+
+fn bar(count: usize, total: usize) -> String {
+    let filled = (count * RESULT_BAR_WIDTH).checked_div(total).unwrap_or(0);
+    format!("`{}{}`", "█".repeat(filled), "░".repeat(RESULT_BAR_WIDTH - filled))
+}
+
+fn voter_list(poll: &Poll, option: usize) -> String {
+    let voters: Vec<String> = poll.votes
+        .iter()
+        .filter(|vote| match poll.kind {
+            PollKind::Ranked => vote.choices.first() == Some(&option),
+            PollKind::Single | PollKind::Multi => vote.choices.contains(&option),
+        })
+        .map(|vote| format!("<@{}>", vote.user_id))
+        .collect();
+    if voters.is_empty() {
+        return String::new();
+    }
+    let mut list = voters.iter().take(VOTERS_SHOWN).cloned().collect::<Vec<_>>().join(" ");
+    if voters.len() > VOTERS_SHOWN {
+        list.push_str(&format!(" +{} more", voters.len() - VOTERS_SHOWN));
+    }
+    format!("{}\n", list)
+}
+
+fn describe_choices(poll: &Poll, choices: &[usize]) -> String {
+    let names = choices.iter().filter_map(|choice| poll.options.get(*choice));
+    match poll.kind {
+        PollKind::Ranked => names.enumerate().map(|(rank, name)| format!("{}. **{}**", rank + 1, name)).collect::<Vec<_>>().join(", "),
+        PollKind::Single | PollKind::Multi => names.map(|name| format!("**{}**", name)).collect::<Vec<_>>().join(", "),
+    }
+}
+
+/// The poll with one bar per option; once closed, the winning options are marked.
+/// Ranked polls show first choices while open and the final runoff round once closed.
+fn poll_embed(poll: &Poll) -> serenity::CreateEmbed {
+    let ballots = poll.votes.len();
+    let (counts, winners, eliminated_in): (Vec<usize>, Vec<usize>, Vec<Option<usize>>) = match poll.kind {
+        PollKind::Ranked if poll.closed => {
+            let (rounds, winner) = instant_runoff(poll);
+            let last = rounds.last().cloned().unwrap_or_default();
+            // The first round an option is missing from follows the round that eliminated it.
+            let eliminated_in = (0..poll.options.len())
+                .map(|option| rounds.iter().position(|round| round[option].is_none()))
+                .collect();
+            (last.iter().map(|count| count.unwrap_or(0)).collect(), winner.into_iter().collect(), eliminated_in)
+        }
+        _ => {
+            let counts = tally(poll);
+            let most = counts.iter().copied().max().unwrap_or(0);
+            let winners = if poll.closed && most > 0 {
+                (0..counts.len()).filter(|i| counts[*i] == most).collect()
+            } else {
+                Vec::new()
+            };
+            (counts, winners, vec![None; poll.options.len()])
+        }
+    };
+
+    let mut description = String::new();
+    for (index, option) in poll.options.iter().enumerate() {
+        if let Some(round) = eliminated_in[index] {
+            description.push_str(&format!("~~{}. {}~~ eliminated in round {}\n", index + 1, option, round));
+            continue;
+        }
+        let count = counts[index];
+        let percent = (count * 100).checked_div(ballots).unwrap_or(0);
+        let marker = if winners.contains(&index) { " 🏆" } else { "" };
+        description.push_str(&format!("**{}. {}**{}\n{} {} ({}%)\n", index + 1, option, marker, bar(count, ballots), count, percent));
+        if !poll.anonymous {
+            description.push_str(&voter_list(poll, index));
+        }
+    }
+    if poll.closed {
+        description.push_str(&format!("\nClosed with {} ballots.", ballots));
+        if poll.kind == PollKind::Ranked && winners.is_empty() && ballots > 0 {
+            description.push_str(" The runoff ended in a tie.");
+        }
+    } else {
+        let how = match poll.kind {
+            PollKind::Single => "Pick an option below",
+            PollKind::Multi => "Pick any number of options below",
+            PollKind::Ranked => "Rank the options below; bars show first choices",
+        };
+        description.push_str(&format!("\n{} ballots so far. {}. Ends <t:{}:R>.", ballots, how, poll.ends_at));
+    }
+    let kind = poise::ChoiceParameter::name(&poll.kind);
+    let visibility = if poll.anonymous { "anonymous" } else { "public" };
+    serenity::CreateEmbed::new()
+        .title(format!("📊 Poll #{}: {}", poll.poll_id, poll.question))
+        .description(description)
+        .footer(serenity::CreateEmbedFooter::new(format!("{}, {}", kind, visibility)))
+        .color(if poll.closed { serenity::Colour::DARK_GREY } else { serenity::Colour::BLUE })
+}
+
+fn option_label(poll: &Poll, index: usize) -> String {
+    format!("{}. {}", index + 1, poll.options[index]).chars().take(80).collect()
+}
+
+fn select_options(poll: &Poll) -> Vec<serenity::CreateSelectMenuOption> {
+    (0..poll.options.len())
+        .map(|index| serenity::CreateSelectMenuOption::new(option_label(poll, index), index.to_string()))
+        .collect()
+}
+
+/// Buttons for single-choice polls, a multi-select menu for multiple choice, and one
+/// menu per rank for ranked polls. Custom ids are `poll:<id>:<action>[:<n>]`.
+fn poll_components(poll: &Poll) -> Vec<serenity::CreateActionRow> {
+    let id = format!("{}{}", COMPONENT_PREFIX, poll.poll_id);
+    let mut rows = match poll.kind {
+        PollKind::Single => (0..poll.options.len())
+            .collect::<Vec<_>>()
+            .chunks(BUTTONS_PER_ROW)
+            .map(|chunk| serenity::CreateActionRow::Buttons(chunk
+                .iter()
+                .map(|index| serenity::CreateButton::new(format!("{}:option:{}", id, index))
+                    .label(option_label(poll, *index))
+                    .style(serenity::ButtonStyle::Primary))
+                .collect()))
+            .collect(),
+        PollKind::Multi => vec![serenity::CreateActionRow::SelectMenu(
+            serenity::CreateSelectMenu::new(
+                format!("{}:select", id),
+                serenity::CreateSelectMenuKind::String { options: select_options(poll) },
+            )
+            .placeholder("Pick your options")
+            .min_values(1)
+            .max_values(poll.options.len() as u8),
+        )],
+        PollKind::Ranked => (0..poll.options.len().min(MAX_RANK_MENUS))
+            .map(|rank| serenity::CreateActionRow::SelectMenu(
+                serenity::CreateSelectMenu::new(
+                    format!("{}:rank:{}", id, rank),
+                    serenity::CreateSelectMenuKind::String { options: select_options(poll) },
+                )
+                .placeholder(format!("Choice #{}", rank + 1)),
+            ))
+            .collect(),
+    };
+    rows.push(serenity::CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(format!("{}:clear", id))
+            .label("Clear my vote")
+            .style(serenity::ButtonStyle::Secondary),
+    ]));
+    rows
+}
+
+/// Replaces a member's ballot, or removes it when `choices` is empty, and returns
+/// their previous choices.
+fn cast_ballot(poll: &mut Poll, user_id: u64, choices: Vec<usize>) -> Vec<usize> {
+    let previous = poll.votes
+        .iter()
+        .position(|vote| vote.user_id == user_id)
+        .map(|index| poll.votes.remove(index).choices)
+        .unwrap_or_default();
+    if !choices.is_empty() {
+        poll.votes.push(PollVote { user_id, choices });
+    }
+    previous
+}
+
+fn ballot_response(poll: &Poll, previous: &[usize], choices: &[usize]) -> String {
+    match (previous.is_empty(), choices.is_empty()) {
+        (_, true) if previous.is_empty() => "You haven't voted in this poll.".to_owned(),
+        (_, true) => "🗑️ Your vote was removed.".to_owned(),
+        (true, false) => format!("🗳️ Voted for {}.", describe_choices(poll, choices)),
+        (false, false) => format!(
+            "🗳️ Changed your vote from {} to {}.",
+            describe_choices(poll, previous),
+            describe_choices(poll, choices)
+        ),
+    }
+}
+
+/// Locks a poll and issues a fencing token under the lock, so tokens are issued in
+/// the same order the poll's changes are saved. Returns `None` when this instance
+/// is no longer the leader.
+async fn lock_poll(
+    locks: &Mutex<PollLocks>,
+    cluster_state: &Mutex<ClusterState>,
+    guild_id: u64,
+    poll_id: u64,
+) -> Option<(tokio::sync::OwnedMutexGuard<()>, FencingToken)> {
+    let lock = locks.lock().await.entry((guild_id, poll_id)).or_default().clone();
+    let guard = lock.lock_owned().await;
+    let token = cluster_state.lock().await.issue_token()?;
+    Some((guard, token))
+}
+
+/// Sends a saved poll to the rest of the cluster. Failures are logged, since the
+/// change has already been saved and answered.
+async fn replicate(transport: &dyn ClusterTransport, poll: &Poll, token: FencingToken) {
+    if let Err(e) = transport.send(&ClusterMessage::PollUpdate { poll: poll.clone(), token }).await {
+        println!("Failed to replicate poll #{} for guild {}: {e}", poll.poll_id, poll.guild_id);
+    }
+}
+
+/// Saves a poll and sends it to the rest of the cluster.
+async fn save_and_replicate(transport: &dyn ClusterTransport, poll: &Poll, token: FencingToken) -> Result<(), Error> {
+    crate::storage::get().save_poll(poll)?;
+    replicate(transport, poll, token).await;
+    Ok(())
+}
+
+/// Redraws the poll message with the current tally. Closed polls lose their components.
+async fn refresh_poll_message(http: &serenity::Http, poll: &Poll) -> Result<(), Error> {
+    let Some(message_id) = poll.message_id else {
+        return Ok(());
+    };
+    let components = if poll.closed { Vec::new() } else { poll_components(poll) };
+    serenity::ChannelId::new(poll.channel_id)
+        .edit_message(http, message_id, serenity::EditMessage::new().embed(poll_embed(poll)).components(components))
+        .await?;
+    Ok(())
+}
+
+/// Closes a poll and posts the final results where it was created. Call it with the
+/// poll's lock held and the poll freshly loaded.
+pub async fn close_poll(
+    http: &serenity::Http,
+    transport: &dyn ClusterTransport,
+    mut poll: Poll,
+    token: FencingToken,
+) -> Result<(), Error> {
+    poll.closed = true;
+    save_and_replicate(transport, &poll, token).await?;
+    if let Err(e) = refresh_poll_message(http, &poll).await {
+        println!("Failed to update message for poll #{}: {e}", poll.poll_id);
+    }
+    let mut message = serenity::CreateMessage::new()
+        .content("🗳️ This poll has closed!")
+        .embed(poll_embed(&poll));
+    if let Some(message_id) = poll.message_id {
+        message = message.reference_message((serenity::ChannelId::new(poll.channel_id), serenity::MessageId::new(message_id)));
+    }
+    serenity::ChannelId::new(poll.channel_id).send_message(http, message).await?;
+    Ok(())
+}
+
+/// Closes polls whose end time has passed. Every instance runs this, but only the
+/// leader acts, so a new leader picks up the polls its predecessor left open.
+pub async fn start_poll_closer(
+    ctx: serenity::Context,
+    transport: Arc<dyn ClusterTransport>,
+    cluster_state: Arc<Mutex<ClusterState>>,
+    poll_locks: Arc<Mutex<PollLocks>>,
+) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(POLL_CHECK_INTERVAL));
+    loop {
+        interval.tick().await;
+        if !cluster_state.lock().await.is_leader {
+            continue;
+        }
+        let now = chrono::Utc::now().timestamp();
+        let due = match crate::storage::get().open_polls(None) {
+            Ok(polls) => polls.into_iter().filter(|poll| poll.ends_at <= now),
+            Err(e) => {
+                println!("Failed to read open polls: {e}");
+                continue;
+            }
+        };
+        for poll in due {
+            let (guild_id, poll_id) = (poll.guild_id, poll.poll_id);
+            let Some((_guard, token)) = lock_poll(&poll_locks, &cluster_state, guild_id, poll_id).await else {
+                break;
+            };
+            // A vote may have landed since the list was read.
+            let poll = match crate::storage::get().poll(guild_id, poll_id) {
+                Ok(Some(poll)) if !poll.closed => poll,
+                Ok(_) => continue,
+                Err(e) => {
+                    println!("Failed to read poll #{poll_id} in guild {guild_id}: {e}");
+                    continue;
+                }
+            };
+            if let Err(e) = close_poll(&ctx.http, transport.as_ref(), poll, token).await {
+                println!("Failed to close poll #{poll_id} in guild {guild_id}: {e}");
+            }
+        }
+    }
+}
+
+/// The ballot a poll component produces for `user_id`, from their current one.
+fn component_ballot(poll: &Poll, user_id: u64, action: &str, argument: Option<usize>, values: &[String]) -> Option<Vec<usize>> {
+    let current = poll.votes.iter().find(|vote| vote.user_id == user_id).map(|vote| vote.choices.clone()).unwrap_or_default();
+    let picked: Vec<usize> = values.iter().filter_map(|value| value.parse().ok()).filter(|i| *i < poll.options.len()).collect();
+    match action {
+        "clear" => Some(Vec::new()),
+        "option" => argument.filter(|i| *i < poll.options.len()).map(|i| vec![i]),
+        "select" => (!picked.is_empty()).then_some(picked),
+        "rank" => {
+            let (rank, choice) = (argument?, *picked.first()?);
+            let mut ranking = current;
+            ranking.retain(|other| *other != choice);
+            if rank < ranking.len() {
+                ranking[rank] = choice;
+            } else {
+                ranking.push(choice);
+            }
+            Some(ranking)
+        }
+        _ => None,
+    }
+}
+
+/// Handles a press or selection on a poll message. Called from the event handler on
+/// the leader, since poll messages outlive any one command invocation.
+pub async fn handle_component(ctx: &serenity::Context, data: &Data, interaction: &serenity::ComponentInteraction) -> Result<(), Error> {
+    let Some(rest) = interaction.data.custom_id.strip_prefix(COMPONENT_PREFIX) else {
+        return Ok(());
+    };
+    let mut parts = rest.split(':');
+    let (Some(poll_id), Some(action)) = (parts.next().and_then(|id| id.parse::<u64>().ok()), parts.next()) else {
+        return Ok(());
+    };
+    let argument = parts.next().and_then(|n| n.parse::<usize>().ok());
+    let Some(guild_id) = interaction.guild_id else {
+        return Ok(());
+    };
+    let Some((guard, token)) = lock_poll(&data.poll_locks, &data.cluster_state, guild_id.into(), poll_id).await else {
+        return Ok(());
+    };
+    let ephemeral = |content: String| serenity::CreateInteractionResponse::Message(
+        serenity::CreateInteractionResponseMessage::new().content(content).ephemeral(true),
+    );
+    let Some(mut poll) = crate::storage::get().poll(guild_id.into(), poll_id)? else {
+        interaction.create_response(ctx, ephemeral("❌ This poll no longer exists.".to_owned())).await?;
+        return Ok(());
+    };
+    if !is_open(&poll) {
+        interaction.create_response(ctx, ephemeral("❌ This poll has closed.".to_owned())).await?;
+        return Ok(());
+    }
+    let values = match &interaction.data.kind {
+        serenity::ComponentInteractionDataKind::StringSelect { values } => values.clone(),
+        _ => Vec::new(),
+    };
+    let user_id = u64::from(interaction.user.id);
+    let Some(choices) = component_ballot(&poll, user_id, action, argument, &values) else {
+        interaction.create_response(ctx, ephemeral("❌ That isn't a valid choice.".to_owned())).await?;
+        return Ok(());
+    };
+    let previous = cast_ballot(&mut poll, user_id, choices.clone());
+    crate::storage::get().save_poll(&poll)?;
+    drop(guard);
+    // Answer before replicating: reaching slow peers could miss the interaction deadline.
+    interaction.create_response(ctx, serenity::CreateInteractionResponse::UpdateMessage(
+        serenity::CreateInteractionResponseMessage::new()
+            .embed(poll_embed(&poll))
+            .components(poll_components(&poll)),
+    )).await?;
+    interaction.create_followup(ctx, serenity::CreateInteractionResponseFollowup::new()
+        .content(ballot_response(&poll, &previous, &choices))
+        .ephemeral(true)
+    ).await?;
+    replicate(data.cluster_transport.as_ref(), &poll, token).await;
+    Ok(())
+}
+
+async fn find_poll(ctx: Context<'_>, poll_id: u64) -> Result<Option<Poll>, Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let poll = crate::storage::get().poll(guild_id.into(), poll_id)?;
+    if poll.is_none() {
+        ctx.say(format!("❌ There is no poll #{}.", poll_id)).await?;
+    }
+    Ok(poll)
+}
+
+async fn autocomplete_open_polls<'a>(
+    ctx: Context<'a>,
+    partial: &'a str,
+) -> Vec<serenity::AutocompleteChoice> {
+    let guild_id = ctx.guild_id().map(u64::from);
+    let polls = crate::storage::get().open_polls(guild_id).unwrap_or_default();
+    polls
+        .into_iter()
+        .filter(|poll| poll.poll_id.to_string().starts_with(partial))
+        .map(|poll| serenity::AutocompleteChoice::new(format!("#{}: {}", poll.poll_id, poll.question), poll.poll_id))
+        .collect()
+}
+
+/// Run polls with fixed options and an end time
+#[poise::command(slash_command, prefix_command, guild_only, subcommands("create", "vote", "results", "close", "list"))]
+pub async fn poll(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Start a poll in this channel
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn create(
+    ctx: Context<'_>,
+    #[description = "What to ask"] question: String,
+    #[description = "Options separated by | (e.g. pizza | tacos | sushi)"] options: String,
+    #[description = "How long the poll runs (e.g. 30m, 2h, 1d)"] duration: String,
+    #[description = "How members vote (default single choice)"] kind: Option<PollKind>,
+    #[description = "Hide who voted for what (default true)"] anonymous: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let options: Vec<String> = options
+        .split('|')
+        .map(|option| option.trim().to_owned())
+        .filter(|option| !option.is_empty())
+        .collect();
+    if options.len() < 2 || options.len() > MAX_POLL_OPTIONS {
+        ctx.say(format!("❌ A poll needs between 2 and {} options, separated by `|`.", MAX_POLL_OPTIONS)).await?;
+        return Ok(());
+    }
+    let mut seen = std::collections::HashSet::new();
+    if !options.iter().all(|option| seen.insert(option.to_lowercase())) {
+        ctx.say("❌ Poll options must all be different.").await?;
+        return Ok(());
+    }
+    let Some(seconds) = parse_duration(&duration).filter(|seconds| *seconds <= MAX_POLL_DURATION) else {
+        ctx.say("❌ Give a duration like `30m`, `2h` or `1d`, up to 30 days.").await?;
+        return Ok(());
+    };
+
+    let mut poll = Poll {
+        guild_id: guild_id.into(),
+        poll_id: 0,
+        channel_id: ctx.channel_id().into(),
+        message_id: None,
+        created_by: ctx.author().id.into(),
+        question,
+        options,
+        kind: kind.unwrap_or_default(),
+        anonymous: anonymous.unwrap_or(true),
+        ends_at: chrono::Utc::now().timestamp() + seconds,
+        closed: false,
+        votes: Vec::new(),
+    };
+    poll.poll_id = crate::storage::get().add_poll(&poll)?;
+    let handle = ctx.send(poise::CreateReply::default()
+        .embed(poll_embed(&poll))
+        .components(poll_components(&poll))
+    ).await?;
+    let message_id = handle.message().await?.id.into();
+    // Members can vote as soon as the message is up, so reload before recording where it is.
+    let data = ctx.data();
+    let Some((_guard, token)) = lock_poll(&data.poll_locks, &data.cluster_state, poll.guild_id, poll.poll_id).await else {
+        return Ok(());
+    };
+    let Some(mut poll) = crate::storage::get().poll(poll.guild_id, poll.poll_id)? else {
+        return Ok(());
+    };
+    poll.message_id = Some(message_id);
+    save_and_replicate(data.cluster_transport.as_ref(), &poll, token).await
+}
+
+/// Vote in a poll, or change your vote
+#[poise::command(slash_command, prefix_command, guild_only, ephemeral)]
+pub async fn vote(
+    ctx: Context<'_>,
+    #[description = "Poll number"]
+    #[autocomplete = "autocomplete_open_polls"]
+    poll_id: u64,
+    #[description = "Option numbers or text, separated by commas (in order for ranked polls)"]
+    #[rest]
+    choices: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let data = ctx.data();
+    let Some((guard, token)) = lock_poll(&data.poll_locks, &data.cluster_state, guild_id.into(), poll_id).await else {
+        return Ok(());
+    };
+    let Some(mut poll) = find_poll(ctx, poll_id).await? else {
+        return Ok(());
+    };
+    if !is_open(&poll) {
+        ctx.say(format!("❌ Poll #{} has closed.", poll_id)).await?;
+        return Ok(());
+    }
+    let mut picked = Vec::new();
+    for choice in choices.split(',').map(str::trim).filter(|choice| !choice.is_empty()) {
+        let option = choice
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| number.checked_sub(1))
+            .filter(|index| *index < poll.options.len())
+            .or_else(|| poll.options.iter().position(|option| option.eq_ignore_ascii_case(choice)));
+        let Some(option) = option else {
+            ctx.say(format!("❌ `{}` is not an option in poll #{}.", choice, poll_id)).await?;
+            return Ok(());
+        };
+        if !picked.contains(&option) {
+            picked.push(option);
+        }
+    }
+    if picked.is_empty() || (poll.kind == PollKind::Single && picked.len() > 1) {
+        ctx.say(format!("❌ Pick {} option in poll #{}.", if poll.kind == PollKind::Single { "exactly one" } else { "at least one" }, poll_id)).await?;
+        return Ok(());
+    }
+
+    let previous = cast_ballot(&mut poll, ctx.author().id.into(), picked.clone());
+    if previous == picked {
+        ctx.say(format!("You already voted for {}.", describe_choices(&poll, &picked))).await?;
+        return Ok(());
+    }
+    crate::storage::get().save_poll(&poll)?;
+    drop(guard);
+    ctx.say(ballot_response(&poll, &previous, &picked)).await?;
+    if let Err(e) = refresh_poll_message(ctx.http(), &poll).await {
+        println!("Failed to update message for poll #{}: {e}", poll.poll_id);
+    }
+    replicate(data.cluster_transport.as_ref(), &poll, token).await;
+    Ok(())
+}
+
+/// Show the current tally of a poll
+#[poise::command(slash_command, prefix_command, guild_only, custom_data = "crate::cluster::InstancePolicy::AnyInstance")]
+pub async fn results(
+    ctx: Context<'_>,
+    #[description = "Poll number"] poll_id: u64,
+) -> Result<(), Error> {
+    if let Some(poll) = find_poll(ctx, poll_id).await? {
+        ctx.send(poise::CreateReply::default().embed(poll_embed(&poll))).await?;
+    }
+    Ok(())
+}
+
+/// End a poll early (its creator or an admin)
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn close(
+    ctx: Context<'_>,
+    #[description = "Poll number"]
+    #[autocomplete = "autocomplete_open_polls"]
+    poll_id: u64,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    // Replicating the close can take a while with unreachable peers.
+    ctx.defer().await?;
+    let data = ctx.data();
+    let Some((_guard, token)) = lock_poll(&data.poll_locks, &data.cluster_state, guild_id.into(), poll_id).await else {
+        return Ok(());
+    };
+    let Some(poll) = find_poll(ctx, poll_id).await? else {
+        return Ok(());
+    };
+    if poll.closed {
+        ctx.say(format!("❌ Poll #{} has already closed.", poll_id)).await?;
+        return Ok(());
+    }
+    let (_, is_staff) = crate::stats::member_standing(ctx.serenity_context(), guild_id, ctx.author().id).await?;
+    if poll.created_by != u64::from(ctx.author().id) && !is_staff {
+        ctx.say("❌ Only the poll's creator or an admin can close it.").await?;
+        return Ok(());
+    }
+    close_poll(ctx.http(), data.cluster_transport.as_ref(), poll, token).await?;
+    ctx.say(format!("✅ Closed poll #{}.", poll_id)).await?;
+    Ok(())
+}
+
+/// List the polls still open on this server
+#[poise::command(slash_command, prefix_command, guild_only, custom_data = "crate::cluster::InstancePolicy::AnyInstance")]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let polls = crate::storage::get().open_polls(Some(guild_id.into()))?;
+    if polls.is_empty() {
+        ctx.say("There are no open polls. Start one with `/poll create`.").await?;
+        return Ok(());
+    }
+    let mut response = String::new();
+    for poll in polls {
+        response.push_str(&format!(
+            "**#{}** {} — {} ballots, ends <t:{}:R> in <#{}>\n",
+            poll.poll_id, poll.question, poll.votes.len(), poll.ends_at, poll.channel_id
+        ));
+    }
+    let embed = serenity::CreateEmbed::new()
+        .title("📊 Open polls")
+        .description(response)
+        .color(serenity::Colour::BLUE);
+    ctx.send(poise::CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranked_poll(options: usize, ballots: &[&[usize]]) -> Poll {
+        Poll {
+            guild_id: 1,
+            poll_id: 1,
+            channel_id: 1,
+            message_id: None,
+            created_by: 1,
+            question: "?".to_owned(),
+            options: (0..options).map(|i| format!("option {i}")).collect(),
+            kind: PollKind::Ranked,
+            anonymous: false,
+            ends_at: 0,
+            closed: true,
+            votes: ballots.iter().enumerate().map(|(user_id, choices)| PollVote {
+                user_id: user_id as u64,
+                choices: choices.to_vec(),
+            }).collect(),
+        }
+    }
+
+    #[test]
+    fn majority_wins_in_the_first_round() {
+        let (rounds, winner) = instant_runoff(&ranked_poll(3, &[&[0, 1], &[0], &[1, 0]]));
+        assert_eq!(rounds, vec![vec![Some(2), Some(1), Some(0)]]);
+        assert_eq!(winner, Some(0));
+    }
+
+    #[test]
+    fn transfers_votes_from_eliminated_options() {
+        let (rounds, winner) = instant_runoff(&ranked_poll(3, &[&[0], &[0], &[1], &[1], &[2, 1]]));
+        assert_eq!(rounds, vec![
+            vec![Some(2), Some(2), Some(1)],
+            vec![Some(2), Some(3), None],
+        ]);
+        assert_eq!(winner, Some(1));
+    }
+
+    #[test]
+    fn exhausted_ballots_leave_the_count() {
+        // The last ballot has nobody left once option 2 goes, so 3 of the 5 remaining is a majority.
+        let (rounds, winner) = instant_runoff(&ranked_poll(3, &[&[0], &[0], &[0], &[1], &[1], &[2]]));
+        assert_eq!(rounds.last(), Some(&vec![Some(3), Some(2), None]));
+        assert_eq!(winner, Some(0));
+    }
+
+    #[test]
+    fn ties_and_empty_polls_have_no_winner() {
+        assert_eq!(instant_runoff(&ranked_poll(2, &[&[0], &[1]])).1, None);
+        assert_eq!(instant_runoff(&ranked_poll(2, &[])), (vec![vec![Some(0), Some(0)]], None));
+        // Choices that no longer exist are skipped rather than counted.
+        assert_eq!(instant_runoff(&ranked_poll(2, &[&[5, 1]])).1, Some(1));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{Context, Error};
+use crate::dice::Sides;
+use crate::storage::{DiceMacro, DiceRoll};
+use poise::serenity_prelude as serenity;
+
+/// Long breakdowns (e.g. 100 exploding dice) are cut to stay under the message limit.
+const MAX_BREAKDOWN_LENGTH: usize = 1800;
+const MAX_MACROS: usize = 25;
+const MAX_MACRO_NAME_LENGTH: usize = 32;
+const ROLL_HISTORY_SHOWN: usize = 10;
+/// Macro names that would be read as a subcommand by `~roll <name>`.
+const RESERVED_NAMES: [&str; 5] = ["save", "use", "macros", "delete", "history"];
+
+/// Rolls an expression, records it in the member's roll history and replies with
+/// the breakdown, or with where the expression went wrong.
+pub async fn roll_and_reply(ctx: Context<'_>, expression: &str, macro_name: Option<&str>) -> Result<(), Error> {
+    let roll = match crate::dice::roll(expression) {
+        Ok(roll) => roll,
+        Err(e) => {
+            ctx.say(format!("❌ {}\n```\n{}\n```", e.message, e.pointer(expression))).await?;
+            return Ok(());
+        }
+    };
+    let kept_d20s = roll.dice.iter()
+        .filter(|(sides, die)| *sides == Sides::Number(20) && !die.dropped)
+        .map(|(_, die)| die.value);
+    let (crits, fumbles) = kept_d20s.fold((0, 0), |(crits, fumbles), value| match value {
+        20 => (crits + 1, fumbles),
+        1 => (crits, fumbles + 1),
+        _ => (crits, fumbles),
+    });
+    if let Some(guild_id) = ctx.guild_id() {
+        let record = DiceRoll {
+            guild_id: guild_id.into(),
+            user_id: ctx.author().id.into(),
+            expression: expression.to_owned(),
+            total: roll.total,
+            crits,
+            fumbles,
+            rolled_at: chrono::Utc::now().timestamp(),
+        };
+        if let Err(e) = crate::storage::get().add_dice_roll(&record) {
+            println!("Failed to record dice roll for {}: {e}", ctx.author().id);
+        }
+    }
+
+    let mut breakdown = roll.breakdown;
+    if breakdown.chars().count() > MAX_BREAKDOWN_LENGTH {
+        breakdown = breakdown.chars().take(MAX_BREAKDOWN_LENGTH).collect::<String>() + "…";
+    }
+    let mut response = match macro_name {
+        Some(name) => format!("🎲 **{}**: You rolled **{}** ({})", name, roll.total, breakdown),
+        None => format!("🎲 You rolled **{}** ({})", roll.total, breakdown),
+    };
+    if crits > 0 {
+        response.push_str("\n💥 Natural 20!");
+    }
+    if fumbles > 0 {
+        response.push_str("\n💀 Natural 1!");
+    }
+    ctx.say(response).await?;
+    Ok(())
+}
+
+async fn autocomplete_macros<'a>(
+    ctx: Context<'a>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    let macros = match ctx.guild_id() {
+        Some(guild_id) => crate::storage::get().dice_macros(guild_id.into(), ctx.author().id.into()).unwrap_or_default(),
+        None => Vec::new(),
+    };
+    macros.into_iter().map(|m| m.name).filter(move |name| name.starts_with(&partial.to_lowercase()))
+}
+
+async fn find_macro(ctx: Context<'_>, name: &str) -> Result<Option<DiceMacro>, Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let macros = crate::storage::get().dice_macros(guild_id.into(), ctx.author().id.into())?;
+    Ok(macros.into_iter().find(|m| m.name == name.to_lowercase()))
+}
+
+/// Roll dice or one of your saved macros; `~roll <expression or macro>` also works
+#[poise::command(slash_command, prefix_command, guild_only, category = "Fun", subcommands("save", "use_macro", "macros", "delete", "history"))]
+pub async fn roll(
+    ctx: Context<'_>,
+    #[description = "Dice expression or macro name"]
+    #[rest]
+    expression: Option<String>,
+) -> Result<(), Error> {
+    let expression = expression.as_deref().map(str::trim).filter(|s| !s.is_empty()).unwrap_or("1d6");
+    match find_macro(ctx, expression).await? {
+        Some(saved) => roll_and_reply(ctx, &saved.expression, Some(&saved.name)).await,
+        None => roll_and_reply(ctx, expression, None).await,
+    }
+}
+
+/// Save a dice expression under a name
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn save(
+    ctx: Context<'_>,
+    #[description = "Macro name (e.g. attack)"] name: String,
+    #[description = "Dice expression (e.g. 1d20+5)"]
+    #[rest]
+    expression: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let name = name.to_lowercase();
+    let expression = expression.trim();
+    let valid_name = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if name.is_empty() || name.len() > MAX_MACRO_NAME_LENGTH || !valid_name {
+        ctx.say(format!("❌ Macro names are up to {} letters, digits, `-` or `_`.", MAX_MACRO_NAME_LENGTH)).await?;
+        return Ok(());
+    }
+    if RESERVED_NAMES.contains(&name.as_str()) {
+        ctx.say(format!("❌ `{}` is a `roll` subcommand, so it can't be a macro name.", name)).await?;
+        return Ok(());
+    }
+    if let Err(e) = crate::dice::parse(expression) {
+        ctx.say(format!("❌ {}\n```\n{}\n```", e.message, e.pointer(expression))).await?;
+        return Ok(());
+    }
+    let storage = crate::storage::get();
+    let macros = storage.dice_macros(guild_id.into(), ctx.author().id.into())?;
+    let replacing = macros.iter().find(|m| m.name == name);
+    if replacing.is_none() && macros.len() >= MAX_MACROS {
+        ctx.say(format!("❌ You already have {} macros. Delete one with `/roll delete` first.", MAX_MACROS)).await?;
+        return Ok(());
+    }
+    let response = match replacing {
+        Some(old) => format!("💾 Updated **{}**: `{}` → `{}`", name, old.expression, expression),
+        None => format!("💾 Saved **{}** as `{}`. Roll it with `/roll use {}`.", name, expression, name),
+    };
+    storage.save_dice_macro(&DiceMacro {
+        guild_id: guild_id.into(),
+        user_id: ctx.author().id.into(),
+        name,
+        expression: expression.to_owned(),
+    })?;
+    ctx.say(response).await?;
+    Ok(())
+}
+
+/// Roll one of your saved macros
+#[poise::command(slash_command, prefix_command, guild_only, rename = "use")]
+pub async fn use_macro(
+    ctx: Context<'_>,
+    #[description = "Macro name"]
+    #[autocomplete = "autocomplete_macros"]
+    name: String,
+) -> Result<(), Error> {
+    match find_macro(ctx, &name).await? {
+        Some(saved) => roll_and_reply(ctx, &saved.expression, Some(&saved.name)).await,
+        None => {
+            ctx.say(format!("❌ You have no macro called `{}`. See yours with `/roll macros`.", name)).await?;
+            Ok(())
+        }
+    }
+}
+
+/// List your saved macros
+#[poise::command(slash_command, prefix_command, guild_only, ephemeral)]
+pub async fn macros(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let macros = crate::storage::get().dice_macros(guild_id.into(), ctx.author().id.into())?;
+    if macros.is_empty() {
+        ctx.say("You have no saved macros. Save one with `/roll save <name> <expression>`.").await?;
+        return Ok(());
+    }
+    let list: String = macros.iter().map(|m| format!("**{}**: `{}`\n", m.name, m.expression)).collect();
+    let embed = serenity::CreateEmbed::new()
+        .title(format!("🎲 {}'s macros", ctx.author().name))
+        .description(list)
+        .color(serenity::Colour::BLUE);
+    ctx.send(poise::CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Delete one of your saved macros
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn delete(
+    ctx: Context<'_>,
+    #[description = "Macro name"]
+    #[autocomplete = "autocomplete_macros"]
+    name: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let name = name.to_lowercase();
+    if crate::storage::get().delete_dice_macro(guild_id.into(), ctx.author().id.into(), &name)? {
+        ctx.say(format!("🗑️ Deleted **{}**.", name)).await?;
+    } else {
+        ctx.say(format!("❌ You have no macro called `{}`.", name)).await?;
+    }
+    Ok(())
+}
+
+/// Show recent rolls and roll statistics on this server
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "Member whose rolls to show (defaults to you)"] user: Option<serenity::User>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    let user = user.as_ref().unwrap_or_else(|| ctx.author());
+    let rolls = crate::storage::get().dice_rolls(guild_id.into(), user.id.into(), crate::storage::DICE_ROLLS_KEPT)?;
+    if rolls.is_empty() {
+        ctx.say(format!("{} hasn't rolled any dice here yet.", user.name)).await?;
+        return Ok(());
+    }
+    let recent: String = rolls.iter()
+        .take(ROLL_HISTORY_SHOWN)
+        .map(|roll| format!("`{}` → **{}** <t:{}:R>\n", roll.expression, roll.total, roll.rolled_at))
+        .collect();
+    let average = rolls.iter().map(|roll| roll.total as f64).sum::<f64>() / rolls.len() as f64;
+    let crits: u32 = rolls.iter().map(|roll| roll.crits).sum();
+    let fumbles: u32 = rolls.iter().map(|roll| roll.fumbles).sum();
+    let embed = serenity::CreateEmbed::new()
+        .title(format!("🎲 {}'s recent rolls", user.name))
+        .description(recent)
+        .field("Rolls", rolls.len().to_string(), true)
+        .field("Average total", format!("{:.1}", average), true)
+        .field("Natural 20s / 1s", format!("{} / {}", crits, fumbles), true)
+        .footer(serenity::CreateEmbedFooter::new(format!("Statistics cover the last {} rolls", crate::storage::DICE_ROLLS_KEPT)))
+        .color(serenity::Colour::BLUE);
+    ctx.send(poise::CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
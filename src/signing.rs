@@ -0,0 +1,175 @@
+use crate::Error;
+use crate::cluster::ClusterMessage;
+use ring::hmac;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Messages older (or further in the future) than this are dropped as stale. Also
+/// bounds how long nonces are remembered.
+const MAX_MESSAGE_AGE: u64 = 120;
+
+/// What actually goes over the wire. `payload` is the JSON of a `ClusterMessage`,
+/// kept as a string so the signature covers exactly the bytes that were sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    pub sender: String,
+    pub nonce: u64,
+    pub timestamp: u64,
+    pub payload: String,
+    pub signature: String,
+}
+
+pub struct Signer {
+    instance_id: String,
+    key: hmac::Key,
+    seen_nonces: Mutex<HashMap<(String, u64), u64>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn signing_input(sender: &str, nonce: u64, timestamp: u64, payload: &str) -> Vec<u8> {
+    format!("{sender}\n{nonce}\n{timestamp}\n{payload}").into_bytes()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+impl Signer {
+    pub fn new(instance_id: String, secret: &str) -> Self {
+        Signer {
+            instance_id,
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            seen_nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn seal(&self, message: &ClusterMessage) -> Result<Envelope, Error> {
+        let payload = serde_json::to_string(message)?;
+        let nonce = rand::random::<u64>();
+        let timestamp = now();
+        let tag = hmac::sign(&self.key, &signing_input(&self.instance_id, nonce, timestamp, &payload));
+        Ok(Envelope {
+            sender: self.instance_id.clone(),
+            nonce,
+            timestamp,
+            payload,
+            signature: to_hex(tag.as_ref()),
+        })
+    }
+
+    /// Verifies the signature, freshness and nonce of an envelope and returns the
+    /// message inside. The error explains why the envelope was dropped.
+    pub fn open(&self, envelope: Envelope) -> Result<ClusterMessage, String> {
+        let signature = from_hex(&envelope.signature)
+            .ok_or_else(|| format!("malformed signature from {}", envelope.sender))?;
+        let input = signing_input(&envelope.sender, envelope.nonce, envelope.timestamp, &envelope.payload);
+        hmac::verify(&self.key, &input, &signature)
+            .map_err(|_| format!("bad signature claiming to be from {}", envelope.sender))?;
+        let now = now();
+        if now.abs_diff(envelope.timestamp) > MAX_MESSAGE_AGE {
+            return Err(format!("stale message from {} ({}s old)", envelope.sender, now as i64 - envelope.timestamp as i64));
+        }
+        {
+            let mut seen_nonces = self.seen_nonces.lock().unwrap();
+            seen_nonces.retain(|_, timestamp| now.abs_diff(*timestamp) <= MAX_MESSAGE_AGE);
+            if seen_nonces.insert((envelope.sender.clone(), envelope.nonce), envelope.timestamp).is_some() {
+                return Err(format!("replayed message from {} (nonce {})", envelope.sender, envelope.nonce));
+            }
+        }
+        let message: ClusterMessage = serde_json::from_str(&envelope.payload)
+            .map_err(|e| format!("unreadable payload from {}: {e}", envelope.sender))?;
+        if let Some(claimed) = message.sender() {
+            if claimed != envelope.sender {
+                return Err(format!("{} sent a message on behalf of {claimed}", envelope.sender));
+            }
+        }
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer(instance_id: &str) -> Signer {
+        Signer::new(instance_id.to_owned(), "shared secret")
+    }
+
+    /// Re-signs an envelope after changing it, as a sender with the right key could.
+    fn resign(signer: &Signer, mut envelope: Envelope, timestamp: u64) -> Envelope {
+        envelope.timestamp = timestamp;
+        let input = signing_input(&envelope.sender, envelope.nonce, timestamp, &envelope.payload);
+        envelope.signature = to_hex(hmac::sign(&signer.key, &input).as_ref());
+        envelope
+    }
+
+    #[test]
+    fn opens_what_it_seals() {
+        let envelope = signer("a").seal(&ClusterMessage::ConfigRequest).unwrap();
+        assert!(matches!(signer("b").open(envelope), Ok(ClusterMessage::ConfigRequest)));
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let a = signer("a");
+        let mut envelope = a.seal(&ClusterMessage::LeaderAnnouncement { instance_id: "a".to_owned(), term: 1 }).unwrap();
+        envelope.payload = envelope.payload.replace("1", "9");
+        assert!(signer("b").open(envelope).unwrap_err().starts_with("bad signature"));
+
+        let mut envelope = a.seal(&ClusterMessage::ConfigRequest).unwrap();
+        envelope.sender = "c".to_owned();
+        assert!(signer("b").open(envelope).unwrap_err().starts_with("bad signature"));
+
+        let mut envelope = a.seal(&ClusterMessage::ConfigRequest).unwrap();
+        envelope.signature = "not hex".to_owned();
+        assert!(signer("b").open(envelope).unwrap_err().starts_with("malformed signature"));
+
+        let envelope = Signer::new("a".to_owned(), "other secret").seal(&ClusterMessage::ConfigRequest).unwrap();
+        assert!(signer("b").open(envelope).unwrap_err().starts_with("bad signature"));
+    }
+
+    #[test]
+    fn rejects_stale_messages() {
+        let a = signer("a");
+        let b = signer("b");
+        let envelope = a.seal(&ClusterMessage::ConfigRequest).unwrap();
+        let old = resign(&a, envelope.clone(), now() - MAX_MESSAGE_AGE - 5);
+        assert!(b.open(old).unwrap_err().starts_with("stale message"));
+        let future = resign(&a, envelope.clone(), now() + MAX_MESSAGE_AGE + 5);
+        assert!(b.open(future).unwrap_err().starts_with("stale message"));
+        let recent = resign(&a, envelope, now() - 5);
+        assert!(b.open(recent).is_ok());
+    }
+
+    #[test]
+    fn rejects_replayed_nonces() {
+        let b = signer("b");
+        let envelope = signer("a").seal(&ClusterMessage::ConfigRequest).unwrap();
+        assert!(b.open(envelope.clone()).is_ok());
+        assert!(b.open(envelope).unwrap_err().starts_with("replayed message"));
+    }
+
+    #[test]
+    fn rejects_messages_on_behalf_of_another_instance() {
+        let envelope = signer("a").seal(&ClusterMessage::LeaderAnnouncement { instance_id: "c".to_owned(), term: 1 }).unwrap();
+        assert_eq!(signer("b").open(envelope).unwrap_err(), "a sent a message on behalf of c");
+    }
+}
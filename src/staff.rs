@@ -25,65 +25,75 @@ pub async fn quit(
     instance_id: Option<String>,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    let should_shutdown = match &instance_id {
-        Some(target_instance_id) => {
-            cluster_state.my_instance_id == *target_instance_id
-        }
-        None => true,
+    let request_id = rand::random::<u64>();
+    let (my_instance_id, mut expected) = {
+        let mut cluster_state = data.cluster_state.lock().await;
+        cluster_state.shutdown_acks.insert(request_id, Vec::new());
+        let expected: Vec<String> = cluster_state.instances.keys()
+            .filter(|id| **id != cluster_state.my_instance_id)
+            .filter(|id| instance_id.as_ref().is_none_or(|target| target == *id))
+            .cloned()
+            .collect();
+        (cluster_state.my_instance_id.clone(), expected)
     };
-    if !should_shutdown {
-        let response = format!(
-            "Not shutting down - this instance is '{}'",
-            cluster_state.my_instance_id
-        );
-        ctx.say(response).await?;
-        return Ok(());
+    let shut_down_self = instance_id.as_ref().is_none_or(|target| *target == my_instance_id);
+    if instance_id.as_ref() != Some(&my_instance_id) {
+        data.cluster_transport.send(&crate::cluster::ClusterMessage::Shutdown {
+            request_id,
+            target: instance_id.clone(),
+            requested_by: my_instance_id.clone(),
+        }).await?;
+        tokio::time::sleep(std::time::Duration::from_secs(crate::cluster::SHUTDOWN_ACK_TIMEOUT)).await;
     }
-    let guilds = ctx.cache().guilds();
-    for guild_id in guilds {
-        if let Some(log_channel) = get_logging_channel(guild_id.into(), LogEventType::BootQuit).await {
-            let something = format!("{} is being shut down!", cluster_state.my_instance_id);
-            let embed = serenity::CreateEmbed::new()
-                .title("Instance Shutting Down")
-                .description(something)
-                .color(serenity::Colour::DARK_RED);
-            if let Err(e) = log_channel.send_message(
-                ctx.http(),
-                serenity::CreateMessage::new().embed(embed)
-            ).await {
-                println!("Failed to send shutdown announcement to guild {guild_id}: {e}");
-            }
+    let acks = data.cluster_state.lock().await.shutdown_acks.remove(&request_id).unwrap_or_default();
+    let mut report = Vec::new();
+    for (ack_instance_id, error) in acks {
+        expected.retain(|id| *id != ack_instance_id);
+        match error {
+            None => report.push(format!("✅ {ack_instance_id} saved config and is shutting down")),
+            Some(e) => report.push(format!("❌ {ack_instance_id} failed to save config: {e}. Not shutting down")),
         }
     }
-    match save_config_to_disk().await {
-        Ok(_) => {
-            let message = if instance_id.is_some() {
-                format!("Config saved successfully. Shutting down instance '{}'!",
-                cluster_state.my_instance_id)
-            } else {
-                "Config saved successfully. Shutting down all instances!".to_string()
-            };
-            if ctx.invocation_string().starts_with('/') {
-                ctx.send(poise::CreateReply::default().content(message).ephemeral(true)).await?;
-            } else {
-                ctx.say(message).await?;
+    for missing in expected {
+        report.push(format!("⚠️ {missing} did not respond"));
+    }
+    if shut_down_self {
+        match save_config_to_disk().await {
+            Ok(_) => report.push(format!("✅ {my_instance_id} saved config and is shutting down")),
+            Err(e) => {
+                report.push(format!("❌ {my_instance_id} failed to save config: {e}. Not shutting down"));
+                ctx.say(report.join("\n")).await?;
+                return Ok(());
             }
-            ctx.framework().shard_manager().shutdown_all().await;
-        }
-        Err(e) => {
-            ctx.say(format!("Failed to save config: {e}. Not shutting down!")).await?;
         }
     }
+    if report.is_empty() {
+        report.push(format!("No instance named '{}' responded", instance_id.unwrap_or_default()));
+    }
+    ctx.say(report.join("\n")).await?;
+    if shut_down_self {
+        crate::cluster::finish_shutdown(
+            ctx.serenity_context(),
+            data.cluster_transport.as_ref(),
+            &data.cluster_state,
+            &ctx.framework().shard_manager(),
+        ).await;
+    }
     Ok(())
 }
 
 #[poise::command(
     prefix_command,
     owners_only,
-    hide_in_help
+    hide_in_help,
+    custom_data = "crate::cluster::InstancePolicy::SpecificInstance"
 )]
-pub async fn writeconfig(ctx: Context<'_>) -> Result<(), Error> {
+pub async fn writeconfig(
+    ctx: Context<'_>,
+    #[description = "Specific instance ID to write config on (leave empty for all)"]
+    #[rename = "instance_id"]
+    _instance_id: Option<String>,
+) -> Result<(), Error> {
     match save_config_to_disk().await {
         Ok(_) => ctx.say("Successfully wrote config to disk!").await?,
         Err(e) => ctx.say(format!("Failed to write config: {e}")).await?,
@@ -91,6 +101,117 @@ pub async fn writeconfig(ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
+#[poise::command(
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    category = "Cluster",
+    subcommands("status", "stepdown")
+)]
+pub async fn cluster(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say("Use `/cluster status` or `/cluster stepdown`.").await?;
+    Ok(())
+}
+
+fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60);
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else if minutes > 0 {
+        format!("{minutes}m {}s", seconds % 60)
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+/// Show every known instance, the current leader and the election term
+#[poise::command(
+    prefix_command,
+    slash_command,
+    required_permissions = "ADMINISTRATOR",
+    custom_data = "crate::cluster::InstancePolicy::AnyInstance"
+)]
+pub async fn status(ctx: Context<'_>) -> Result<(), Error> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let (table, leader, term) = {
+        let state = ctx.data().cluster_state.lock().await;
+        let mut instances: Vec<_> = state.instances.values().collect();
+        instances.sort_by(|a, b| a.priority.cmp(&b.priority).then_with(|| a.instance_id.cmp(&b.instance_id)));
+        let mut table = format!(
+            "{:<16} {:>4} {:^6} {:>9} {:>11} {:>8} {:>6}\n",
+            "ID", "PRIO", "LEADER", "HEARTBEAT", "UPTIME", "VERSION", "GUILDS"
+        );
+        for info in instances {
+            let is_leader = state.current_leader.as_deref() == Some(info.instance_id.as_str());
+            let uptime = if info.started_at == 0 {
+                "?".to_owned()
+            } else {
+                format_duration(now.saturating_sub(info.started_at))
+            };
+            table.push_str(&format!(
+                "{:<16} {:>4} {:^6} {:>9} {:>11} {:>8} {:>6}\n",
+                info.instance_id,
+                info.priority,
+                if is_leader { "*" } else if info.yielded { "-" } else { "" },
+                format!("{} ago", format_duration(now.saturating_sub(info.last_seen))),
+                uptime,
+                if info.version.is_empty() { "?" } else { &info.version },
+                info.guild_count,
+            ));
+        }
+        (table, state.current_leader.clone(), state.term)
+    };
+    let embed = serenity::CreateEmbed::new()
+        .title("Cluster Status")
+        .description(format!("```\n{table}```"))
+        .field("Leader", leader.unwrap_or_else(|| "None".to_owned()), true)
+        .field("Election Term", term.to_string(), true)
+        .footer(serenity::CreateEmbedFooter::new("* leader, - stepped down"))
+        .color(serenity::Colour::BLURPLE);
+    ctx.send(poise::CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Make the current leader hand over to the next-best instance
+#[poise::command(prefix_command, slash_command, required_permissions = "ADMINISTRATOR")]
+pub async fn stepdown(ctx: Context<'_>) -> Result<(), Error> {
+    let (instance_id, handoff) = {
+        let mut state = ctx.data().cluster_state.lock().await;
+        (state.my_instance_id.clone(), state.step_down())
+    };
+    let Some((successor, term)) = handoff else {
+        ctx.say("No other live instance to hand leadership to.").await?;
+        return Ok(());
+    };
+    ctx.data().cluster_transport.send(&crate::cluster::ClusterMessage::StepDown {
+        instance_id: instance_id.clone(),
+        successor: successor.clone(),
+        term,
+    }).await?;
+    ctx.say(format!("Instance {instance_id} stepped down. {successor} now leads term {term}.")).await?;
+    Ok(())
+}
+
+fn record_case(ctx: Context<'_>, guild_id: serenity::GuildId, action: &str, target: &serenity::User, reason: &str) -> Option<u64> {
+    let case = crate::storage::ModerationCase {
+        guild_id: guild_id.into(),
+        case_id: 0,
+        action: action.to_owned(),
+        target_id: target.id.into(),
+        moderator_id: ctx.author().id.into(),
+        reason: reason.to_owned(),
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    crate::storage::get().add_case(&case)
+        .map_err(|e| println!("Failed to record {action} of {}: {e}", target.id))
+        .ok()
+}
+
 #[poise::command(
     prefix_command,
     slash_command,
@@ -107,18 +228,15 @@ pub async fn ban(
     #[max = 7]
     delete_message_days: Option<u8>,
 ) -> Result<(), Error> {
-    let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     let reason = reason.unwrap_or_else(|| "No reason provided".to_owned());
     let delete_message_days = delete_message_days.unwrap_or(0);
+    let token = crate::cluster::confirm_leader(ctx).await?;
     guild_id.ban_with_reason(&ctx.http(), user.id, delete_message_days, &reason).await?;
+    let case_id = record_case(ctx, guild_id, "ban", &user, &reason);
     let response = format!(
-        "🔨 Banned {} ({}) | Reason: {}",
-        user.name, user.id, reason
+        "🔨 Banned {} ({}) | Reason: {}{}",
+        user.name, user.id, reason, case_id.map(|id| format!(" | Case #{id}")).unwrap_or_default()
     );
     ctx.say(&response).await?;
     if let Some(log_channel) = get_logging_channel(guild_id.into(), LogEventType::Moderation).await {
@@ -127,6 +245,7 @@ pub async fn ban(
             .description(&response)
             .field("Moderator", ctx.author().mention().to_string(), true)
             .field("Message Delete Days", delete_message_days.to_string(), true)
+            .footer(serenity::CreateEmbedFooter::new(format!("Fencing token {token}")))
             .color(serenity::Colour::DARK_RED);
         log_channel.send_message(
             &ctx.http(),
@@ -148,17 +267,14 @@ pub async fn kick(
     #[description = "User to kick"] user: serenity::User,
     #[description = "Reason for kicking"] reason: Option<String>,
 ) -> Result<(), Error> {
-    let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     let reason = reason.unwrap_or_else(|| "No reason provided".to_owned());
+    let token = crate::cluster::confirm_leader(ctx).await?;
     guild_id.kick_with_reason(&ctx.http(), user.id, &reason).await?;
+    let case_id = record_case(ctx, guild_id, "kick", &user, &reason);
     let response = format!(
-        "🔨 Kicked {} ({}) | Reason: {}",
-        user.name, user.id, reason
+        "🔨 Kicked {} ({}) | Reason: {}{}",
+        user.name, user.id, reason, case_id.map(|id| format!(" | Case #{id}")).unwrap_or_default()
     );
     ctx.say(&response).await?;
     if let Some(log_channel) = get_logging_channel(guild_id.into(), LogEventType::Moderation).await {
@@ -166,6 +282,7 @@ pub async fn kick(
             .title("Member Kicked")
             .description(&response)
             .field("Moderator", ctx.author().mention().to_string(), true)
+            .footer(serenity::CreateEmbedFooter::new(format!("Fencing token {token}")))
             .color(serenity::Colour::DARK_RED);
         log_channel.send_message(
             &ctx.http(),
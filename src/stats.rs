@@ -1,8 +1,16 @@
-use crate::{Context, Error};
-use std::fs;
-use std::path::Path;
-use toml::Value;
-use poise::serenity_prelude as serenity;
+use crate::{Context, Data, Error};
+use crate::storage::StatEvent;
+use crate::utils::{StatAwarders, StatDefinition};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use serde::{Deserialize, Serialize};
+
+const LEADERBOARD_PAGE_SIZE: usize = 10;
+const LEADERBOARD_TIMEOUT: u64 = 600;
+const HISTORY_LENGTH: usize = 15;
+const IMPORT_CONFIRM_TIMEOUT: u64 = 120;
+
+/// Last change per (guild, member, stat), for stats with a cooldown.
+pub type StatCooldowns = std::collections::HashMap<(u64, u64, String), std::time::Instant>;
 
 async fn autocomplete_stats<'a>(
     ctx: Context<'a>,
@@ -13,39 +21,86 @@ async fn autocomplete_stats<'a>(
     stats.into_iter().filter(move |s| s.starts_with(&partial.to_lowercase()))
 }
 
-pub async fn modify_user_stat(guild_id: u64, user_id: u64, stat_name: &str, amount: i64) -> Result<i64, Error> {
-    std::fs::create_dir_all("./user_stats")?;
-    let filename = format!("./user_stats/stats_{}_{}.toml", guild_id, user_id);
-    let path = Path::new(&filename);
-    let mut doc = if path.exists() {
-        fs::read_to_string(path)?.parse::<Value>().unwrap_or(Value::Table(toml::value::Table::new()))
-    } else {
-        Value::Table(toml::value::Table::new())
-    };
-    let table = doc.as_table_mut().unwrap();
-    let current = table.get(stat_name).and_then(|v| v.as_integer()).unwrap_or(0);
-    let new_val = current + amount;
-    table.insert(stat_name.to_string(), Value::Integer(new_val));
-    fs::write(path, toml::to_string_pretty(&doc)?)?;
-    Ok(new_val)
+/// `source` names what made the change (e.g. `/stat add` or `~solves`) and is kept in the stat history.
+pub async fn modify_user_stat(
+    ctx: &serenity::Context,
+    guild_id: u64,
+    user_id: u64,
+    stat_name: &str,
+    amount: i64,
+    source: &str,
+) -> Result<i64, Error> {
+    apply_stat_change(ctx, StatEvent {
+        id: 0,
+        guild_id,
+        user_id,
+        stat: stat_name.to_owned(),
+        delta: amount,
+        timestamp: chrono::Utc::now().timestamp(),
+        source: source.to_owned(),
+        awarded_by: None,
+        reason: None,
+    }).await
 }
 
-pub async fn get_user_stat(guild_id: u64, user_id: u64, stat_name: &str) -> i64 {
-    let filename = format!("./user_stats/stats_{}_{}.toml", guild_id, user_id);
-    let path = Path::new(&filename);
-    if path.exists() {
-        if let Ok(content) = fs::read_to_string(path) {
-            if let Ok(doc) = content.parse::<Value>() {
-                if let Some(val) = doc.get(stat_name).and_then(|v| v.as_integer()) {
-                    return val;
+/// Records a stat change and grants or removes milestone roles it crossed.
+pub async fn apply_stat_change(ctx: &serenity::Context, event: StatEvent) -> Result<i64, Error> {
+    let new_total = crate::storage::get().add_to_stat(&event)?;
+    update_milestones(ctx, &event, new_total).await;
+    Ok(new_total)
+}
+
+/// Milestone failures are logged rather than returned, since the stat change itself
+/// has already been saved.
+async fn update_milestones(ctx: &serenity::Context, event: &StatEvent, new_total: i64) {
+    let Some(definition) = crate::utils::get_stat_definition(event.guild_id, &event.stat).await else {
+        return;
+    };
+    let Some(old_total) = new_total.checked_sub(event.delta) else {
+        return;
+    };
+    let guild_id = serenity::GuildId::new(event.guild_id);
+    let user_id = serenity::UserId::new(event.user_id);
+    for milestone in &definition.milestones {
+        let role_id = serenity::RoleId::new(milestone.role);
+        if old_total < milestone.threshold && new_total >= milestone.threshold {
+            if let Err(e) = ctx.http.add_member_role(guild_id, user_id, role_id, Some("Stat milestone reached")).await {
+                println!("Failed to grant milestone role {role_id} to {user_id}: {e}");
+                continue;
+            }
+            if let Some(channel) = crate::utils::get_logging_channel(event.guild_id, crate::utils::LogEventType::Milestones).await {
+                let message = serenity::CreateMessage::new()
+                    .content(format!(
+                        "🎉 Congratulations {}! You reached **{}** {} and earned {}!",
+                        user_id.mention(), milestone.threshold, definition.label(&event.stat), role_id.mention()
+                    ))
+                    .allowed_mentions(serenity::CreateAllowedMentions::new().users(vec![user_id]));
+                if let Err(e) = channel.send_message(&ctx.http, message).await {
+                    println!("Failed to announce milestone for {user_id}: {e}");
                 }
             }
+        } else if old_total >= milestone.threshold && new_total < milestone.threshold {
+            if let Err(e) = ctx.http.remove_member_role(guild_id, user_id, role_id, Some("Stat dropped below milestone")).await {
+                println!("Failed to remove milestone role {role_id} from {user_id}: {e}");
+            }
         }
     }
-    0
 }
 
-#[poise::command(slash_command, prefix_command, guild_only)]
+/// How a command shows up as the source of a stat change, e.g. `/stat add` or `~stat`.
+fn command_source(ctx: Context<'_>) -> String {
+    format!("{}{}", ctx.prefix(), ctx.command().qualified_name)
+}
+
+pub async fn get_user_stat(guild_id: u64, user_id: u64, stat_name: &str) -> i64 {
+    crate::storage::get().get_stat(guild_id, user_id, stat_name).unwrap_or_else(|e| {
+        println!("Failed to read stat {stat_name} for {user_id}: {e}");
+        0
+    })
+}
+
+/// Record a stat; `~stat <stat> [amount]` still works as a prefix command.
+#[poise::command(slash_command, prefix_command, guild_only, subcommands("add", "give", "history", "set", "reset", "revert", "export", "import"))]
 pub async fn stat(
     ctx: Context<'_>,
     #[description = "The stat to record"]
@@ -54,20 +109,638 @@ pub async fn stat(
     #[description = "The amount to add (default 1)"]
     amount: Option<i64>,
 ) -> Result<(), Error> {
+    add_own_stat(ctx, stat_name, amount).await
+}
+
+/// Add to one of your own stats
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "The stat to record"]
+    #[autocomplete = "autocomplete_stats"]
+    stat_name: String,
+    #[description = "The amount to add (default 1)"]
+    amount: Option<i64>,
+) -> Result<(), Error> {
+    add_own_stat(ctx, stat_name, amount).await
+}
+
+async fn add_own_stat(ctx: Context<'_>, stat_name: String, amount: Option<i64>) -> Result<(), Error> {
     let guild_id = ctx.guild_id().unwrap();
-    let user_id = ctx.author().id;
     let stat_lower = stat_name.to_lowercase();
-    let allowed_stats = crate::utils::get_custom_stats(guild_id.into()).await;
-    if !allowed_stats.contains(&stat_lower) {
+    let Some(definition) = crate::utils::get_stat_definition(guild_id.into(), &stat_lower).await else {
         ctx.say(format!("❌ `{}` is not a tracked stat on this server.", stat_name)).await?;
         return Ok(());
+    };
+    let reply = record_own_stat(
+        ctx.serenity_context(),
+        ctx.data(),
+        guild_id,
+        ctx.author().id,
+        &stat_lower,
+        &definition,
+        amount.unwrap_or(1),
+        &command_source(ctx),
+    ).await?;
+    ctx.say(reply).await?;
+    Ok(())
+}
+
+/// A member's roles and whether they count as staff (server administrators) for stat rules.
+pub async fn member_standing(
+    ctx: &serenity::Context,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+) -> Result<(Vec<serenity::RoleId>, bool), Error> {
+    let member = guild_id.member(ctx, user_id).await?;
+    let is_staff = ctx.cache.guild(guild_id)
+        .map(|guild| guild.member_permissions(&member).administrator())
+        .unwrap_or(false);
+    Ok((member.roles, is_staff))
+}
+
+/// Checks a stat's rules for `actor_id` adding `amount` to `target_id`'s stat and
+/// returns why the change isn't allowed. Cooldowns are per actor and stat.
+#[allow(clippy::too_many_arguments)]
+pub async fn check_stat_rules(
+    data: &Data,
+    guild_id: u64,
+    stat_name: &str,
+    definition: &StatDefinition,
+    actor_id: u64,
+    actor_roles: &[serenity::RoleId],
+    actor_is_staff: bool,
+    target_id: u64,
+    amount: i64,
+) -> Result<(), String> {
+    let label = definition.label(stat_name);
+    match definition.awarded_by {
+        StatAwarders::SelfOnly if actor_id != target_id => {
+            return Err(format!("{label} can only be recorded by members for themselves."));
+        }
+        StatAwarders::Staff if !actor_is_staff || actor_id == target_id => {
+            return Err(format!("{label} can only be awarded to members by staff."));
+        }
+        StatAwarders::Members if actor_id == target_id => {
+            return Err(format!("{label} can only be awarded by other members."));
+        }
+        _ => {}
+    }
+    if !definition.allowed_roles.is_empty()
+        && !actor_is_staff
+        && !actor_roles.iter().any(|role| definition.allowed_roles.contains(&u64::from(*role)))
+    {
+        return Err(format!("You don't have a role that can change {label}."));
+    }
+    if let Some(min) = definition.min_delta.filter(|min| amount < *min) {
+        return Err(format!("{label} can't change by less than {min} at a time."));
+    }
+    if let Some(max) = definition.max_delta.filter(|max| amount > *max) {
+        return Err(format!("{label} can't change by more than {max} at a time."));
+    }
+    if get_user_stat(guild_id, target_id, stat_name).await.checked_add(amount).is_none() {
+        return Err(format!("That would take {label} past the largest value it can hold."));
+    }
+    if let Some(cooldown_secs) = definition.cooldown {
+        let cooldowns = data.stat_cooldowns.lock().await;
+        if let Some(last_changed) = cooldowns.get(&(guild_id, actor_id, stat_name.to_owned())) {
+            let elapsed = last_changed.elapsed().as_secs();
+            if elapsed < cooldown_secs {
+                return Err(format!("⏳ Please wait **{}** more seconds before changing {label} again.", cooldown_secs - elapsed));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn start_stat_cooldown(data: &Data, guild_id: u64, actor_id: u64, stat_name: &str, definition: &StatDefinition) {
+    if definition.cooldown.is_some() {
+        data.stat_cooldowns.lock().await
+            .insert((guild_id, actor_id, stat_name.to_owned()), std::time::Instant::now());
+    }
+}
+
+/// Adds to a member's own stat if the stat's rules allow it. Shared by `stat` and the
+/// `~<stat>` prefix shortcut; returns the reply to show the member either way.
+#[allow(clippy::too_many_arguments)]
+pub async fn record_own_stat(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+    stat_name: &str,
+    definition: &StatDefinition,
+    amount: i64,
+    source: &str,
+) -> Result<String, Error> {
+    let (roles, is_staff) = member_standing(ctx, guild_id, user_id).await?;
+    if let Err(reason) = check_stat_rules(
+        data, guild_id.into(), stat_name, definition, user_id.into(), &roles, is_staff, user_id.into(), amount,
+    ).await {
+        return Ok(format!("❌ {reason}"));
+    }
+    let new_total = modify_user_stat(ctx, guild_id.into(), user_id.into(), stat_name, amount, source).await?;
+    start_stat_cooldown(data, guild_id.into(), user_id.into(), stat_name, definition).await;
+    Ok(format!("📈 Added {} to your {} stat! New total: **{}**", amount, definition.label(stat_name), new_total))
+}
+
+/// Award a stat to another member
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn give(
+    ctx: Context<'_>,
+    #[description = "Member to award"] user: serenity::User,
+    #[description = "The stat to award"]
+    #[autocomplete = "autocomplete_stats"]
+    stat_name: String,
+    #[description = "The amount to award (default 1)"] amount: Option<i64>,
+    #[description = "Why they earned it"]
+    #[rest]
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let stat_lower = stat_name.to_lowercase();
+    let Some(definition) = crate::utils::get_stat_definition(guild_id.into(), &stat_lower).await else {
+        ctx.say(format!("❌ `{}` is not a tracked stat on this server.", stat_name)).await?;
+        return Ok(());
+    };
+    if user.bot {
+        ctx.say("❌ Bots can't be awarded stats.").await?;
+        return Ok(());
+    }
+    let amount = amount.unwrap_or(1);
+    let actor = ctx.author();
+    let (roles, is_staff) = member_standing(ctx.serenity_context(), guild_id, actor.id).await?;
+    if let Err(reason) = check_stat_rules(
+        ctx.data(), guild_id.into(), &stat_lower, &definition, actor.id.into(), &roles, is_staff, user.id.into(), amount,
+    ).await {
+        ctx.say(format!("❌ {reason}")).await?;
+        return Ok(());
+    }
+    let new_total = apply_stat_change(ctx.serenity_context(), StatEvent {
+        id: 0,
+        guild_id: guild_id.into(),
+        user_id: user.id.into(),
+        stat: stat_lower.clone(),
+        delta: amount,
+        timestamp: chrono::Utc::now().timestamp(),
+        source: command_source(ctx),
+        awarded_by: Some(actor.id.into()),
+        reason: reason.clone(),
+    }).await?;
+    start_stat_cooldown(ctx.data(), guild_id.into(), actor.id.into(), &stat_lower, &definition).await;
+    let label = definition.label(&stat_lower);
+    let mut response = format!("🎁 {} awarded {} {} to {}! New total: **{}**", actor.mention(), amount, label, user.mention(), new_total);
+    if let Some(reason) = &reason {
+        response.push_str(&format!("\n> {reason}"));
+    }
+    ctx.say(response).await?;
+    if let Some(log_channel) = crate::utils::get_logging_channel(guild_id.into(), crate::utils::LogEventType::Default).await {
+        let embed = serenity::CreateEmbed::new()
+            .title("Stat Awarded")
+            .field("Recipient", format!("{} ({})", user.tag(), user.id), true)
+            .field("Awarded By", format!("{} ({})", actor.tag(), actor.id), true)
+            .field("Stat", format!("{} {:+} (now {})", label, amount, new_total), true)
+            .field("Reason", reason.as_deref().unwrap_or("No reason provided"), false)
+            .color(serenity::Colour::DARK_GREEN);
+        log_channel.send_message(&ctx.http(), serenity::CreateMessage::new().embed(embed)).await?;
+    }
+    Ok(())
+}
+
+/// List recent stat changes
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "Only show changes to this user's stats"]
+    user: Option<serenity::User>,
+    #[description = "Only show changes to this stat"]
+    #[autocomplete = "autocomplete_stats"]
+    stat_name: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let stat_lower = stat_name.map(|name| name.to_lowercase());
+    let events = crate::storage::get().stat_history(
+        guild_id.into(),
+        user.as_ref().map(|user| user.id.into()),
+        stat_lower.as_deref(),
+        HISTORY_LENGTH,
+    )?;
+    if events.is_empty() {
+        ctx.say("📜 No matching stat changes have been recorded.").await?;
+        return Ok(());
+    }
+    let mut description = String::new();
+    for event in events {
+        description.push_str(&format!(
+            "`#{}` <@{}> `{}` **{:+}** <t:{}:R> via `{}`",
+            event.id, event.user_id, event.stat, event.delta, event.timestamp, event.source
+        ));
+        if let Some(awarded_by) = event.awarded_by {
+            description.push_str(&format!(" from <@{awarded_by}>"));
+        }
+        if let Some(reason) = &event.reason {
+            description.push_str(&format!(" — {reason}"));
+        }
+        description.push('\n');
+    }
+    let title = match &user {
+        Some(user) => format!("📜 Recent stat changes for {}", user.name),
+        None => "📜 Recent stat changes".to_owned(),
+    };
+    let embed = serenity::CreateEmbed::new()
+        .title(title)
+        .description(description)
+        .color(serenity::Colour::BLUE);
+    ctx.send(poise::CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+async fn log_stat_admin_action(ctx: Context<'_>, title: &str, description: String) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
+    if let Some(log_channel) = crate::utils::get_logging_channel(guild_id.into(), crate::utils::LogEventType::Moderation).await {
+        let embed = serenity::CreateEmbed::new()
+            .title(title)
+            .description(description)
+            .field("Moderator", format!("{} ({})", ctx.author().tag(), ctx.author().id), true)
+            .color(serenity::Colour::ORANGE);
+        log_channel.send_message(&ctx.http(), serenity::CreateMessage::new().embed(embed)).await?;
+    }
+    Ok(())
+}
+
+/// Set a member's stat to an exact value (admin-only)
+#[poise::command(slash_command, prefix_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "Member whose stat to set"] user: serenity::User,
+    #[description = "The stat to set"]
+    #[autocomplete = "autocomplete_stats"]
+    stat_name: String,
+    #[description = "The new value"] value: i64,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let stat_lower = stat_name.to_lowercase();
+    if !crate::utils::get_custom_stats(guild_id.into()).await.contains(&stat_lower) {
+        ctx.say(format!("❌ `{}` is not a tracked stat on this server.", stat_name)).await?;
+        return Ok(());
+    }
+    let current = get_user_stat(guild_id.into(), user.id.into(), &stat_lower).await;
+    let Some(delta) = value.checked_sub(current) else {
+        ctx.say(format!("❌ {} is too far from the current value ({}) to record as one change.", value, current)).await?;
+        return Ok(());
+    };
+    if delta != 0 {
+        modify_user_stat(ctx.serenity_context(), guild_id.into(), user.id.into(), &stat_lower, delta, &command_source(ctx)).await?;
+    }
+    let response = format!("✏️ Set {}'s `{}` from {} to **{}**", user.name, stat_lower, current, value);
+    ctx.say(&response).await?;
+    log_stat_admin_action(ctx, "Stat Set", response).await
+}
+
+/// Reset a stat to zero for one member or the whole server (admin-only)
+#[poise::command(slash_command, prefix_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn reset(
+    ctx: Context<'_>,
+    #[description = "The stat to reset"]
+    #[autocomplete = "autocomplete_stats"]
+    stat_name: String,
+    #[description = "Only reset this member (defaults to everyone)"] user: Option<serenity::User>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let stat_lower = stat_name.to_lowercase();
+    let source = command_source(ctx);
+    let targets = match &user {
+        Some(user) => vec![(user.id.into(), get_user_stat(guild_id.into(), user.id.into(), &stat_lower).await)],
+        None => crate::storage::get().stat_leaderboard(guild_id.into(), &stat_lower, None)?,
+    };
+    let (mut reset_count, mut skipped) = (0, Vec::new());
+    for (user_id, value) in targets {
+        let Some(delta) = value.checked_neg() else {
+            skipped.push(user_id);
+            continue;
+        };
+        if delta != 0 {
+            modify_user_stat(ctx.serenity_context(), guild_id.into(), user_id, &stat_lower, delta, &source).await?;
+            reset_count += 1;
+        }
+    }
+    let mut response = match &user {
+        Some(user) if !skipped.is_empty() => {
+            ctx.say(format!("❌ {}'s `{}` is too low to reset in one change; use `/stat set` instead.", user.name, stat_lower)).await?;
+            return Ok(());
+        }
+        Some(user) => format!("🧹 Reset {}'s `{}` to 0", user.name, stat_lower),
+        None => format!("🧹 Reset `{}` to 0 for {} members", stat_lower, reset_count),
+    };
+    if !skipped.is_empty() {
+        let members: Vec<String> = skipped.iter().map(|user_id| format!("<@{}>", user_id)).collect();
+        response.push_str(&format!("\n⚠️ Too low to reset in one change: {}", members.join(", ")));
+    }
+    ctx.say(&response).await?;
+    log_stat_admin_action(ctx, "Stat Reset", response).await
+}
+
+/// Undo a change listed in `stat history` (admin-only)
+#[poise::command(slash_command, prefix_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn revert(
+    ctx: Context<'_>,
+    #[description = "Change number shown in stat history (e.g. 42)"] change_id: u64,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let storage = crate::storage::get();
+    let Some(event) = storage.stat_event(guild_id.into(), change_id)? else {
+        ctx.say(format!("❌ There is no stat change #{}.", change_id)).await?;
+        return Ok(());
+    };
+    let revert_source = format!("revert #{}", change_id);
+    let already_reverted = storage
+        .stat_history(guild_id.into(), Some(event.user_id), Some(&event.stat), usize::MAX)?
+        .iter()
+        .any(|other| other.source == revert_source);
+    if already_reverted {
+        ctx.say(format!("❌ Change #{} has already been reverted.", change_id)).await?;
+        return Ok(());
+    }
+    let Some(delta) = event.delta.checked_neg() else {
+        ctx.say(format!("❌ Change #{} is too large to revert; use `/stat set` instead.", change_id)).await?;
+        return Ok(());
+    };
+    let new_total = match get_user_stat(guild_id.into(), event.user_id, &event.stat).await.checked_add(delta) {
+        Some(_) => modify_user_stat(ctx.serenity_context(), guild_id.into(), event.user_id, &event.stat, delta, &revert_source).await?,
+        None => {
+            ctx.say(format!("❌ Reverting change #{} would take the stat past the largest value it can hold.", change_id)).await?;
+            return Ok(());
+        }
+    };
+    let response = format!(
+        "↩️ Reverted change #{} ({:+} `{}` for <@{}>). New total: **{}**",
+        change_id, event.delta, event.stat, event.user_id, new_total
+    );
+    ctx.say(&response).await?;
+    log_stat_admin_action(ctx, "Stat Change Reverted", response).await
+}
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum StatFileFormat {
+    #[name = "csv"]
+    Csv,
+    #[name = "json"]
+    Json,
+}
+
+/// One member's value in an export or import file.
+#[derive(Debug, Serialize, Deserialize)]
+struct StatRow {
+    user_id: u64,
+    #[serde(default)]
+    username: String,
+    stat: String,
+    value: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct HistoryRow {
+    id: u64,
+    timestamp: i64,
+    user_id: u64,
+    stat: String,
+    delta: i64,
+    source: String,
+    awarded_by: Option<u64>,
+    reason: Option<String>,
+}
+
+impl From<StatEvent> for HistoryRow {
+    fn from(event: StatEvent) -> Self {
+        HistoryRow {
+            id: event.id,
+            timestamp: event.timestamp,
+            user_id: event.user_id,
+            stat: event.stat,
+            delta: event.delta,
+            source: event.source,
+            awarded_by: event.awarded_by,
+            reason: event.reason,
+        }
     }
-    let add_amount = amount.unwrap_or(1);
-    let new_total = modify_user_stat(guild_id.into(), user_id.into(), &stat_lower, add_amount).await?;
-    ctx.say(format!("📈 Added {} to your `{}` stat! New total: **{}**", add_amount, stat_lower, new_total)).await?;
+}
+
+/// JSON exports also carry the history; imports only read `stats`.
+#[derive(Debug, Serialize, Deserialize)]
+struct StatFile {
+    stats: Vec<StatRow>,
+    #[serde(default, skip_deserializing)]
+    history: Vec<HistoryRow>,
+}
+
+fn to_csv<T: Serialize>(rows: &[T]) -> Result<Vec<u8>, Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    Ok(writer.into_inner().map_err(|e| e.into_error())?)
+}
+
+/// Export stat values and history as CSV or JSON (admin-only)
+#[poise::command(slash_command, prefix_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn export(
+    ctx: Context<'_>,
+    #[description = "Only export this stat (defaults to all)"]
+    #[autocomplete = "autocomplete_stats"]
+    stat_name: Option<String>,
+    #[description = "File format (default csv)"] format: Option<StatFileFormat>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let stat_lower = stat_name.map(|s| s.to_lowercase());
+    let storage = crate::storage::get();
+    let usernames: std::collections::HashMap<u64, String> = ctx
+        .guild()
+        .map(|guild| guild.members.iter().map(|(id, member)| (id.get(), member.user.name.clone())).collect())
+        .unwrap_or_default();
+    let stats: Vec<StatRow> = storage
+        .guild_stats(guild_id.into())?
+        .into_iter()
+        .filter(|(_, stat, _)| stat_lower.as_ref().is_none_or(|wanted| wanted == stat))
+        .map(|(user_id, stat, value)| StatRow {
+            user_id,
+            username: usernames.get(&user_id).cloned().unwrap_or_default(),
+            stat,
+            value,
+        })
+        .collect();
+    let mut history = storage.stat_history(guild_id.into(), None, stat_lower.as_deref(), usize::MAX)?;
+    history.reverse();
+    let history: Vec<HistoryRow> = history.into_iter().map(HistoryRow::from).collect();
+
+    let label = stat_lower.as_deref().unwrap_or("stats");
+    let member_count = stats.iter().map(|row| row.user_id).collect::<std::collections::HashSet<_>>().len();
+    let mut reply = poise::CreateReply::default().content(format!(
+        "📤 Exported {} values for {} members and {} history entries.",
+        stats.len(), member_count, history.len()
+    ));
+    match format.unwrap_or(StatFileFormat::Csv) {
+        StatFileFormat::Csv => {
+            reply = reply
+                .attachment(serenity::CreateAttachment::bytes(to_csv(&stats)?, format!("{}.csv", label)))
+                .attachment(serenity::CreateAttachment::bytes(to_csv(&history)?, format!("{}-history.csv", label)));
+        }
+        StatFileFormat::Json => {
+            let json = serde_json::to_vec_pretty(&StatFile { stats, history })?;
+            reply = reply.attachment(serenity::CreateAttachment::bytes(json, format!("{}.json", label)));
+        }
+    }
+    ctx.send(reply).await?;
     Ok(())
 }
 
+/// Rows with their line number (CSV) or position (JSON) for error messages.
+fn parse_stat_file(filename: &str, content: &[u8]) -> Result<Vec<(usize, StatRow)>, String> {
+    if filename.to_lowercase().ends_with(".json") {
+        let file: StatFile = serde_json::from_slice(content)
+            .map_err(|e| format!("Invalid JSON at line {}, column {}: {}", e.line(), e.column(), e))?;
+        Ok(file.stats.into_iter().enumerate().map(|(index, row)| (index + 1, row)).collect())
+    } else if filename.to_lowercase().ends_with(".csv") {
+        let mut reader = csv::Reader::from_reader(content);
+        let mut rows = Vec::new();
+        for record in reader.deserialize::<StatRow>() {
+            match record {
+                Ok(row) => rows.push((rows.len() + 2, row)),
+                Err(e) => {
+                    let line = e.position().map(|p| p.line()).unwrap_or_default();
+                    return Err(format!("Invalid CSV on line {}: {}", line, e));
+                }
+            }
+        }
+        Ok(rows)
+    } else {
+        Err("Upload a `.csv` or `.json` file in the format produced by `stat export`.".to_owned())
+    }
+}
+
+/// Set stat values from a CSV or JSON file, previewing the changes first (admin-only)
+#[poise::command(slash_command, prefix_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn import(
+    ctx: Context<'_>,
+    #[description = "CSV or JSON file from stat export"] file: serenity::Attachment,
+    #[description = "Only show what would change"] dry_run: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let content = file.download().await?;
+    let rows = match parse_stat_file(&file.filename, &content) {
+        Ok(rows) => rows,
+        Err(e) => {
+            ctx.say(format!("❌ {}", e)).await?;
+            return Ok(());
+        }
+    };
+
+    let tracked = crate::utils::get_custom_stats(guild_id.into()).await;
+    let mut seen = std::collections::HashSet::new();
+    let mut errors = Vec::new();
+    let mut changes = Vec::new();
+    for (line, row) in rows {
+        let stat = row.stat.to_lowercase();
+        if !tracked.contains(&stat) {
+            errors.push(format!("Line {}: `{}` is not a tracked stat", line, row.stat));
+        } else if !seen.insert((row.user_id, stat.clone())) {
+            errors.push(format!("Line {}: duplicate `{}` value for <@{}>", line, stat, row.user_id));
+        } else {
+            let current = get_user_stat(guild_id.into(), row.user_id, &stat).await;
+            if row.value.checked_sub(current).is_none() {
+                errors.push(format!("Line {}: {} is too far from <@{}>'s current `{}` ({}) to record as one change", line, row.value, row.user_id, stat, current));
+            } else if current != row.value {
+                changes.push((row.user_id, stat, current, row.value));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        let mut message = format!("❌ `{}` has {} problems, nothing was imported:\n", file.filename, errors.len());
+        for error in errors.iter().take(HISTORY_LENGTH) {
+            message.push_str(&format!("• {}\n", error));
+        }
+        if errors.len() > HISTORY_LENGTH {
+            message.push_str(&format!("…and {} more", errors.len() - HISTORY_LENGTH));
+        }
+        ctx.send(poise::CreateReply::default()
+            .content(message)
+            .allowed_mentions(serenity::CreateAllowedMentions::new())
+        ).await?;
+        return Ok(());
+    }
+    if changes.is_empty() {
+        ctx.say(format!("✅ `{}` matches the current stats; nothing to change.", file.filename)).await?;
+        return Ok(());
+    }
+
+    let mut preview = String::new();
+    for (user_id, stat, current, value) in changes.iter().take(HISTORY_LENGTH) {
+        preview.push_str(&format!("<@{}> `{}`: {} → **{}**\n", user_id, stat, current, value));
+    }
+    if changes.len() > HISTORY_LENGTH {
+        preview.push_str(&format!("…and {} more", changes.len() - HISTORY_LENGTH));
+    }
+    let embed = serenity::CreateEmbed::new()
+        .title(format!("📥 Import preview: {} changes", changes.len()))
+        .description(preview)
+        .color(serenity::Colour::BLUE);
+    if dry_run.unwrap_or(false) {
+        ctx.send(poise::CreateReply::default()
+            .content("Dry run: nothing was changed.")
+            .embed(embed)
+            .allowed_mentions(serenity::CreateAllowedMentions::new())
+        ).await?;
+        return Ok(());
+    }
+
+    let ctx_id = ctx.id();
+    let apply_id = format!("{}apply", ctx_id);
+    let cancel_id = format!("{}cancel", ctx_id);
+    let buttons = serenity::CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(&apply_id).label("Apply").style(serenity::ButtonStyle::Danger),
+        serenity::CreateButton::new(&cancel_id).label("Cancel").style(serenity::ButtonStyle::Secondary),
+    ]);
+    let handle = ctx.send(poise::CreateReply::default()
+        .embed(embed)
+        .components(vec![buttons])
+        .allowed_mentions(serenity::CreateAllowedMentions::new())
+    ).await?;
+    let author_id = ctx.author().id;
+    let press = serenity::ComponentInteractionCollector::new(ctx)
+        .filter(move |press| press.data.custom_id.starts_with(&ctx_id.to_string()) && press.user.id == author_id)
+        .timeout(std::time::Duration::from_secs(IMPORT_CONFIRM_TIMEOUT))
+        .await;
+    let Some(press) = press.filter(|press| press.data.custom_id == apply_id) else {
+        handle.edit(ctx, poise::CreateReply::default()
+            .content("Import cancelled; nothing was changed.")
+            .components(Vec::new())
+        ).await?;
+        return Ok(());
+    };
+    press.create_response(ctx.serenity_context(), serenity::CreateInteractionResponse::Acknowledge).await?;
+
+    // Values may have moved since the preview, so the delta is taken again here.
+    let source = command_source(ctx);
+    let (mut applied, mut skipped) = (0, 0);
+    for (user_id, stat, _, value) in &changes {
+        let current = get_user_stat(guild_id.into(), *user_id, stat).await;
+        match value.checked_sub(current) {
+            Some(0) => {}
+            Some(delta) => {
+                modify_user_stat(ctx.serenity_context(), guild_id.into(), *user_id, stat, delta, &source).await?;
+                applied += 1;
+            }
+            None => skipped += 1,
+        }
+    }
+    let mut response = format!("📥 Imported `{}`: updated {} stat values", file.filename, applied);
+    if skipped > 0 {
+        response.push_str(&format!(" (skipped {} that moved too far since the preview)", skipped));
+    }
+    handle.edit(ctx, poise::CreateReply::default().content(&response).components(Vec::new())).await?;
+    log_stat_admin_action(ctx, "Stats Imported", response).await
+}
+
 #[poise::command(slash_command, prefix_command, guild_only)]
 pub async fn viewstat(
     ctx: Context<'_>,
@@ -76,21 +749,46 @@ pub async fn viewstat(
     stat_name: Option<String>,
     #[description = "User to view (defaults to you)"]
     user: Option<serenity::User>,
+    #[description = "Time window (default all-time)"] period: Option<StatPeriod>,
 ) -> Result<(), Error> {
     let guild_id = ctx.guild_id().unwrap();
     let target_user = user.as_ref().unwrap_or(ctx.author());
+    let period = period.unwrap_or(StatPeriod::AllTime);
+    let windowed_stats = match period.since() {
+        Some(since) => match crate::storage::get().user_stats_since(guild_id.into(), target_user.id.into(), since) {
+            Ok(stats) => Some(stats),
+            Err(e) => {
+                ctx.say(format!("❌ Couldn't total stats for {}: {}", period.label(), e)).await?;
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    let suffix = match period {
+        StatPeriod::AllTime => String::new(),
+        _ => format!(" ({})", period.label()),
+    };
     match stat_name {
         Some(name) => {
             let stat_lower = name.to_lowercase();
-            let total = get_user_stat(guild_id.into(), target_user.id.into(), &stat_lower).await;
-            ctx.say(format!("📊 {} > `{}`: **{}**", target_user.name, stat_lower, total)).await?;
+            let total = match &windowed_stats {
+                Some(stats) => stats.iter().find(|(name, _)| *name == stat_lower).map(|(_, value)| *value).unwrap_or(0),
+                None => get_user_stat(guild_id.into(), target_user.id.into(), &stat_lower).await,
+            };
+            let label = crate::utils::get_stat_definition(guild_id.into(), &stat_lower).await
+                .map(|definition| definition.label(&stat_lower))
+                .unwrap_or_else(|| format!("`{stat_lower}`"));
+            ctx.say(format!("📊 {} > {}{}: **{}**", target_user.name, label, suffix, total)).await?;
         }
         None => {
-            let all_stats = get_all_user_stats(guild_id.into(), target_user.id.into()).await;
+            let all_stats = match windowed_stats {
+                Some(stats) => stats,
+                None => get_all_user_stats(guild_id.into(), target_user.id.into()).await,
+            };
             if all_stats.is_empty() {
-                ctx.say(format!("📊 {} has no recorded stats.", target_user.name)).await?;
+                ctx.say(format!("📊 {} has no recorded stats{}.", target_user.name, suffix)).await?;
             } else {
-                let mut response = format!("📊 **{}'s Stats:**\n", target_user.name);
+                let mut response = format!("📊 **{}'s Stats{}:**\n", target_user.name, suffix);
                 for (name, value) in all_stats {
                     response.push_str(&format!("- `{}`: **{}**\n", name, value));
                 }
@@ -102,22 +800,140 @@ pub async fn viewstat(
 }
 
 pub async fn get_all_user_stats(guild_id: u64, user_id: u64) -> Vec<(String, i64)> {
-    let filename = format!("./user_stats/stats_{}_{}.toml", guild_id, user_id);
-    let path = Path::new(&filename);
-    let mut stats = Vec::new();
-    
-    if path.exists() {
-        if let Ok(content) = fs::read_to_string(path) {
-            if let Ok(doc) = content.parse::<Value>() {
-                if let Some(table) = doc.as_table() {
-                    for (k, v) in table {
-                        if let Some(val) = v.as_integer() {
-                            stats.push((k.clone(), val));
-                        }
-                    }
-                }
-            }
+    crate::storage::get().user_stats(guild_id, user_id).unwrap_or_else(|e| {
+        println!("Failed to read stats for {user_id}: {e}");
+        Vec::new()
+    })
+}
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum StatPeriod {
+    #[name = "weekly"]
+    Weekly,
+    #[name = "monthly"]
+    Monthly,
+    #[name = "all-time"]
+    AllTime,
+}
+
+impl StatPeriod {
+    /// Start of the window as a unix timestamp; weeks and months are rolling 7 and 30 days.
+    pub fn since(self) -> Option<i64> {
+        let days = match self {
+            StatPeriod::Weekly => 7,
+            StatPeriod::Monthly => 30,
+            StatPeriod::AllTime => return None,
+        };
+        Some((chrono::Utc::now() - chrono::Duration::days(days)).timestamp())
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            StatPeriod::Weekly => "this week",
+            StatPeriod::Monthly => "this month",
+            StatPeriod::AllTime => "all time",
+        }
+    }
+}
+
+fn leaderboard_page(
+    label: &str,
+    period: StatPeriod,
+    entries: &[(u64, i64)],
+    page: usize,
+    invoker: serenity::UserId,
+) -> serenity::CreateEmbed {
+    let page_count = entries.len().div_ceil(LEADERBOARD_PAGE_SIZE).max(1);
+    let mut description = String::new();
+    for (index, (user_id, value)) in entries.iter().enumerate().skip(page * LEADERBOARD_PAGE_SIZE).take(LEADERBOARD_PAGE_SIZE) {
+        if *user_id == u64::from(invoker) {
+            description.push_str(&format!("**{}. <@{}> — {}** ⬅️\n", index + 1, user_id, value));
+        } else {
+            description.push_str(&format!("{}. <@{}> — {}\n", index + 1, user_id, value));
         }
     }
-    stats
+    if description.is_empty() {
+        description.push_str("Nobody has recorded this stat yet.");
+    }
+    let own_rank = match entries.iter().position(|(user_id, _)| *user_id == u64::from(invoker)) {
+        Some(index) => format!("Your rank: #{} of {}", index + 1, entries.len()),
+        None => "You're not on this leaderboard yet".to_owned(),
+    };
+    serenity::CreateEmbed::new()
+        .title(format!("🏆 {} leaderboard ({})", label, period.label()))
+        .description(description)
+        .footer(serenity::CreateEmbedFooter::new(format!("{own_rank} • Page {}/{}", page + 1, page_count)))
+        .color(serenity::Colour::GOLD)
+}
+
+#[poise::command(slash_command, prefix_command, guild_only)]
+pub async fn leaderboard(
+    ctx: Context<'_>,
+    #[description = "The stat to rank members by"]
+    #[autocomplete = "autocomplete_stats"]
+    stat_name: String,
+    #[description = "Time window (default all-time)"] period: Option<StatPeriod>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let stat_lower = stat_name.to_lowercase();
+    let Some(definition) = crate::utils::get_stat_definition(guild_id.into(), &stat_lower).await else {
+        ctx.say(format!("❌ `{}` is not a tracked stat on this server.", stat_name)).await?;
+        return Ok(());
+    };
+    let label = definition.label(&stat_lower);
+    let period = period.unwrap_or(StatPeriod::AllTime);
+    let entries = match crate::storage::get().stat_leaderboard(guild_id.into(), &stat_lower, period.since()) {
+        Ok(entries) => entries,
+        Err(e) => {
+            ctx.say(format!("❌ Couldn't build the {} leaderboard: {}", label, e)).await?;
+            return Ok(());
+        }
+    };
+    let invoker = ctx.author().id;
+    let page_count = entries.len().div_ceil(LEADERBOARD_PAGE_SIZE).max(1);
+    let mut page = entries.iter()
+        .position(|(user_id, _)| *user_id == u64::from(invoker))
+        .map(|index| index / LEADERBOARD_PAGE_SIZE)
+        .unwrap_or(0);
+    let prev_id = format!("{}prev", ctx.id());
+    let next_id = format!("{}next", ctx.id());
+    let buttons = serenity::CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(&prev_id).emoji('◀'),
+        serenity::CreateButton::new(&next_id).emoji('▶'),
+    ]);
+    let mut reply = poise::CreateReply::default()
+        .embed(leaderboard_page(&label, period, &entries, page, invoker));
+    if page_count > 1 {
+        reply = reply.components(vec![buttons]);
+    }
+    let handle = ctx.send(reply).await?;
+    if page_count == 1 {
+        return Ok(());
+    }
+    let ctx_id = ctx.id();
+    while let Some(press) = serenity::ComponentInteractionCollector::new(ctx)
+        .filter(move |press| press.data.custom_id.starts_with(&ctx_id.to_string()))
+        .timeout(std::time::Duration::from_secs(LEADERBOARD_TIMEOUT))
+        .await
+    {
+        if press.data.custom_id == next_id {
+            page = (page + 1) % page_count;
+        } else if press.data.custom_id == prev_id {
+            page = page.checked_sub(1).unwrap_or(page_count - 1);
+        } else {
+            continue;
+        }
+        press.create_response(
+            ctx.serenity_context(),
+            serenity::CreateInteractionResponse::UpdateMessage(
+                serenity::CreateInteractionResponseMessage::new()
+                    .embed(leaderboard_page(&label, period, &entries, page, invoker)),
+            ),
+        ).await?;
+    }
+    handle.edit(ctx, poise::CreateReply::default()
+        .embed(leaderboard_page(&label, period, &entries, page, invoker))
+        .components(Vec::new())
+    ).await?;
+    Ok(())
 }
@@ -0,0 +1,1569 @@
+use crate::Error;
+use crate::utils::{write_atomic, CONFIG_BACKUP_PATH, CONFIG_PATH};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use toml::Value;
+
+const USER_STATS_DIR: &str = "./user_stats";
+const STAT_EVENTS_DIR: &str = "./stat_events";
+const TICKET_TEMPLATES_DIR: &str = "./ticket_templates";
+const TICKET_RECORDS_DIR: &str = "./ticket_records";
+const MODERATION_CASES_DIR: &str = "./moderation_cases";
+const POLLS_DIR: &str = "./polls";
+const DICE_DIR: &str = "./dice";
+const COUNTDOWNS_DIR: &str = "./countdowns";
+/// Rolls kept per member and guild for `roll history`.
+pub const DICE_ROLLS_KEPT: usize = 100;
+const CONFIG_BACKUPS_KEPT: i64 = 20;
+
+static STORAGE: OnceLock<Box<dyn Storage>> = OnceLock::new();
+/// One lock per guild's dice file, keyed by guild ID. See `update_dice_file`.
+static DICE_FILE_LOCKS: OnceLock<Mutex<HashMap<u64, Arc<Mutex<()>>>>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Files,
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+    #[serde(default = "default_database_path")]
+    pub path: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: StorageBackend::default(),
+            path: default_database_path(),
+        }
+    }
+}
+
+fn default_database_path() -> String {
+    "archbot.db".to_owned()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TicketRecord {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub owner_id: u64,
+    pub issue: String,
+    pub opened_at: i64,
+    #[serde(default)]
+    pub closed_at: Option<i64>,
+    #[serde(default)]
+    pub closed_by: Option<String>,
+    #[serde(default)]
+    pub close_reason: Option<String>,
+}
+
+/// One change to a member's stat. Totals are kept separately; events let stats be
+/// summed over a time window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatEvent {
+    /// Assigned by the storage backend; the line number in the file layout.
+    #[serde(skip)]
+    pub id: u64,
+    pub guild_id: u64,
+    pub user_id: u64,
+    pub stat: String,
+    pub delta: i64,
+    pub timestamp: i64,
+    /// The command or prefix trigger that made the change, e.g. `/stat add` or `~solves`.
+    #[serde(default = "unknown_source")]
+    pub source: String,
+    /// Who awarded the change to someone else, if it wasn't their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub awarded_by: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+fn unknown_source() -> String {
+    "unknown".to_owned()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModerationCase {
+    pub guild_id: u64,
+    /// Numbered per guild. Ignored by `Storage::add_case`, which assigns the next one.
+    pub case_id: u64,
+    pub action: String,
+    pub target_id: u64,
+    pub moderator_id: u64,
+    pub reason: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Poll {
+    pub guild_id: u64,
+    /// Numbered per guild. Ignored by `Storage::add_poll`, which assigns the next one.
+    pub poll_id: u64,
+    pub channel_id: u64,
+    /// The message announcing the poll, once it has been posted.
+    #[serde(default)]
+    pub message_id: Option<u64>,
+    pub created_by: u64,
+    pub question: String,
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub kind: PollKind,
+    /// Whether voter lists are hidden; public polls show who picked each option.
+    #[serde(default = "default_anonymous")]
+    pub anonymous: bool,
+    pub ends_at: i64,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub votes: Vec<PollVote>,
+}
+
+fn default_anonymous() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter)]
+#[serde(rename_all = "lowercase")]
+pub enum PollKind {
+    #[default]
+    #[name = "single choice"]
+    Single,
+    #[name = "multiple choice"]
+    Multi,
+    #[name = "ranked choice"]
+    Ranked,
+}
+
+/// A member's current ballot; voting again replaces it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollVote {
+    pub user_id: u64,
+    /// Option indexes; in preference order for ranked polls. Older single-choice
+    /// ballots stored one `option` instead.
+    #[serde(alias = "option", deserialize_with = "one_or_many")]
+    pub choices: Vec<usize>,
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<usize>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(usize),
+        Many(Vec<usize>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(choice) => vec![choice],
+        OneOrMany::Many(choices) => choices,
+    })
+}
+
+/// A countdown job. Only changes of state (started, cancelled, finished) are stored;
+/// the current number lives in the countdown message while it runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Countdown {
+    pub guild_id: u64,
+    /// Numbered per guild. Ignored by `Storage::add_countdown`, which assigns the next one.
+    pub countdown_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub started_by: u64,
+    pub start: u32,
+    pub current: i32,
+    /// Chance of counting up instead of down once the countdown is nearly over.
+    pub max_chance: f32,
+    /// Sent when the countdown reaches 0.
+    pub ending: String,
+    pub started_at: i64,
+    #[serde(default)]
+    pub finished: bool,
+}
+
+/// A dice expression a member saved under a name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiceMacro {
+    pub guild_id: u64,
+    pub user_id: u64,
+    pub name: String,
+    pub expression: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiceRoll {
+    pub guild_id: u64,
+    pub user_id: u64,
+    pub expression: String,
+    pub total: i64,
+    /// Kept d20s that showed 20 and 1.
+    #[serde(default)]
+    pub crits: u32,
+    #[serde(default)]
+    pub fumbles: u32,
+    pub rolled_at: i64,
+}
+
+/// Where an instance keeps its persistent state. Every instance has its own store;
+/// only config, ticket templates, polls and countdowns are replicated across the cluster.
+pub trait Storage: Send + Sync {
+    /// The raw `config.toml` document, or None if none was saved yet.
+    fn load_config(&self) -> Result<Option<String>, Error>;
+    /// Replaces the config document, keeping the previous one as a backup.
+    fn save_config(&self, content: &str) -> Result<(), Error>;
+    fn backup_config(&self, label: &str, content: &str) -> Result<(), Error>;
+
+    fn get_stat(&self, guild_id: u64, user_id: u64, stat_name: &str) -> Result<i64, Error>;
+    fn set_stat(&self, guild_id: u64, user_id: u64, stat_name: &str, value: i64) -> Result<(), Error>;
+    /// Adds `event.delta` to the member's stat, logs the event and returns the new total.
+    /// The event's `id` is assigned by the backend.
+    fn add_to_stat(&self, event: &StatEvent) -> Result<i64, Error>;
+    fn user_stats(&self, guild_id: u64, user_id: u64) -> Result<Vec<(String, i64)>, Error>;
+    /// A member's stats summed over the changes made at or after `since`.
+    fn user_stats_since(&self, guild_id: u64, user_id: u64, since: i64) -> Result<Vec<(String, i64)>, Error>;
+    /// The most recent stat changes in a guild, newest first, optionally narrowed to one member or stat.
+    fn stat_history(&self, guild_id: u64, user_id: Option<u64>, stat_name: Option<&str>, limit: usize) -> Result<Vec<StatEvent>, Error>;
+    /// Every member with a value for `stat_name`, highest first. With `since`, only
+    /// changes made at or after that timestamp are counted.
+    fn stat_leaderboard(&self, guild_id: u64, stat_name: &str, since: Option<i64>) -> Result<Vec<(u64, i64)>, Error>;
+    /// Every (member, stat, value) in a guild, sorted by stat and then member.
+    fn guild_stats(&self, guild_id: u64) -> Result<Vec<(u64, String, i64)>, Error>;
+    fn stat_event(&self, guild_id: u64, event_id: u64) -> Result<Option<StatEvent>, Error>;
+    /// Moves every member's `from` value (and its history) to `to`, adding to any value
+    /// already there. Returns how many members had a `from` value.
+    fn rename_stat(&self, guild_id: u64, from: &str, to: &str) -> Result<usize, Error>;
+
+    fn ticket_template(&self, guild_id: u64) -> Result<Option<String>, Error>;
+    fn set_ticket_template(&self, guild_id: u64, content: &str) -> Result<(), Error>;
+    fn open_ticket(&self, record: &TicketRecord) -> Result<(), Error>;
+    fn close_ticket(&self, guild_id: u64, channel_id: u64, closed_by: &str, reason: &str, closed_at: i64) -> Result<(), Error>;
+
+    /// Stores a new moderation case and returns its case number.
+    fn add_case(&self, case: &ModerationCase) -> Result<u64, Error>;
+
+    /// Stores a new poll and returns its poll number.
+    fn add_poll(&self, poll: &Poll) -> Result<u64, Error>;
+    /// Inserts or replaces a poll under its existing number.
+    fn save_poll(&self, poll: &Poll) -> Result<(), Error>;
+    fn poll(&self, guild_id: u64, poll_id: u64) -> Result<Option<Poll>, Error>;
+    /// Polls that have not been closed yet, across every guild or just one.
+    fn open_polls(&self, guild_id: Option<u64>) -> Result<Vec<Poll>, Error>;
+
+    /// Stores a new countdown and returns its number.
+    fn add_countdown(&self, countdown: &Countdown) -> Result<u64, Error>;
+    /// Inserts or replaces a countdown under its existing number.
+    fn save_countdown(&self, countdown: &Countdown) -> Result<(), Error>;
+    /// Countdowns that haven't finished or been cancelled, across every guild or just one.
+    fn active_countdowns(&self, guild_id: Option<u64>) -> Result<Vec<Countdown>, Error>;
+
+    /// A member's dice macros, sorted by name.
+    fn dice_macros(&self, guild_id: u64, user_id: u64) -> Result<Vec<DiceMacro>, Error>;
+    /// Adds a macro or replaces the member's macro with the same name.
+    fn save_dice_macro(&self, dice_macro: &DiceMacro) -> Result<(), Error>;
+    /// Returns whether the member had a macro with that name.
+    fn delete_dice_macro(&self, guild_id: u64, user_id: u64, name: &str) -> Result<bool, Error>;
+    /// Records a roll, keeping only the member's latest `DICE_ROLLS_KEPT` in the guild.
+    fn add_dice_roll(&self, roll: &DiceRoll) -> Result<(), Error>;
+    /// A member's most recent rolls in a guild, newest first.
+    fn dice_rolls(&self, guild_id: u64, user_id: u64, limit: usize) -> Result<Vec<DiceRoll>, Error>;
+}
+
+pub fn init(config: &StorageConfig) -> Result<(), Error> {
+    let storage: Box<dyn Storage> = match config.backend {
+        StorageBackend::Files => Box::new(FileStorage),
+        StorageBackend::Sqlite => Box::new(SqliteStorage::open(&config.path)?),
+    };
+    STORAGE.set(storage).map_err(|_| "Storage was already initialised")?;
+    Ok(())
+}
+
+/// The storage backend picked in `cluster.toml`, or the file layout if `init` wasn't called.
+pub fn get() -> &'static dyn Storage {
+    STORAGE.get_or_init(|| Box::new(FileStorage)).as_ref()
+}
+
+/// The original layout: `config.toml`, one TOML file per member under `user_stats/`,
+/// one text file per guild under `ticket_templates/`, and per-guild TOML files for
+/// ticket records and moderation cases.
+pub struct FileStorage;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TicketRecordFile {
+    #[serde(default)]
+    tickets: Vec<TicketRecord>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ModerationCaseFile {
+    #[serde(default)]
+    cases: Vec<ModerationCase>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PollFile {
+    #[serde(default)]
+    polls: Vec<Poll>,
+}
+
+fn polls_path(guild_id: u64) -> String {
+    format!("{POLLS_DIR}/polls_{guild_id}.toml")
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CountdownFile {
+    #[serde(default)]
+    countdowns: Vec<Countdown>,
+}
+
+fn countdowns_path(guild_id: u64) -> String {
+    format!("{COUNTDOWNS_DIR}/countdowns_{guild_id}.toml")
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DiceFile {
+    #[serde(default)]
+    macros: Vec<DiceMacro>,
+    /// Oldest first.
+    #[serde(default)]
+    rolls: Vec<DiceRoll>,
+}
+
+fn dice_path(guild_id: u64) -> String {
+    format!("{DICE_DIR}/dice_{guild_id}.toml")
+}
+
+/// Loads a guild's dice file, lets `update` change it, and writes it back if `update`
+/// returns true. Holds that file's lock throughout, so members rolling or saving
+/// macros at the same time don't overwrite each other.
+fn update_dice_file(guild_id: u64, update: impl FnOnce(&mut DiceFile) -> bool) -> Result<bool, Error> {
+    let lock = DICE_FILE_LOCKS.get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(guild_id)
+        .or_default()
+        .clone();
+    let _guard = lock.lock().unwrap();
+    let path = dice_path(guild_id);
+    let mut file: DiceFile = read_toml_or_default(&path)?;
+    if !update(&mut file) {
+        return Ok(false);
+    }
+    write_toml(DICE_DIR, &path, &file)?;
+    Ok(true)
+}
+
+fn stats_path(guild_id: u64, user_id: u64) -> String {
+    format!("{USER_STATS_DIR}/stats_{guild_id}_{user_id}.toml")
+}
+
+fn stat_events_path(guild_id: u64) -> String {
+    format!("{STAT_EVENTS_DIR}/events_{guild_id}.jsonl")
+}
+
+fn sort_leaderboard(totals: std::collections::HashMap<u64, i64>) -> Vec<(u64, i64)> {
+    let mut leaderboard: Vec<(u64, i64)> = totals.into_iter().collect();
+    leaderboard.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    leaderboard
+}
+
+fn read_toml_or_default<T: Default + serde::de::DeserializeOwned>(path: &str) -> Result<T, Error> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(toml::from_str(&content).map_err(|e| format!("{path} is invalid: {e}"))?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_toml(dir: &str, path: &str, value: &impl Serialize) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+    write_atomic(path, toml::to_string_pretty(value)?.as_bytes())?;
+    Ok(())
+}
+
+impl FileStorage {
+    fn read_stats(&self, guild_id: u64, user_id: u64) -> Result<toml::Table, Error> {
+        read_toml_or_default(&stats_path(guild_id, user_id))
+    }
+
+    /// Every stat file as (guild, user, stats), for the importer.
+    fn all_stats(&self) -> Result<Vec<(u64, u64, toml::Table)>, Error> {
+        let mut result = Vec::new();
+        let Ok(entries) = fs::read_dir(USER_STATS_DIR) else {
+            return Ok(result);
+        };
+        for entry in entries {
+            let file_name = entry?.file_name().to_string_lossy().into_owned();
+            let Some(ids) = file_name.strip_prefix("stats_").and_then(|s| s.strip_suffix(".toml")) else {
+                continue;
+            };
+            let Some((guild_id, user_id)) = ids.split_once('_')
+                .and_then(|(g, u)| Some((g.parse().ok()?, u.parse().ok()?)))
+            else {
+                println!("Skipping unrecognised stats file {file_name}");
+                continue;
+            };
+            result.push((guild_id, user_id, self.read_stats(guild_id, user_id)?));
+        }
+        Ok(result)
+    }
+
+    /// Stat events are appended one JSON object per line, so logging a change never
+    /// rewrites the history before it.
+    fn append_stat_event(&self, event: &StatEvent) -> Result<(), Error> {
+        fs::create_dir_all(STAT_EVENTS_DIR)?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(stat_events_path(event.guild_id))?;
+        writeln!(file, "{}", serde_json::to_string(event)?)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn stat_events(&self, guild_id: u64) -> Result<Vec<StatEvent>, Error> {
+        self.read_stat_events(&stat_events_path(guild_id))
+    }
+
+    fn read_stat_events(&self, path: &str) -> Result<Vec<StatEvent>, Error> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut events = Vec::new();
+        for (number, line) in std::io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<StatEvent>(&line) {
+                Ok(event) => events.push(StatEvent { id: number as u64 + 1, ..event }),
+                // A crash mid-append can leave a partial last line; skip it rather than lose the log.
+                Err(e) => println!("Skipping unreadable line {} of {path}: {e}", number + 1),
+            }
+        }
+        Ok(events)
+    }
+
+    /// Every (guild, file path) in a per-guild directory whose files are named `<prefix><guild_id><suffix>`.
+    fn guild_files(&self, dir: &str, prefix: &str, suffix: &str) -> Result<Vec<(u64, String)>, Error> {
+        let mut result = Vec::new();
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Ok(result);
+        };
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(guild_id) = file_name.strip_prefix(prefix)
+                .and_then(|s| s.strip_suffix(suffix))
+                .and_then(|s| s.parse::<u64>().ok())
+            {
+                result.push((guild_id, entry.path().to_string_lossy().into_owned()));
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Storage for FileStorage {
+    fn load_config(&self) -> Result<Option<String>, Error> {
+        match fs::read_to_string(CONFIG_PATH) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read {CONFIG_PATH}: {e}").into()),
+        }
+    }
+
+    fn save_config(&self, content: &str) -> Result<(), Error> {
+        if fs::metadata(CONFIG_PATH).is_ok() {
+            fs::copy(CONFIG_PATH, CONFIG_BACKUP_PATH)?;
+        }
+        write_atomic(CONFIG_PATH, content.as_bytes())?;
+        Ok(())
+    }
+
+    fn backup_config(&self, label: &str, content: &str) -> Result<(), Error> {
+        write_atomic(format!("{CONFIG_PATH}.{label}.bak"), content.as_bytes())?;
+        Ok(())
+    }
+
+    fn get_stat(&self, guild_id: u64, user_id: u64, stat_name: &str) -> Result<i64, Error> {
+        Ok(self.read_stats(guild_id, user_id)?
+            .get(stat_name)
+            .and_then(|v| v.as_integer())
+            .unwrap_or(0))
+    }
+
+    fn set_stat(&self, guild_id: u64, user_id: u64, stat_name: &str, value: i64) -> Result<(), Error> {
+        let mut stats = self.read_stats(guild_id, user_id)?;
+        stats.insert(stat_name.to_owned(), Value::Integer(value));
+        write_toml(USER_STATS_DIR, &stats_path(guild_id, user_id), &stats)
+    }
+
+    fn add_to_stat(&self, event: &StatEvent) -> Result<i64, Error> {
+        let new_val = self.get_stat(event.guild_id, event.user_id, &event.stat)?
+            .checked_add(event.delta)
+            .ok_or("Stat value would overflow")?;
+        self.set_stat(event.guild_id, event.user_id, &event.stat, new_val)?;
+        self.append_stat_event(event)?;
+        Ok(new_val)
+    }
+
+    fn user_stats(&self, guild_id: u64, user_id: u64) -> Result<Vec<(String, i64)>, Error> {
+        Ok(self.read_stats(guild_id, user_id)?
+            .into_iter()
+            .filter_map(|(k, v)| v.as_integer().map(|val| (k, val)))
+            .collect())
+    }
+
+    fn user_stats_since(&self, guild_id: u64, user_id: u64, since: i64) -> Result<Vec<(String, i64)>, Error> {
+        let mut totals = std::collections::BTreeMap::new();
+        for event in self.stat_events(guild_id)? {
+            if event.user_id == user_id && event.timestamp >= since {
+                let total = totals.entry(event.stat).or_insert(0i64);
+                *total = total.checked_add(event.delta).ok_or("Stat total would overflow")?;
+            }
+        }
+        Ok(totals.into_iter().collect())
+    }
+
+    fn stat_history(&self, guild_id: u64, user_id: Option<u64>, stat_name: Option<&str>, limit: usize) -> Result<Vec<StatEvent>, Error> {
+        Ok(self.stat_events(guild_id)?
+            .into_iter()
+            .rev()
+            .filter(|event| user_id.is_none_or(|user_id| event.user_id == user_id))
+            .filter(|event| stat_name.is_none_or(|stat_name| event.stat == stat_name))
+            .take(limit)
+            .collect())
+    }
+
+    fn stat_leaderboard(&self, guild_id: u64, stat_name: &str, since: Option<i64>) -> Result<Vec<(u64, i64)>, Error> {
+        let mut totals = std::collections::HashMap::new();
+        match since {
+            Some(since) => {
+                for event in self.stat_events(guild_id)? {
+                    if event.stat == stat_name && event.timestamp >= since {
+                        let total = totals.entry(event.user_id).or_insert(0i64);
+                        *total = total.checked_add(event.delta).ok_or("Stat total would overflow")?;
+                    }
+                }
+            }
+            None => {
+                for (stats_guild_id, user_id, stats) in self.all_stats()? {
+                    if stats_guild_id != guild_id {
+                        continue;
+                    }
+                    if let Some(value) = stats.get(stat_name).and_then(|v| v.as_integer()) {
+                        totals.insert(user_id, value);
+                    }
+                }
+            }
+        }
+        Ok(sort_leaderboard(totals))
+    }
+
+    fn guild_stats(&self, guild_id: u64) -> Result<Vec<(u64, String, i64)>, Error> {
+        let mut result = Vec::new();
+        for (stats_guild_id, user_id, stats) in self.all_stats()? {
+            if stats_guild_id != guild_id {
+                continue;
+            }
+            for (stat_name, value) in stats {
+                if let Some(value) = value.as_integer() {
+                    result.push((user_id, stat_name, value));
+                }
+            }
+        }
+        result.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        Ok(result)
+    }
+
+    fn stat_event(&self, guild_id: u64, event_id: u64) -> Result<Option<StatEvent>, Error> {
+        Ok(self.stat_events(guild_id)?.into_iter().find(|event| event.id == event_id))
+    }
+
+    fn rename_stat(&self, guild_id: u64, from: &str, to: &str) -> Result<usize, Error> {
+        // Merge everything before writing, so an overflow leaves no member half renamed.
+        let mut merged = Vec::new();
+        for (stats_guild_id, user_id, mut stats) in self.all_stats()? {
+            if stats_guild_id != guild_id {
+                continue;
+            }
+            let Some(value) = stats.remove(from).and_then(|v| v.as_integer()) else {
+                continue;
+            };
+            let existing = stats.get(to).and_then(|v| v.as_integer()).unwrap_or(0);
+            let total = existing.checked_add(value).ok_or("Stat value would overflow")?;
+            stats.insert(to.to_owned(), Value::Integer(total));
+            merged.push((user_id, stats));
+        }
+        let renamed = merged.len();
+        for (user_id, stats) in merged {
+            write_toml(USER_STATS_DIR, &stats_path(guild_id, user_id), &stats)?;
+        }
+        let path = stat_events_path(guild_id);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(renamed),
+            Err(e) => return Err(e.into()),
+        };
+        // Rewrite line by line so event ids (line numbers) stay the same.
+        let mut rewritten = String::with_capacity(content.len());
+        for line in content.lines() {
+            match serde_json::from_str::<StatEvent>(line) {
+                Ok(event) if event.stat == from => {
+                    rewritten.push_str(&serde_json::to_string(&StatEvent { stat: to.to_owned(), ..event })?);
+                }
+                _ => rewritten.push_str(line),
+            }
+            rewritten.push('\n');
+        }
+        write_atomic(&path, rewritten.as_bytes())?;
+        Ok(renamed)
+    }
+
+    fn ticket_template(&self, guild_id: u64) -> Result<Option<String>, Error> {
+        match fs::read_to_string(crate::utils::get_ticket_template_path(guild_id)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set_ticket_template(&self, guild_id: u64, content: &str) -> Result<(), Error> {
+        fs::create_dir_all(TICKET_TEMPLATES_DIR)?;
+        write_atomic(crate::utils::get_ticket_template_path(guild_id), content.as_bytes())?;
+        Ok(())
+    }
+
+    fn open_ticket(&self, record: &TicketRecord) -> Result<(), Error> {
+        let path = format!("{TICKET_RECORDS_DIR}/tickets_{}.toml", record.guild_id);
+        let mut file: TicketRecordFile = read_toml_or_default(&path)?;
+        file.tickets.retain(|ticket| ticket.channel_id != record.channel_id);
+        file.tickets.push(record.clone());
+        write_toml(TICKET_RECORDS_DIR, &path, &file)
+    }
+
+    fn close_ticket(&self, guild_id: u64, channel_id: u64, closed_by: &str, reason: &str, closed_at: i64) -> Result<(), Error> {
+        let path = format!("{TICKET_RECORDS_DIR}/tickets_{guild_id}.toml");
+        let mut file: TicketRecordFile = read_toml_or_default(&path)?;
+        let Some(ticket) = file.tickets.iter_mut().find(|ticket| ticket.channel_id == channel_id) else {
+            return Ok(());
+        };
+        ticket.closed_at = Some(closed_at);
+        ticket.closed_by = Some(closed_by.to_owned());
+        ticket.close_reason = Some(reason.to_owned());
+        write_toml(TICKET_RECORDS_DIR, &path, &file)
+    }
+
+    fn add_case(&self, case: &ModerationCase) -> Result<u64, Error> {
+        let path = format!("{MODERATION_CASES_DIR}/cases_{}.toml", case.guild_id);
+        let mut file: ModerationCaseFile = read_toml_or_default(&path)?;
+        let case_id = file.cases.iter().map(|case| case.case_id).max().unwrap_or(0) + 1;
+        file.cases.push(ModerationCase { case_id, ..case.clone() });
+        write_toml(MODERATION_CASES_DIR, &path, &file)?;
+        Ok(case_id)
+    }
+
+    fn add_poll(&self, poll: &Poll) -> Result<u64, Error> {
+        let path = polls_path(poll.guild_id);
+        let mut file: PollFile = read_toml_or_default(&path)?;
+        let poll_id = file.polls.iter().map(|poll| poll.poll_id).max().unwrap_or(0) + 1;
+        file.polls.push(Poll { poll_id, ..poll.clone() });
+        write_toml(POLLS_DIR, &path, &file)?;
+        Ok(poll_id)
+    }
+
+    fn save_poll(&self, poll: &Poll) -> Result<(), Error> {
+        let path = polls_path(poll.guild_id);
+        let mut file: PollFile = read_toml_or_default(&path)?;
+        file.polls.retain(|other| other.poll_id != poll.poll_id);
+        file.polls.push(poll.clone());
+        file.polls.sort_by_key(|poll| poll.poll_id);
+        write_toml(POLLS_DIR, &path, &file)
+    }
+
+    fn poll(&self, guild_id: u64, poll_id: u64) -> Result<Option<Poll>, Error> {
+        let file: PollFile = read_toml_or_default(&polls_path(guild_id))?;
+        Ok(file.polls.into_iter().find(|poll| poll.poll_id == poll_id))
+    }
+
+    fn open_polls(&self, guild_id: Option<u64>) -> Result<Vec<Poll>, Error> {
+        let paths = match guild_id {
+            Some(guild_id) => vec![polls_path(guild_id)],
+            None => self.guild_files(POLLS_DIR, "polls_", ".toml")?.into_iter().map(|(_, path)| path).collect(),
+        };
+        let mut open = Vec::new();
+        for path in paths {
+            let file: PollFile = read_toml_or_default(&path)?;
+            open.extend(file.polls.into_iter().filter(|poll| !poll.closed));
+        }
+        Ok(open)
+    }
+
+    fn add_countdown(&self, countdown: &Countdown) -> Result<u64, Error> {
+        let path = countdowns_path(countdown.guild_id);
+        let mut file: CountdownFile = read_toml_or_default(&path)?;
+        let countdown_id = file.countdowns.iter().map(|c| c.countdown_id).max().unwrap_or(0) + 1;
+        file.countdowns.push(Countdown { countdown_id, ..countdown.clone() });
+        write_toml(COUNTDOWNS_DIR, &path, &file)?;
+        Ok(countdown_id)
+    }
+
+    fn save_countdown(&self, countdown: &Countdown) -> Result<(), Error> {
+        let path = countdowns_path(countdown.guild_id);
+        let mut file: CountdownFile = read_toml_or_default(&path)?;
+        file.countdowns.retain(|other| other.countdown_id != countdown.countdown_id);
+        file.countdowns.push(countdown.clone());
+        file.countdowns.sort_by_key(|c| c.countdown_id);
+        write_toml(COUNTDOWNS_DIR, &path, &file)
+    }
+
+    fn active_countdowns(&self, guild_id: Option<u64>) -> Result<Vec<Countdown>, Error> {
+        let paths = match guild_id {
+            Some(guild_id) => vec![countdowns_path(guild_id)],
+            None => self.guild_files(COUNTDOWNS_DIR, "countdowns_", ".toml")?.into_iter().map(|(_, path)| path).collect(),
+        };
+        let mut active = Vec::new();
+        for path in paths {
+            let file: CountdownFile = read_toml_or_default(&path)?;
+            active.extend(file.countdowns.into_iter().filter(|c| !c.finished));
+        }
+        Ok(active)
+    }
+
+    fn dice_macros(&self, guild_id: u64, user_id: u64) -> Result<Vec<DiceMacro>, Error> {
+        let file: DiceFile = read_toml_or_default(&dice_path(guild_id))?;
+        let mut macros: Vec<DiceMacro> = file.macros.into_iter().filter(|m| m.user_id == user_id).collect();
+        macros.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(macros)
+    }
+
+    fn save_dice_macro(&self, dice_macro: &DiceMacro) -> Result<(), Error> {
+        update_dice_file(dice_macro.guild_id, |file| {
+            file.macros.retain(|m| m.user_id != dice_macro.user_id || m.name != dice_macro.name);
+            file.macros.push(dice_macro.clone());
+            true
+        })?;
+        Ok(())
+    }
+
+    fn delete_dice_macro(&self, guild_id: u64, user_id: u64, name: &str) -> Result<bool, Error> {
+        update_dice_file(guild_id, |file| {
+            let before = file.macros.len();
+            file.macros.retain(|m| m.user_id != user_id || m.name != name);
+            file.macros.len() != before
+        })
+    }
+
+    fn add_dice_roll(&self, roll: &DiceRoll) -> Result<(), Error> {
+        update_dice_file(roll.guild_id, |file| {
+            file.rolls.push(roll.clone());
+            let user_rolls = file.rolls.iter().filter(|r| r.user_id == roll.user_id).count();
+            let mut excess = user_rolls.saturating_sub(DICE_ROLLS_KEPT);
+            file.rolls.retain(|r| {
+                let drop = excess > 0 && r.user_id == roll.user_id;
+                if drop {
+                    excess -= 1;
+                }
+                !drop
+            });
+            true
+        })?;
+        Ok(())
+    }
+
+    fn dice_rolls(&self, guild_id: u64, user_id: u64, limit: usize) -> Result<Vec<DiceRoll>, Error> {
+        let file: DiceFile = read_toml_or_default(&dice_path(guild_id))?;
+        Ok(file.rolls.into_iter().rev().filter(|r| r.user_id == user_id).take(limit).collect())
+    }
+}
+
+const INSERT_STAT_EVENT: &str = "INSERT INTO stat_events
+    (guild_id, user_id, stat, delta, created_at, source, awarded_by, reason)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
+
+fn stat_event_params(event: &StatEvent) -> impl rusqlite::Params + '_ {
+    (
+        event.guild_id as i64,
+        event.user_id as i64,
+        event.stat.as_str(),
+        event.delta,
+        event.timestamp,
+        event.source.as_str(),
+        event.awarded_by.map(|id| id as i64),
+        event.reason.as_deref(),
+    )
+}
+
+fn stat_event_from_row(row: &rusqlite::Row) -> rusqlite::Result<StatEvent> {
+    Ok(StatEvent {
+        id: row.get::<_, i64>(0)? as u64,
+        guild_id: row.get::<_, i64>(1)? as u64,
+        user_id: row.get::<_, i64>(2)? as u64,
+        stat: row.get(3)?,
+        delta: row.get(4)?,
+        timestamp: row.get(5)?,
+        source: row.get(6)?,
+        awarded_by: row.get::<_, Option<i64>>(7)?.map(|id| id as u64),
+        reason: row.get(8)?,
+    })
+}
+
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = FULL;
+            CREATE TABLE IF NOT EXISTS config (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                content TEXT NOT NULL,
+                saved_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS config_backups (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                label TEXT NOT NULL,
+                content TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS stats (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                stat TEXT NOT NULL,
+                value INTEGER NOT NULL,
+                PRIMARY KEY (guild_id, user_id, stat)
+            );
+            CREATE TABLE IF NOT EXISTS stat_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                stat TEXT NOT NULL,
+                delta INTEGER NOT NULL,
+                created_at INTEGER NOT NULL,
+                source TEXT NOT NULL DEFAULT 'unknown',
+                awarded_by INTEGER,
+                reason TEXT
+            );
+            CREATE INDEX IF NOT EXISTS stat_events_by_stat ON stat_events (guild_id, stat, created_at);
+            CREATE TABLE IF NOT EXISTS ticket_templates (
+                guild_id INTEGER PRIMARY KEY,
+                content TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS tickets (
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                owner_id INTEGER NOT NULL,
+                issue TEXT NOT NULL,
+                opened_at INTEGER NOT NULL,
+                closed_at INTEGER,
+                closed_by TEXT,
+                close_reason TEXT,
+                PRIMARY KEY (guild_id, channel_id)
+            );
+            CREATE TABLE IF NOT EXISTS moderation_cases (
+                guild_id INTEGER NOT NULL,
+                case_id INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                target_id INTEGER NOT NULL,
+                moderator_id INTEGER NOT NULL,
+                reason TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                PRIMARY KEY (guild_id, case_id)
+            );
+            CREATE TABLE IF NOT EXISTS polls (
+                guild_id INTEGER NOT NULL,
+                poll_id INTEGER NOT NULL,
+                closed INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                PRIMARY KEY (guild_id, poll_id)
+            );
+            CREATE TABLE IF NOT EXISTS countdowns (
+                guild_id INTEGER NOT NULL,
+                countdown_id INTEGER NOT NULL,
+                finished INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                PRIMARY KEY (guild_id, countdown_id)
+            );
+            CREATE TABLE IF NOT EXISTS dice_macros (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                expression TEXT NOT NULL,
+                PRIMARY KEY (guild_id, user_id, name)
+            );
+            CREATE TABLE IF NOT EXISTS dice_rolls (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                expression TEXT NOT NULL,
+                total INTEGER NOT NULL,
+                crits INTEGER NOT NULL,
+                fumbles INTEGER NOT NULL,
+                rolled_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS dice_rolls_by_member ON dice_rolls (guild_id, user_id, id);",
+        )?;
+        // Columns added after the table was first created.
+        for (column, definition) in [
+            ("source", "TEXT NOT NULL DEFAULT 'unknown'"),
+            ("awarded_by", "INTEGER"),
+            ("reason", "TEXT"),
+        ] {
+            let exists: bool = connection.query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('stat_events') WHERE name = ?1",
+                params![column],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                connection.execute(&format!("ALTER TABLE stat_events ADD COLUMN {column} {definition}"), [])?;
+            }
+        }
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn insert_stat_event(&self, event: &StatEvent) -> Result<(), Error> {
+        self.connection().execute(
+            INSERT_STAT_EVENT,
+            stat_event_params(event),
+        )?;
+        Ok(())
+    }
+
+    fn insert_case(&self, case: &ModerationCase) -> Result<(), Error> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO moderation_cases
+                (guild_id, case_id, action, target_id, moderator_id, reason, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                case.guild_id as i64,
+                case.case_id as i64,
+                case.action,
+                case.target_id as i64,
+                case.moderator_id as i64,
+                case.reason,
+                case.created_at,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load_config(&self) -> Result<Option<String>, Error> {
+        Ok(self.connection()
+            .query_row("SELECT content FROM config WHERE id = 1", [], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn save_config(&self, content: &str) -> Result<(), Error> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let now = chrono::Utc::now().timestamp();
+        transaction.execute(
+            "INSERT INTO config_backups (label, content, created_at)
+                SELECT 'previous', content, ?1 FROM config WHERE id = 1",
+            params![now],
+        )?;
+        transaction.execute(
+            "INSERT INTO config (id, content, saved_at) VALUES (1, ?1, ?2)
+                ON CONFLICT (id) DO UPDATE SET content = excluded.content, saved_at = excluded.saved_at",
+            params![content, now],
+        )?;
+        transaction.execute(
+            "DELETE FROM config_backups WHERE label = 'previous' AND id NOT IN
+                (SELECT id FROM config_backups WHERE label = 'previous' ORDER BY id DESC LIMIT ?1)",
+            params![CONFIG_BACKUPS_KEPT],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn backup_config(&self, label: &str, content: &str) -> Result<(), Error> {
+        self.connection().execute(
+            "INSERT INTO config_backups (label, content, created_at) VALUES (?1, ?2, ?3)",
+            params![label, content, chrono::Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    fn get_stat(&self, guild_id: u64, user_id: u64, stat_name: &str) -> Result<i64, Error> {
+        Ok(self.connection()
+            .query_row(
+                "SELECT value FROM stats WHERE guild_id = ?1 AND user_id = ?2 AND stat = ?3",
+                params![guild_id as i64, user_id as i64, stat_name],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(0))
+    }
+
+    fn set_stat(&self, guild_id: u64, user_id: u64, stat_name: &str, value: i64) -> Result<(), Error> {
+        self.connection().execute(
+            "INSERT INTO stats (guild_id, user_id, stat, value) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (guild_id, user_id, stat) DO UPDATE SET value = excluded.value",
+            params![guild_id as i64, user_id as i64, stat_name, value],
+        )?;
+        Ok(())
+    }
+
+    fn add_to_stat(&self, event: &StatEvent) -> Result<i64, Error> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        // SQLite turns an overflowing sum into a float, so the addition is checked here.
+        let current: i64 = transaction.query_row(
+            "SELECT value FROM stats WHERE guild_id = ?1 AND user_id = ?2 AND stat = ?3",
+            params![event.guild_id as i64, event.user_id as i64, event.stat],
+            |row| row.get(0),
+        ).optional()?.unwrap_or(0);
+        let new_val = current.checked_add(event.delta).ok_or("Stat value would overflow")?;
+        transaction.execute(
+            "INSERT INTO stats (guild_id, user_id, stat, value) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (guild_id, user_id, stat) DO UPDATE SET value = excluded.value",
+            params![event.guild_id as i64, event.user_id as i64, event.stat, new_val],
+        )?;
+        transaction.execute(INSERT_STAT_EVENT, stat_event_params(event))?;
+        transaction.commit()?;
+        Ok(new_val)
+    }
+
+    fn user_stats(&self, guild_id: u64, user_id: u64) -> Result<Vec<(String, i64)>, Error> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT stat, value FROM stats WHERE guild_id = ?1 AND user_id = ?2 ORDER BY stat",
+        )?;
+        let rows = statement.query_map(params![guild_id as i64, user_id as i64], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn user_stats_since(&self, guild_id: u64, user_id: u64, since: i64) -> Result<Vec<(String, i64)>, Error> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT stat, SUM(delta) FROM stat_events
+                WHERE guild_id = ?1 AND user_id = ?2 AND created_at >= ?3
+                GROUP BY stat ORDER BY stat",
+        )?;
+        let rows = statement.query_map(params![guild_id as i64, user_id as i64, since], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn stat_history(&self, guild_id: u64, user_id: Option<u64>, stat_name: Option<&str>, limit: usize) -> Result<Vec<StatEvent>, Error> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT id, guild_id, user_id, stat, delta, created_at, source, awarded_by, reason FROM stat_events
+                WHERE guild_id = ?1 AND (?2 IS NULL OR user_id = ?2) AND (?3 IS NULL OR stat = ?3)
+                ORDER BY id DESC LIMIT ?4",
+        )?;
+        let rows = statement.query_map(
+            params![guild_id as i64, user_id.map(|id| id as i64), stat_name, limit as i64],
+            stat_event_from_row,
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn stat_leaderboard(&self, guild_id: u64, stat_name: &str, since: Option<i64>) -> Result<Vec<(u64, i64)>, Error> {
+        let connection = self.connection();
+        let mut statement = match since {
+            Some(_) => connection.prepare(
+                "SELECT user_id, SUM(delta) AS total FROM stat_events
+                    WHERE guild_id = ?1 AND stat = ?2 AND created_at >= ?3
+                    GROUP BY user_id ORDER BY total DESC, user_id",
+            )?,
+            None => connection.prepare(
+                "SELECT user_id, value FROM stats WHERE guild_id = ?1 AND stat = ?2 AND ?3 IS NULL
+                    ORDER BY value DESC, user_id",
+            )?,
+        };
+        let rows = statement.query_map(params![guild_id as i64, stat_name, since], |row| {
+            Ok((row.get::<_, i64>(0)? as u64, row.get(1)?))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn guild_stats(&self, guild_id: u64) -> Result<Vec<(u64, String, i64)>, Error> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT user_id, stat, value FROM stats WHERE guild_id = ?1 ORDER BY stat, user_id",
+        )?;
+        let rows = statement.query_map(params![guild_id as i64], |row| {
+            Ok((row.get::<_, i64>(0)? as u64, row.get(1)?, row.get(2)?))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn stat_event(&self, guild_id: u64, event_id: u64) -> Result<Option<StatEvent>, Error> {
+        Ok(self.connection()
+            .query_row(
+                "SELECT id, guild_id, user_id, stat, delta, created_at, source, awarded_by, reason FROM stat_events
+                    WHERE guild_id = ?1 AND id = ?2",
+                params![guild_id as i64, event_id as i64],
+                stat_event_from_row,
+            )
+            .optional()?)
+    }
+
+    fn rename_stat(&self, guild_id: u64, from: &str, to: &str) -> Result<usize, Error> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        // Summed in Rust for the same reason as `add_to_stat`.
+        let merged: Vec<(i64, i64, Option<i64>)> = {
+            let mut statement = transaction.prepare(
+                "SELECT source.user_id, source.value, target.value FROM stats AS source
+                    LEFT JOIN stats AS target
+                        ON target.guild_id = source.guild_id AND target.user_id = source.user_id AND target.stat = ?3
+                    WHERE source.guild_id = ?1 AND source.stat = ?2",
+            )?;
+            let rows = statement.query_map(params![guild_id as i64, from, to], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?;
+            rows.collect::<Result<_, _>>()?
+        };
+        for (user_id, value, existing) in merged {
+            let total = existing.unwrap_or(0).checked_add(value).ok_or("Stat value would overflow")?;
+            transaction.execute(
+                "INSERT INTO stats (guild_id, user_id, stat, value) VALUES (?1, ?2, ?3, ?4)
+                    ON CONFLICT (guild_id, user_id, stat) DO UPDATE SET value = excluded.value",
+                params![guild_id as i64, user_id, to, total],
+            )?;
+        }
+        let renamed = transaction.execute(
+            "DELETE FROM stats WHERE guild_id = ?1 AND stat = ?2",
+            params![guild_id as i64, from],
+        )?;
+        transaction.execute(
+            "UPDATE stat_events SET stat = ?3 WHERE guild_id = ?1 AND stat = ?2",
+            params![guild_id as i64, from, to],
+        )?;
+        transaction.commit()?;
+        Ok(renamed)
+    }
+
+    fn ticket_template(&self, guild_id: u64) -> Result<Option<String>, Error> {
+        Ok(self.connection()
+            .query_row(
+                "SELECT content FROM ticket_templates WHERE guild_id = ?1",
+                params![guild_id as i64],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn set_ticket_template(&self, guild_id: u64, content: &str) -> Result<(), Error> {
+        self.connection().execute(
+            "INSERT INTO ticket_templates (guild_id, content) VALUES (?1, ?2)
+                ON CONFLICT (guild_id) DO UPDATE SET content = excluded.content",
+            params![guild_id as i64, content],
+        )?;
+        Ok(())
+    }
+
+    fn open_ticket(&self, record: &TicketRecord) -> Result<(), Error> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO tickets
+                (guild_id, channel_id, owner_id, issue, opened_at, closed_at, closed_by, close_reason)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                record.guild_id as i64,
+                record.channel_id as i64,
+                record.owner_id as i64,
+                record.issue,
+                record.opened_at,
+                record.closed_at,
+                record.closed_by,
+                record.close_reason,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn close_ticket(&self, guild_id: u64, channel_id: u64, closed_by: &str, reason: &str, closed_at: i64) -> Result<(), Error> {
+        self.connection().execute(
+            "UPDATE tickets SET closed_at = ?3, closed_by = ?4, close_reason = ?5
+                WHERE guild_id = ?1 AND channel_id = ?2",
+            params![guild_id as i64, channel_id as i64, closed_at, closed_by, reason],
+        )?;
+        Ok(())
+    }
+
+    fn add_case(&self, case: &ModerationCase) -> Result<u64, Error> {
+        let case_id: i64 = self.connection().query_row(
+            "INSERT INTO moderation_cases
+                (guild_id, case_id, action, target_id, moderator_id, reason, created_at)
+                VALUES (?1, (SELECT COALESCE(MAX(case_id), 0) + 1 FROM moderation_cases WHERE guild_id = ?1), ?2, ?3, ?4, ?5, ?6)
+                RETURNING case_id",
+            params![
+                case.guild_id as i64,
+                case.action,
+                case.target_id as i64,
+                case.moderator_id as i64,
+                case.reason,
+                case.created_at,
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(case_id as u64)
+    }
+
+    fn add_poll(&self, poll: &Poll) -> Result<u64, Error> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let poll_id: i64 = transaction.query_row(
+            "SELECT COALESCE(MAX(poll_id), 0) + 1 FROM polls WHERE guild_id = ?1",
+            params![poll.guild_id as i64],
+            |row| row.get(0),
+        )?;
+        let poll = Poll { poll_id: poll_id as u64, ..poll.clone() };
+        transaction.execute(
+            "INSERT INTO polls (guild_id, poll_id, closed, content) VALUES (?1, ?2, ?3, ?4)",
+            params![poll.guild_id as i64, poll_id, poll.closed, serde_json::to_string(&poll)?],
+        )?;
+        transaction.commit()?;
+        Ok(poll_id as u64)
+    }
+
+    fn save_poll(&self, poll: &Poll) -> Result<(), Error> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO polls (guild_id, poll_id, closed, content) VALUES (?1, ?2, ?3, ?4)",
+            params![poll.guild_id as i64, poll.poll_id as i64, poll.closed, serde_json::to_string(poll)?],
+        )?;
+        Ok(())
+    }
+
+    fn poll(&self, guild_id: u64, poll_id: u64) -> Result<Option<Poll>, Error> {
+        let content: Option<String> = self.connection()
+            .query_row(
+                "SELECT content FROM polls WHERE guild_id = ?1 AND poll_id = ?2",
+                params![guild_id as i64, poll_id as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(content.map(|content| serde_json::from_str(&content)).transpose()?)
+    }
+
+    fn open_polls(&self, guild_id: Option<u64>) -> Result<Vec<Poll>, Error> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT content FROM polls WHERE closed = 0 AND (?1 IS NULL OR guild_id = ?1) ORDER BY guild_id, poll_id",
+        )?;
+        let rows = statement.query_map(params![guild_id.map(|id| id as i64)], |row| row.get::<_, String>(0))?;
+        let mut open = Vec::new();
+        for content in rows {
+            open.push(serde_json::from_str(&content?)?);
+        }
+        Ok(open)
+    }
+
+    fn add_countdown(&self, countdown: &Countdown) -> Result<u64, Error> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let countdown_id: i64 = transaction.query_row(
+            "SELECT COALESCE(MAX(countdown_id), 0) + 1 FROM countdowns WHERE guild_id = ?1",
+            params![countdown.guild_id as i64],
+            |row| row.get(0),
+        )?;
+        let countdown = Countdown { countdown_id: countdown_id as u64, ..countdown.clone() };
+        transaction.execute(
+            "INSERT INTO countdowns (guild_id, countdown_id, finished, content) VALUES (?1, ?2, ?3, ?4)",
+            params![countdown.guild_id as i64, countdown_id, countdown.finished, serde_json::to_string(&countdown)?],
+        )?;
+        transaction.commit()?;
+        Ok(countdown_id as u64)
+    }
+
+    fn save_countdown(&self, countdown: &Countdown) -> Result<(), Error> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO countdowns (guild_id, countdown_id, finished, content) VALUES (?1, ?2, ?3, ?4)",
+            params![countdown.guild_id as i64, countdown.countdown_id as i64, countdown.finished, serde_json::to_string(countdown)?],
+        )?;
+        Ok(())
+    }
+
+    fn active_countdowns(&self, guild_id: Option<u64>) -> Result<Vec<Countdown>, Error> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT content FROM countdowns WHERE finished = 0 AND (?1 IS NULL OR guild_id = ?1) ORDER BY guild_id, countdown_id",
+        )?;
+        let rows = statement.query_map(params![guild_id.map(|id| id as i64)], |row| row.get::<_, String>(0))?;
+        let mut active = Vec::new();
+        for content in rows {
+            active.push(serde_json::from_str(&content?)?);
+        }
+        Ok(active)
+    }
+
+    fn dice_macros(&self, guild_id: u64, user_id: u64) -> Result<Vec<DiceMacro>, Error> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT name, expression FROM dice_macros WHERE guild_id = ?1 AND user_id = ?2 ORDER BY name",
+        )?;
+        let rows = statement.query_map(params![guild_id as i64, user_id as i64], |row| {
+            Ok(DiceMacro { guild_id, user_id, name: row.get(0)?, expression: row.get(1)? })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn save_dice_macro(&self, dice_macro: &DiceMacro) -> Result<(), Error> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO dice_macros (guild_id, user_id, name, expression) VALUES (?1, ?2, ?3, ?4)",
+            params![dice_macro.guild_id as i64, dice_macro.user_id as i64, dice_macro.name, dice_macro.expression],
+        )?;
+        Ok(())
+    }
+
+    fn delete_dice_macro(&self, guild_id: u64, user_id: u64, name: &str) -> Result<bool, Error> {
+        let deleted = self.connection().execute(
+            "DELETE FROM dice_macros WHERE guild_id = ?1 AND user_id = ?2 AND name = ?3",
+            params![guild_id as i64, user_id as i64, name],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn add_dice_roll(&self, roll: &DiceRoll) -> Result<(), Error> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO dice_rolls (guild_id, user_id, expression, total, crits, fumbles, rolled_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                roll.guild_id as i64,
+                roll.user_id as i64,
+                roll.expression,
+                roll.total,
+                roll.crits,
+                roll.fumbles,
+                roll.rolled_at,
+            ],
+        )?;
+        transaction.execute(
+            "DELETE FROM dice_rolls WHERE guild_id = ?1 AND user_id = ?2 AND id NOT IN
+                (SELECT id FROM dice_rolls WHERE guild_id = ?1 AND user_id = ?2 ORDER BY id DESC LIMIT ?3)",
+            params![roll.guild_id as i64, roll.user_id as i64, DICE_ROLLS_KEPT as i64],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn dice_rolls(&self, guild_id: u64, user_id: u64, limit: usize) -> Result<Vec<DiceRoll>, Error> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT expression, total, crits, fumbles, rolled_at FROM dice_rolls
+                WHERE guild_id = ?1 AND user_id = ?2 ORDER BY id DESC LIMIT ?3",
+        )?;
+        let rows = statement.query_map(params![guild_id as i64, user_id as i64, limit.min(i64::MAX as usize) as i64], |row| {
+            Ok(DiceRoll {
+                guild_id,
+                user_id,
+                expression: row.get(0)?,
+                total: row.get(1)?,
+                crits: row.get(2)?,
+                fumbles: row.get(3)?,
+                rolled_at: row.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+/// Copies everything from the file layout into a SQLite database. Safe to rerun:
+/// existing rows for the same keys are overwritten, not duplicated.
+pub fn import_files(database_path: &str) -> Result<(), Error> {
+    let files = FileStorage;
+    let database = SqliteStorage::open(database_path)?;
+    if let Some(content) = files.load_config()? {
+        let (config, migrated_from) = crate::utils::BotConfig::parse(&content)
+            .map_err(|e| format!("{CONFIG_PATH} is invalid: {e}"))?;
+        if migrated_from.is_some() {
+            database.backup_config("imported", &content)?;
+        }
+        database.save_config(&toml::to_string_pretty(&config)?)?;
+        println!("Imported {CONFIG_PATH} ({} guilds)", config.guilds.len());
+    }
+    let mut stat_count = 0;
+    for (guild_id, user_id, stats) in files.all_stats()? {
+        for (stat_name, value) in stats {
+            if let Some(value) = value.as_integer() {
+                database.set_stat(guild_id, user_id, &stat_name, value)?;
+                stat_count += 1;
+            }
+        }
+    }
+    println!("Imported {stat_count} stat values from {USER_STATS_DIR}");
+    let mut event_count = 0;
+    for (guild_id, path) in files.guild_files(STAT_EVENTS_DIR, "events_", ".jsonl")? {
+        // Events have no natural key, so replace the guild's history instead of appending to it.
+        database.connection().execute("DELETE FROM stat_events WHERE guild_id = ?1", params![guild_id as i64])?;
+        for event in files.read_stat_events(&path)? {
+            database.insert_stat_event(&event)?;
+            event_count += 1;
+        }
+    }
+    println!("Imported {event_count} stat changes from {STAT_EVENTS_DIR}");
+    let templates = files.guild_files(TICKET_TEMPLATES_DIR, "", ".txt")?;
+    for (guild_id, path) in &templates {
+        database.set_ticket_template(*guild_id, &fs::read_to_string(path)?)?;
+    }
+    println!("Imported {} ticket templates from {TICKET_TEMPLATES_DIR}", templates.len());
+    let mut ticket_count = 0;
+    for (_, path) in files.guild_files(TICKET_RECORDS_DIR, "tickets_", ".toml")? {
+        let file: TicketRecordFile = read_toml_or_default(&path)?;
+        for record in &file.tickets {
+            database.open_ticket(record)?;
+            ticket_count += 1;
+        }
+    }
+    println!("Imported {ticket_count} ticket records from {TICKET_RECORDS_DIR}");
+    let mut case_count = 0;
+    for (_, path) in files.guild_files(MODERATION_CASES_DIR, "cases_", ".toml")? {
+        let file: ModerationCaseFile = read_toml_or_default(&path)?;
+        for case in &file.cases {
+            database.insert_case(case)?;
+            case_count += 1;
+        }
+    }
+    println!("Imported {case_count} moderation cases from {MODERATION_CASES_DIR}");
+    let mut poll_count = 0;
+    for (_, path) in files.guild_files(POLLS_DIR, "polls_", ".toml")? {
+        let file: PollFile = read_toml_or_default(&path)?;
+        for poll in &file.polls {
+            database.save_poll(poll)?;
+            poll_count += 1;
+        }
+    }
+    println!("Imported {poll_count} polls from {POLLS_DIR}");
+    let mut countdown_count = 0;
+    for (_, path) in files.guild_files(COUNTDOWNS_DIR, "countdowns_", ".toml")? {
+        let file: CountdownFile = read_toml_or_default(&path)?;
+        for countdown in &file.countdowns {
+            database.save_countdown(countdown)?;
+            countdown_count += 1;
+        }
+    }
+    println!("Imported {countdown_count} countdowns from {COUNTDOWNS_DIR}");
+    let (mut macro_count, mut roll_count) = (0, 0);
+    for (guild_id, path) in files.guild_files(DICE_DIR, "dice_", ".toml")? {
+        let file: DiceFile = read_toml_or_default(&path)?;
+        for dice_macro in &file.macros {
+            database.save_dice_macro(dice_macro)?;
+            macro_count += 1;
+        }
+        // Like stat events, rolls have no natural key, so the guild's history is replaced.
+        database.connection().execute("DELETE FROM dice_rolls WHERE guild_id = ?1", params![guild_id as i64])?;
+        for roll in &file.rolls {
+            database.add_dice_roll(roll)?;
+            roll_count += 1;
+        }
+    }
+    println!("Imported {macro_count} dice macros and {roll_count} dice rolls from {DICE_DIR}");
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(value: &impl Serialize) -> serde_json::Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    fn stat_event(user_id: u64, stat: &str, delta: i64) -> StatEvent {
+        StatEvent {
+            id: 0,
+            guild_id: 1,
+            user_id,
+            stat: stat.to_owned(),
+            delta,
+            timestamp: 1_700_000_000,
+            source: "/stat add".to_owned(),
+            awarded_by: Some(9),
+            reason: None,
+        }
+    }
+
+    // The file layout lives in the working directory, so this is the only test that
+    // touches it and it works in a scratch directory of its own.
+    #[test]
+    fn imports_the_file_layout_into_sqlite() {
+        let dir = std::env::temp_dir().join(format!("archbot-import-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+        let files = FileStorage;
+        files.save_config("[1]\nlogging_channel = 5\n").unwrap();
+        files.add_to_stat(&stat_event(2, "solves", 3)).unwrap();
+        files.add_to_stat(&stat_event(2, "solves", 4)).unwrap();
+        files.add_to_stat(&stat_event(3, "bugs", -1)).unwrap();
+        files.set_ticket_template(1, "Welcome!").unwrap();
+        let poll_id = files.add_poll(&Poll {
+            guild_id: 1,
+            poll_id: 0,
+            channel_id: 6,
+            message_id: Some(7),
+            created_by: 2,
+            question: "Tea or coffee?".to_owned(),
+            options: vec!["tea".to_owned(), "coffee".to_owned()],
+            kind: PollKind::Ranked,
+            anonymous: false,
+            ends_at: 1_800_000_000,
+            closed: false,
+            votes: vec![PollVote { user_id: 3, choices: vec![1, 0] }],
+        }).unwrap();
+        files.add_countdown(&Countdown {
+            guild_id: 1,
+            countdown_id: 0,
+            channel_id: 6,
+            message_id: 8,
+            started_by: 2,
+            start: 10,
+            current: 10,
+            max_chance: 0.25,
+            ending: "0 <@2>".to_owned(),
+            started_at: 1_700_000_000,
+            finished: false,
+        }).unwrap();
+        files.save_dice_macro(&DiceMacro { guild_id: 1, user_id: 2, name: "fireball".to_owned(), expression: "8d6".to_owned() }).unwrap();
+        files.add_dice_roll(&DiceRoll { guild_id: 1, user_id: 2, expression: "1d20".to_owned(), total: 20, crits: 1, fumbles: 0, rolled_at: 1_700_000_000 }).unwrap();
+
+        // Importing twice must not duplicate history that has no natural key.
+        import_files("archbot.db").unwrap();
+        import_files("archbot.db").unwrap();
+        let database = SqliteStorage::open("archbot.db").unwrap();
+
+        let (config, _) = crate::utils::BotConfig::parse(&database.load_config().unwrap().unwrap()).unwrap();
+        assert_eq!(config.guilds["1"].channels.default, Some(5));
+        for user_id in [2, 3] {
+            assert_eq!(database.user_stats(1, user_id).unwrap(), files.user_stats(1, user_id).unwrap());
+        }
+        assert_eq!(database.get_stat(1, 2, "solves").unwrap(), 7);
+        assert_eq!(json(&database.stat_history(1, None, None, 10).unwrap()), json(&files.stat_history(1, None, None, 10).unwrap()));
+        assert_eq!(database.stat_leaderboard(1, "solves", Some(0)).unwrap(), vec![(2, 7)]);
+        assert_eq!(database.ticket_template(1).unwrap().as_deref(), Some("Welcome!"));
+        assert_eq!(json(&database.poll(1, poll_id).unwrap()), json(&files.poll(1, poll_id).unwrap()));
+        assert_eq!(json(&database.active_countdowns(Some(1)).unwrap()), json(&files.active_countdowns(Some(1)).unwrap()));
+        assert_eq!(json(&database.dice_macros(1, 2).unwrap()), json(&files.dice_macros(1, 2).unwrap()));
+        assert_eq!(json(&database.dice_rolls(1, 2, 10).unwrap()), json(&files.dice_rolls(1, 2, 10).unwrap()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
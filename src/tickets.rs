@@ -4,6 +4,7 @@ use poise::serenity_prelude::{self as serenity, CreateChannel, CreateEmbed, Crea
 use poise::serenity_prelude::{Permissions, PermissionOverwrite, PermissionOverwriteType};
 use poise::serenity_prelude::Mentionable;
 
+use crate::storage::TicketRecord;
 use crate::utils::*;
 
 pub async fn close_ticket_routine(
@@ -22,12 +23,21 @@ pub async fn close_ticket_routine(
         .field("Closed At", format!("<t:{}:F>", chrono::Utc::now().timestamp()), true)
         .color(serenity::Colour::DARK_RED);
     let _ = channel_id.send_message(&ctx.http, CreateMessage::new().embed(embed)).await;
+    if let Err(e) = crate::storage::get().close_ticket(
+        guild_id.into(),
+        channel_id.into(),
+        &closer_name,
+        &reason,
+        chrono::Utc::now().timestamp(),
+    ) {
+        println!("Failed to record closing ticket {channel_id}: {e}");
+    }
     if let Some(log_channel) = get_logging_channel(guild_id.into(), LogEventType::TicketActivity).await {
         let mut log_embed = CreateEmbed::new()
             .title("Ticket Closed")
             .description(format!("[Original Ticket]({})", channel_id.mention()))
             .field("Ticket Name", &channel_name, true)
-            .field("Closed By", format!("{} ({})", closer_name, closer_id.map(|id| id.to_string()).unwrap_or_else(|| "System".to_string())), true)
+            .field("Closed By", format!("{} ({})", closer_name, closer_id.map(|id| id.to_string()).unwrap_or_else(|| "System".to_owned())), true)
             .field("Reason", &reason, false)
             .color(serenity::Colour::DARK_RED);
         if closer_id.is_none() {
@@ -77,10 +87,6 @@ pub async fn ticket(
     #[description = "Describe your issue"] issue: Option<String>,
 ) -> Result<(), Error> {
     let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     let author = ctx.author();
     let guild_id_u64: u64 = guild_id.into();
@@ -147,6 +153,7 @@ pub async fn ticket(
             kind: PermissionOverwriteType::Role(serenity::RoleId::new(role_id)),
         });
     }
+    let token = crate::cluster::confirm_leader(ctx).await?;
     let channel = match guild_id.create_channel(&ctx.http(),
         CreateChannel::new(&channel_name)
             .kind(serenity::ChannelType::Text)
@@ -161,6 +168,19 @@ pub async fn ticket(
         }
     };
     channel.say(&ctx.http(), format!("{} created this ticket", author.mention())).await?;
+    let record = TicketRecord {
+        guild_id: guild_id_u64,
+        channel_id: channel.id.into(),
+        owner_id: author_id_u64,
+        issue: issue_description.clone(),
+        opened_at: chrono::Utc::now().timestamp(),
+        closed_at: None,
+        closed_by: None,
+        close_reason: None,
+    };
+    if let Err(e) = crate::storage::get().open_ticket(&record) {
+        println!("Failed to record ticket {}: {e}", channel.id);
+    }
     let show_message = match get_ticket_exempt_role(guild_id.into()).await {
         Some(exempt_role_id) => {
             let member = guild_id.member(&ctx.http(), author.id).await?;
@@ -169,9 +189,12 @@ pub async fn ticket(
         None => true,
     };
     if show_message {
-        let template_path = get_ticket_template_path(guild_id.into());
-        let message = std::fs::read_to_string(&template_path)
-            .unwrap_or_else(|_|
+        let message = crate::storage::get().ticket_template(guild_id.into())
+            .unwrap_or_else(|e| {
+                println!("Failed to read ticket template for guild {guild_id}: {e}");
+                None
+            })
+            .unwrap_or_else(||
                 "Thank you for creating a ticket! Support staff will be with you shortly.".to_string()
             );
         channel.say(&ctx.http(), message).await?;
@@ -191,6 +214,7 @@ pub async fn ticket(
             .description(format!("[Jump to Ticket]({})", channel.id.mention()))
             .field("Creator", format!("{} ({})", author.tag(), author.id), true)
             .field("Description", &issue_description, false)
+            .footer(serenity::CreateEmbedFooter::new(format!("Fencing token {token}")))
             .color(serenity::Colour::DARK_GREEN);
         log_channel.send_message(&ctx.http(),
             serenity::CreateMessage::new()
@@ -219,11 +243,6 @@ pub async fn closeticket(
     #[rest]
     reason: Option<String>,
 ) -> Result<(), Error> {
-    let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     let channel_id = ctx.channel_id();
     let closer = ctx.author();
@@ -276,11 +295,6 @@ pub async fn closeticket(
     guild_only
 )]
 pub async fn scantickets(ctx: Context<'_>) -> Result<(), Error> {
-    let data = ctx.data();
-    let cluster_state = data.cluster_state.lock().await;
-    if !cluster_state.is_leader {
-        return Ok(());
-    }
     let guild_id = ctx.guild_id().ok_or("This command must be used in a guild")?;
     let channels = guild_id.channels(&ctx.http()).await?;
     let mut closed_count = 0;
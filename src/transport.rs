@@ -0,0 +1,303 @@
+use crate::Error;
+use crate::cluster::ClusterMessage;
+use crate::signing::{Envelope, Signer};
+use async_trait::async_trait;
+use poise::serenity_prelude::{self as serenity, ChannelId};
+use serde::{Serialize, Deserialize};
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{timeout, Duration};
+
+const DISCORD_MESSAGE_LIMIT: usize = 2000;
+const ATTACHMENT_NAME: &str = "cluster_message.json";
+const PEER_CONNECT_TIMEOUT: u64 = 3;
+const MEMORY_HUB_CAPACITY: usize = 256;
+
+/// The hub `kind = "memory"` attaches to, shared by every instance in this process.
+static MEMORY_HUB: OnceLock<MemoryHub> = OnceLock::new();
+
+pub type Inbox = mpsc::UnboundedSender<Envelope>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    #[default]
+    Discord,
+    Tcp,
+    Unix,
+    Memory,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TransportConfig {
+    #[serde(default)]
+    pub kind: TransportKind,
+    #[serde(default)]
+    pub listen: Option<String>,
+    #[serde(default)]
+    pub peers: Vec<String>,
+}
+
+/// Carries `ClusterMessage`s between instances, sealed in signed `Envelope`s.
+/// Messages sent by an instance are not guaranteed to be delivered back to itself.
+#[async_trait]
+pub trait ClusterTransport: Send + Sync {
+    async fn send(&self, message: &ClusterMessage) -> Result<(), Error>;
+
+    /// Hands a gateway message to the transport. Only the Discord transport reads these.
+    async fn ingest(&self, _message: &serenity::Message) {}
+}
+
+pub async fn connect(
+    config: &TransportConfig,
+    http: Arc<serenity::Http>,
+    coordination_channel_id: Option<u64>,
+    signer: Arc<Signer>,
+    inbox: Inbox,
+) -> Result<Arc<dyn ClusterTransport>, Error> {
+    let transport: Arc<dyn ClusterTransport> = match config.kind {
+        TransportKind::Discord => {
+            let channel_id = coordination_channel_id
+                .ok_or("Missing coordination channel ID. Please set either COORDINATION_CHANNEL_ID environment variable or use --coordination argument")?;
+            Arc::new(DiscordTransport::new(http, channel_id, signer, inbox))
+        }
+        TransportKind::Tcp | TransportKind::Unix => {
+            Arc::new(PeerTransport::bind(config, signer, inbox).await?)
+        }
+        TransportKind::Memory => {
+            Arc::new(MEMORY_HUB.get_or_init(MemoryHub::new).attach(signer, inbox))
+        }
+    };
+    Ok(transport)
+}
+
+pub struct DiscordTransport {
+    http: Arc<serenity::Http>,
+    channel_id: ChannelId,
+    signer: Arc<Signer>,
+    inbox: Inbox,
+}
+
+impl DiscordTransport {
+    pub fn new(http: Arc<serenity::Http>, channel_id: u64, signer: Arc<Signer>, inbox: Inbox) -> Self {
+        DiscordTransport {
+            http,
+            channel_id: ChannelId::new(channel_id),
+            signer,
+            inbox,
+        }
+    }
+}
+
+#[async_trait]
+impl ClusterTransport for DiscordTransport {
+    async fn send(&self, message: &ClusterMessage) -> Result<(), Error> {
+        let payload = serde_json::to_string(&self.signer.seal(message)?)?;
+        if payload.len() <= DISCORD_MESSAGE_LIMIT {
+            self.channel_id.send_message(
+                &self.http,
+                serenity::CreateMessage::new().content(payload)
+            ).await?;
+        } else {
+            self.channel_id.send_files(
+                &self.http,
+                vec![serenity::CreateAttachment::bytes(payload.into_bytes(), ATTACHMENT_NAME)],
+                serenity::CreateMessage::new()
+            ).await?;
+        }
+        Ok(())
+    }
+
+    async fn ingest(&self, message: &serenity::Message) {
+        if message.channel_id != self.channel_id {
+            return;
+        }
+        let envelope = match serde_json::from_str::<Envelope>(&message.content) {
+            Ok(envelope) => envelope,
+            Err(_) => {
+                let Some(attachment) = message.attachments.iter().find(|a| a.filename == ATTACHMENT_NAME) else {
+                    if serde_json::from_str::<ClusterMessage>(&message.content).is_ok() {
+                        println!("Dropping unsigned cluster message posted by {}", message.author.tag());
+                    }
+                    return;
+                };
+                match attachment.download().await {
+                    Ok(bytes) => match serde_json::from_slice::<Envelope>(&bytes) {
+                        Ok(envelope) => envelope,
+                        Err(_) => {
+                            println!("Dropping unsigned cluster message attachment posted by {}", message.author.tag());
+                            return;
+                        }
+                    },
+                    Err(e) => {
+                        println!("Failed to download cluster message attachment: {e}");
+                        return;
+                    }
+                }
+            }
+        };
+        let _ = self.inbox.send(envelope);
+    }
+}
+
+/// Direct peer-to-peer transport over TCP or Unix sockets. Every message is written
+/// as one line of JSON on a fresh connection to each configured peer.
+pub struct PeerTransport {
+    kind: TransportKind,
+    peers: Vec<String>,
+    signer: Arc<Signer>,
+}
+
+impl PeerTransport {
+    pub async fn bind(config: &TransportConfig, signer: Arc<Signer>, inbox: Inbox) -> Result<Self, Error> {
+        let listen = config.listen.as_deref()
+            .ok_or("The tcp and unix cluster transports need a `listen` address in cluster.toml")?;
+        match config.kind {
+            TransportKind::Tcp => {
+                let listener = tokio::net::TcpListener::bind(listen).await?;
+                println!("Cluster transport listening on tcp {listen}");
+                tokio::spawn(async move {
+                    loop {
+                        match listener.accept().await {
+                            Ok((stream, _)) => {
+                                tokio::spawn(read_peer_stream(stream, inbox.clone()));
+                            }
+                            Err(e) => println!("Failed to accept cluster peer connection: {e}"),
+                        }
+                    }
+                });
+            }
+            #[cfg(unix)]
+            TransportKind::Unix => {
+                let _ = std::fs::remove_file(listen);
+                let listener = tokio::net::UnixListener::bind(listen)?;
+                println!("Cluster transport listening on unix socket {listen}");
+                tokio::spawn(async move {
+                    loop {
+                        match listener.accept().await {
+                            Ok((stream, _)) => {
+                                tokio::spawn(read_peer_stream(stream, inbox.clone()));
+                            }
+                            Err(e) => println!("Failed to accept cluster peer connection: {e}"),
+                        }
+                    }
+                });
+            }
+            _ => return Err(format!("{:?} is not a peer transport", config.kind).into()),
+        }
+        Ok(PeerTransport {
+            kind: config.kind,
+            peers: config.peers.clone(),
+            signer,
+        })
+    }
+
+    async fn send_to_peer(&self, peer: &str, line: &[u8]) -> Result<(), Error> {
+        let connect_timeout = Duration::from_secs(PEER_CONNECT_TIMEOUT);
+        match self.kind {
+            TransportKind::Tcp => {
+                let mut stream = timeout(connect_timeout, tokio::net::TcpStream::connect(peer)).await??;
+                write_line(&mut stream, line).await
+            }
+            #[cfg(unix)]
+            TransportKind::Unix => {
+                let mut stream = timeout(connect_timeout, tokio::net::UnixStream::connect(peer)).await??;
+                write_line(&mut stream, line).await
+            }
+            _ => Err(format!("{:?} is not a peer transport", self.kind).into()),
+        }
+    }
+}
+
+#[async_trait]
+impl ClusterTransport for PeerTransport {
+    async fn send(&self, message: &ClusterMessage) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(&self.signer.seal(message)?)?;
+        line.push(b'\n');
+        for peer in &self.peers {
+            if let Err(e) = self.send_to_peer(peer, &line).await {
+                println!("Failed to reach cluster peer {peer}: {e}");
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn write_line<S: AsyncWrite + Unpin>(stream: &mut S, line: &[u8]) -> Result<(), Error> {
+    stream.write_all(line).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn read_peer_stream<S: AsyncRead + Unpin>(stream: S, inbox: Inbox) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match serde_json::from_str::<Envelope>(&line) {
+            Ok(envelope) => {
+                let _ = inbox.send(envelope);
+            }
+            Err(e) => println!("Dropping unsigned or malformed cluster message from peer: {e}"),
+        }
+    }
+}
+
+/// In-process message bus. Every transport attached to the same hub receives what
+/// the others send, but not its own messages, like peers on a real transport.
+#[derive(Clone)]
+pub struct MemoryHub {
+    sender: broadcast::Sender<(u64, Envelope)>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl MemoryHub {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(MEMORY_HUB_CAPACITY);
+        MemoryHub {
+            sender,
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn attach(&self, signer: Arc<Signer>, inbox: Inbox) -> MemoryTransport {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut receiver = self.sender.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok((sender_id, _)) if sender_id == id => {}
+                    Ok((_, envelope)) => {
+                        if inbox.send(envelope).is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        println!("In-memory cluster transport dropped {skipped} messages");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        MemoryTransport {
+            id,
+            hub: self.sender.clone(),
+            signer,
+        }
+    }
+}
+
+pub struct MemoryTransport {
+    id: u64,
+    hub: broadcast::Sender<(u64, Envelope)>,
+    signer: Arc<Signer>,
+}
+
+#[async_trait]
+impl ClusterTransport for MemoryTransport {
+    async fn send(&self, message: &ClusterMessage) -> Result<(), Error> {
+        // Nobody else attached is not an error, just like an empty peer list.
+        let _ = self.hub.send((self.id, self.signer.seal(message)?));
+        Ok(())
+    }
+}
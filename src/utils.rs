@@ -1,34 +1,525 @@
 use poise::serenity_prelude::{self as serenity, ChannelId};
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, oneshot, RwLock};
 use std::{
     fs,
-    sync::Arc,
+    io::Write,
+    path::Path,
+    sync::{Arc, OnceLock},
+    sync::atomic::{AtomicBool, Ordering},
 };
 use toml::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use lazy_static::lazy_static;
 use serde::Deserialize;
 use serde::Serialize;
 
 pub const CONFIG_PATH: &str = "config.toml";
+pub const CONFIG_BACKUP_PATH: &str = "config.toml.bak";
 pub const CLUSTER_CONFIG_PATH: &str = "cluster.toml";
+const CONFIG_AUTOSAVE_INTERVAL: u64 = 60;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClusterConfig {
     pub cluster: ClusterInfo,
+    #[serde(default)]
+    pub transport: crate::transport::TransportConfig,
+    #[serde(default)]
+    pub storage: crate::storage::StorageConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClusterInfo {
     pub instance_id: String,
     pub priority: i32,
+    #[serde(default)]
+    pub secret: Option<String>,
 }
 
 lazy_static! {
-    static ref CONFIG_CACHE: Arc<RwLock<Value>> = Arc::new(RwLock::new(load_config_from_disk()));
+    static ref CONFIG_CACHE: Arc<RwLock<BotConfig>> = Arc::new(RwLock::new(BotConfig::default()));
 }
 
-pub fn load_cluster_config() -> Result<ClusterConfig, Box<dyn std::error::Error + Send + Sync>> {
+/// Set whenever `CONFIG_CACHE` changes and cleared when the writer task snapshots it.
+static CONFIG_DIRTY: AtomicBool = AtomicBool::new(false);
+static CONFIG_WRITER: OnceLock<mpsc::UnboundedSender<oneshot::Sender<Result<(), String>>>> = OnceLock::new();
+
+type ConfigError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Bumped whenever the layout of `config.toml` changes; see `CONFIG_MIGRATIONS`.
+pub const CONFIG_SCHEMA_VERSION: u64 = 2;
+
+type Migration = fn(&mut toml::Table) -> Result<(), ConfigError>;
+
+/// `CONFIG_MIGRATIONS[n]` upgrades a guild table from schema version `n` to `n + 1`.
+const CONFIG_MIGRATIONS: &[Migration] = &[
+    migrate_flat_channel_keys,
+    migrate_stat_list_to_definitions,
+];
+
+/// The whole `config.toml`: the schema version plus one table per guild, keyed by guild ID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotConfig {
+    pub schema_version: u64,
+    #[serde(flatten)]
+    pub guilds: BTreeMap<String, GuildConfig>,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        BotConfig {
+            schema_version: CONFIG_SCHEMA_VERSION,
+            guilds: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GuildConfig {
+    #[serde(default, skip_serializing_if = "LogChannels::is_empty")]
+    pub channels: LogChannels,
+    #[serde(default, skip_serializing_if = "TicketSettings::is_empty")]
+    pub tickets: TicketSettings,
+    /// Keyed by message ID.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub react_roles: BTreeMap<String, ReactRoleMessage>,
+    /// Keyed by the stat's lowercase name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom_stats: BTreeMap<String, StatDefinition>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub countdown_endings: BTreeMap<String, String>,
+    /// Bumped by every replicated change to this guild, so followers can tell when
+    /// they missed one. Stored with the config so it survives restarts.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub revision: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// Logging channel per event type. Anything unset falls back to `default`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogChannels {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_quit: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ticket: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moderation: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announcement: Option<u64>,
+    /// Where stat milestone congratulations are posted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub milestone: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TicketSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exempt_role: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_open: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReactRoleMessage {
+    pub channel_id: u64,
+    /// Emoji to role ID.
+    #[serde(default)]
+    pub roles: BTreeMap<String, u64>,
+}
+
+/// Who may add to a stat.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter)]
+#[serde(rename_all = "lowercase")]
+pub enum StatAwarders {
+    /// Members record their own progress.
+    #[default]
+    #[name = "self"]
+    #[serde(rename = "self")]
+    SelfOnly,
+    /// Only staff can award it, and only to other members.
+    #[name = "staff"]
+    Staff,
+    /// Members award it to each other but never to themselves.
+    #[name = "members"]
+    Members,
+}
+
+/// Rules and presentation for one custom stat. Every limit is optional.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatDefinition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<String>,
+    /// Smallest amount a single call may add (can be negative).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_delta: Option<i64>,
+    /// Largest amount a single call may add.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_delta: Option<i64>,
+    /// Seconds a member has to wait between changes to this stat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown: Option<u64>,
+    /// Roles allowed to change this stat; empty means everyone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_roles: Vec<u64>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub awarded_by: StatAwarders,
+    /// Roles granted while a member's total is at or above a threshold, lowest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub milestones: Vec<StatMilestone>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatMilestone {
+    pub threshold: i64,
+    pub role: u64,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+impl StatDefinition {
+    /// How the stat is shown to members, e.g. "⭐ Solves" or "`solves`".
+    pub fn label(&self, name: &str) -> String {
+        let name = match &self.display_name {
+            Some(display_name) => display_name.clone(),
+            None => format!("`{name}`"),
+        };
+        match &self.emoji {
+            Some(emoji) => format!("{emoji} {name}"),
+            None => name,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let (Some(min), Some(max)) = (self.min_delta, self.max_delta) {
+            if min > max {
+                return Err(format!("min_delta ({min}) is larger than max_delta ({max})").into());
+            }
+        }
+        if self.display_name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+            return Err("display_name must not be blank".into());
+        }
+        if self.emoji.as_deref().is_some_and(|emoji| emoji.trim().is_empty()) {
+            return Err("emoji must not be blank".into());
+        }
+        if self.milestones.windows(2).any(|pair| pair[0].threshold >= pair[1].threshold) {
+            return Err("milestones must have distinct thresholds in ascending order".into());
+        }
+        Ok(())
+    }
+}
+
+/// Schema 0 kept every setting as a flat key in the guild table (`logging_channel`,
+/// `ticket_roles`, ...). Schema 1 groups them under `channels` and `tickets`.
+fn migrate_flat_channel_keys(guild: &mut toml::Table) -> Result<(), ConfigError> {
+    const MOVES: &[(&str, &str, &str)] = &[
+        ("logging_channel", "channels", "default"),
+        ("boot_quit_channel", "channels", "boot_quit"),
+        ("member_log_channel", "channels", "member"),
+        ("ticket_log_channel", "channels", "ticket"),
+        ("mod_log_channel", "channels", "moderation"),
+        ("message_log_channel", "channels", "message"),
+        ("announcement_channel", "channels", "announcement"),
+        ("ticket_category", "tickets", "category"),
+        ("ticket_roles", "tickets", "roles"),
+        ("ticket_exempt_role", "tickets", "exempt_role"),
+        ("max_open_tickets", "tickets", "max_open"),
+        ("ticket_cooldown", "tickets", "cooldown"),
+    ];
+    for (old_key, section, new_key) in MOVES {
+        if let Some(value) = guild.remove(*old_key) {
+            guild.entry(*section)
+                .or_insert(Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| format!("`{section}` should be a table"))?
+                .insert((*new_key).to_owned(), value);
+        }
+    }
+    Ok(())
+}
+
+/// Schema 1 listed custom stats as plain names. Schema 2 makes each one a table of
+/// rules, so `custom_stats = ["solves"]` becomes an empty `[custom_stats.solves]`.
+fn migrate_stat_list_to_definitions(guild: &mut toml::Table) -> Result<(), ConfigError> {
+    let Some(stats) = guild.remove("custom_stats") else {
+        return Ok(());
+    };
+    let names = stats.as_array().ok_or("`custom_stats` should be a list of names")?;
+    let mut definitions = toml::Table::new();
+    for name in names {
+        let name = name.as_str().ok_or("`custom_stats` should only contain names")?;
+        definitions.insert(name.to_owned(), Value::Table(toml::Table::new()));
+    }
+    guild.insert("custom_stats".to_owned(), Value::Table(definitions));
+    Ok(())
+}
+
+/// Upgrades a raw config document to `CONFIG_SCHEMA_VERSION` in place. Returns the
+/// version the document was at before, if it had to be migrated.
+fn migrate_config(document: &mut Value) -> Result<Option<u64>, ConfigError> {
+    let root = document.as_table_mut().ok_or("config root should be a table")?;
+    let version = match root.get("schema_version") {
+        None => 0,
+        Some(value) => value.as_integer()
+            .and_then(|v| u64::try_from(v).ok())
+            .ok_or("schema_version should be a non-negative integer")?,
+    };
+    if version > CONFIG_SCHEMA_VERSION {
+        return Err(format!(
+            "schema_version {version} is newer than this build supports ({CONFIG_SCHEMA_VERSION})"
+        ).into());
+    }
+    if version == CONFIG_SCHEMA_VERSION {
+        return Ok(None);
+    }
+    for (guild_id, guild) in root.iter_mut().filter(|(key, _)| *key != "schema_version") {
+        let guild = guild.as_table_mut()
+            .ok_or_else(|| format!("`{guild_id}` should be a guild table"))?;
+        for migration in &CONFIG_MIGRATIONS[version as usize..] {
+            migration(guild).map_err(|e| format!("guild {guild_id}: {e}"))?;
+        }
+    }
+    root.insert("schema_version".to_owned(), Value::Integer(CONFIG_SCHEMA_VERSION as i64));
+    Ok(Some(version))
+}
+
+impl BotConfig {
+    /// Parses, migrates and validates a config document. Also returns the schema
+    /// version it was migrated from, if any.
+    pub fn parse(content: &str) -> Result<(Self, Option<u64>), ConfigError> {
+        let mut document: Value = toml::from_str(content)?;
+        let migrated_from = migrate_config(&mut document)?;
+        let config: BotConfig = document.try_into()?;
+        config.validate()?;
+        Ok((config, migrated_from))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (guild_id, guild) in &self.guilds {
+            if guild_id.parse::<u64>().is_err() {
+                return Err(format!("`[{guild_id}]` is not a guild ID").into());
+            }
+            guild.validate().map_err(|e| format!("guild {guild_id}: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+impl LogChannels {
+    fn is_empty(&self) -> bool {
+        *self == LogChannels::default()
+    }
+
+    fn slot(&mut self, channel_key: &str) -> Option<&mut Option<u64>> {
+        match channel_key {
+            "default" => Some(&mut self.default),
+            "boot_quit" => Some(&mut self.boot_quit),
+            "member" => Some(&mut self.member),
+            "ticket" => Some(&mut self.ticket),
+            "moderation" => Some(&mut self.moderation),
+            "message" => Some(&mut self.message),
+            "announcement" => Some(&mut self.announcement),
+            "milestone" => Some(&mut self.milestone),
+            _ => None,
+        }
+    }
+}
+
+impl TicketSettings {
+    fn is_empty(&self) -> bool {
+        *self == TicketSettings::default()
+    }
+}
+
+impl GuildConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.tickets.max_open == Some(0) {
+            return Err("tickets.max_open must be at least 1".into());
+        }
+        for message_id in self.react_roles.keys() {
+            if message_id.parse::<u64>().is_err() {
+                return Err(format!("react_roles key `{message_id}` is not a message ID").into());
+            }
+        }
+        for (stat, definition) in &self.custom_stats {
+            if stat.is_empty() || stat.to_lowercase() != *stat {
+                return Err(format!("custom stat `{stat}` must be a non-empty lowercase name").into());
+            }
+            definition.validate().map_err(|e| format!("custom stat `{stat}`: {e}"))?;
+        }
+        if let Some(name) = self.countdown_endings.keys().find(|name| name.to_lowercase() != **name) {
+            return Err(format!("countdown ending `{name}` must be lowercase").into());
+        }
+        Ok(())
+    }
+
+    pub fn logging_channel_for(&self, event_type: LogEventType) -> Option<u64> {
+        let channels = &self.channels;
+        let specific = match event_type {
+            LogEventType::BootQuit => channels.boot_quit,
+            LogEventType::MemberJoinLeave => channels.member,
+            LogEventType::TicketActivity => channels.ticket,
+            LogEventType::Moderation => channels.moderation,
+            LogEventType::Default => channels.default,
+            LogEventType::Announcements => channels.announcement,
+            LogEventType::MessageDeletion => channels.message,
+            LogEventType::Milestones => channels.milestone,
+        };
+        specific.or(channels.default)
+    }
+}
+
+/// A single replicated config mutation: the new value (or `None` for a removal) at
+/// `path` inside a guild's table. `version` is the guild's revision after the change,
+/// so a follower can tell whether it missed one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub guild_id: u64,
+    pub path: Vec<String>,
+    pub value: Option<Value>,
+    pub version: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOutcome {
+    Applied,
+    Duplicate,
+    Gap { expected: u64 },
+    /// The follower already has this revision but with a different value, so it and
+    /// the leader have drifted apart.
+    Diverged,
+}
+
+fn value_at<'a>(guild_value: &'a Value, path: &[impl AsRef<str>]) -> Option<&'a Value> {
+    path.iter().try_fold(guild_value, |v, key| v.get(key.as_ref()))
+}
+
+impl GuildConfig {
+    /// Runs `mutate` on this guild's config and keeps the result if it still validates.
+    /// `path` names the part of the guild table that changed, for replication. There is
+    /// no change to replicate if `mutate` left the config as it was.
+    pub fn update<T>(
+        &mut self,
+        guild_id: u64,
+        path: &[&str],
+        mutate: impl FnOnce(&mut GuildConfig) -> T,
+    ) -> Result<(T, Option<ConfigChange>), ConfigError> {
+        let mut guild = self.clone();
+        let result = mutate(&mut guild);
+        if guild == *self {
+            return Ok((result, None));
+        }
+        guild.validate()?;
+        guild.revision += 1;
+        let guild_value = Value::try_from(&guild)?;
+        let change = ConfigChange {
+            guild_id,
+            path: path.iter().map(|key| (*key).to_owned()).collect(),
+            value: value_at(&guild_value, path).cloned(),
+            version: guild.revision,
+        };
+        *self = guild;
+        Ok((result, Some(change)))
+    }
+
+    /// Applies a change recorded by the leader's `update`, if it is the next revision.
+    pub fn apply_change(&mut self, change: &ConfigChange) -> Result<ChangeOutcome, ConfigError> {
+        let mut guild_value = Value::try_from(&*self)?;
+        let current = self.revision;
+        if change.version == current && value_at(&guild_value, &change.path) != change.value.as_ref() {
+            return Ok(ChangeOutcome::Diverged);
+        }
+        if change.version <= current {
+            return Ok(ChangeOutcome::Duplicate);
+        }
+        if change.version > current + 1 {
+            return Ok(ChangeOutcome::Gap { expected: current + 1 });
+        }
+        let (key, parents) = change.path.split_last().ok_or("Config change has an empty path")?;
+        let mut table = guild_value.as_table_mut().ok_or("Guild section should be a table")?;
+        for parent in parents {
+            table = table
+                .entry(parent.clone())
+                .or_insert(Value::Table(toml::value::Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| format!("{parent} should be a table"))?;
+        }
+        match &change.value {
+            Some(value) => {
+                table.insert(key.clone(), value.clone());
+            }
+            None => {
+                table.remove(key);
+            }
+        }
+        let mut guild: GuildConfig = guild_value.try_into()?;
+        guild.validate()?;
+        guild.revision = change.version;
+        *self = guild;
+        Ok(ChangeOutcome::Applied)
+    }
+}
+
+/// Returns a copy of a guild's config, or the defaults if the guild has none.
+pub async fn guild_config(guild_id: u64) -> GuildConfig {
+    CONFIG_CACHE.read().await
+        .guilds
+        .get(&guild_id.to_string())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Updates a guild's config in the cache; see `GuildConfig::update`.
+pub async fn update_guild_config<T>(
+    guild_id: u64,
+    path: &[&str],
+    mutate: impl FnOnce(&mut GuildConfig) -> T,
+) -> Result<(T, Option<ConfigChange>), ConfigError> {
+    let mut config = CONFIG_CACHE.write().await;
+    let mut guild = config.guilds.get(&guild_id.to_string()).cloned().unwrap_or_default();
+    let (result, change) = guild.update(guild_id, path, mutate)?;
+    if change.is_some() {
+        config.guilds.insert(guild_id.to_string(), guild);
+        CONFIG_DIRTY.store(true, Ordering::SeqCst);
+    }
+    Ok((result, change))
+}
+
+pub async fn apply_config_change(change: &ConfigChange) -> Result<ChangeOutcome, ConfigError> {
+    let mut config = CONFIG_CACHE.write().await;
+    let mut guild = config.guilds.get(&change.guild_id.to_string()).cloned().unwrap_or_default();
+    let outcome = guild.apply_change(change)?;
+    if outcome == ChangeOutcome::Applied {
+        config.guilds.insert(change.guild_id.to_string(), guild);
+        CONFIG_DIRTY.store(true, Ordering::SeqCst);
+    }
+    Ok(outcome)
+}
+
+pub fn load_cluster_config() -> Result<ClusterConfig, ConfigError> {
     let config_content = fs::read_to_string(CLUSTER_CONFIG_PATH)?;
     let config: ClusterConfig = toml::from_str(&config_content)?;
     Ok(config)
@@ -43,155 +534,187 @@ pub enum LogEventType {
     Default,
     Announcements,
     MessageDeletion,
+    Milestones,
 }
 
-pub async fn get_config_as_string() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+pub async fn get_config_as_string() -> Result<String, ConfigError> {
     let config = CONFIG_CACHE.read().await;
     Ok(toml::to_string_pretty(&*config)?)
 }
 
-pub async fn update_config_from_str(config_str: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let new_config = config_str.parse::<Value>()?;
+/// Replaces the whole config with a leader's snapshot. Guilds the snapshot moves
+/// back to an older revision are logged, since their newer local changes are lost.
+pub async fn update_config_from_str(config_str: &str) -> Result<(), ConfigError> {
+    let (new_config, _) = BotConfig::parse(config_str)?;
     let mut config = CONFIG_CACHE.write().await;
+    for (guild_id, guild) in &config.guilds {
+        let snapshot_revision = new_config.guilds.get(guild_id).map_or(0, |g| g.revision);
+        if snapshot_revision < guild.revision {
+            println!(
+                "!! Leader snapshot rolls guild {guild_id} back from config revision {} to {snapshot_revision}",
+                guild.revision
+            );
+        }
+    }
     *config = new_config;
+    CONFIG_DIRTY.store(true, Ordering::SeqCst);
     Ok(())
 }
 
 pub async fn get_logging_channel(guild_id: u64, event_type: LogEventType) -> Option<ChannelId> {
-    let config = CONFIG_CACHE.read().await;
-    let guild_section = config.get(guild_id.to_string())
-        .and_then(|v| v.as_table())?;
-    let channel_key = match event_type {
-        LogEventType::BootQuit => "boot_quit_channel",
-        LogEventType::MemberJoinLeave => "member_log_channel",
-        LogEventType::TicketActivity => "ticket_log_channel",
-        LogEventType::Moderation => "mod_log_channel",
-        LogEventType::Default => "logging_channel",
-        LogEventType::Announcements => "announcement_channel",
-        LogEventType::MessageDeletion => "message_log_channel"
-    };
-    if let Some(channel_id) = guild_section.get(channel_key)
-        .and_then(|v| v.as_integer())
-    {
-        return Some(ChannelId::new(channel_id as u64));
-    }
-    guild_section.get("logging_channel")
-        .and_then(|v| v.as_integer())
-        .map(|channel_id| ChannelId::new(channel_id as u64))
+    guild_config(guild_id).await
+        .logging_channel_for(event_type)
+        .map(ChannelId::new)
 }
 
 pub async fn set_specific_logging_channel(
     guild_id: u64,
     channel_key: &str,
     channel_id: u64
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    let guild_table = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .entry(guild_id.to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("Guild section should be a table");
-    guild_table.insert(channel_key.to_owned(), Value::Integer(channel_id as i64));
-    Ok(())
-}
-
-fn load_config_from_disk() -> Value {
-    match fs::read_to_string(CONFIG_PATH) {
-        Ok(toml_content) => {
-            toml_content.parse::<Value>().unwrap_or_else(|_| {
-                Value::Table(toml::value::Table::new())
-            })
-        },
-        Err(_) => {
-            let default_config = Value::Table(toml::value::Table::new());
-            let _ = fs::write(CONFIG_PATH, toml::to_string_pretty(&default_config).unwrap_or_default());
+) -> Result<Option<ConfigChange>, ConfigError> {
+    if LogChannels::default().slot(channel_key).is_none() {
+        return Err(format!("Unknown logging channel `{channel_key}`").into());
+    }
+    let (_, change) = update_guild_config(guild_id, &["channels", channel_key], |guild| {
+        if let Some(slot) = guild.channels.slot(channel_key) {
+            *slot = Some(channel_id);
+        }
+    }).await?;
+    Ok(change)
+}
+
+/// Loads the config from storage into the cache, creating an empty one if there is none.
+/// A config that fails to parse or validate is an error rather than an empty config.
+/// Older schema versions are migrated after backing up the original document.
+pub async fn load_config_from_disk() -> Result<(), ConfigError> {
+    let storage = crate::storage::get();
+    let config = match storage.load_config()? {
+        Some(toml_content) => {
+            let (config, migrated_from) = BotConfig::parse(&toml_content)
+                .map_err(|e| format!("{CONFIG_PATH} is invalid: {e}"))?;
+            if let Some(old_version) = migrated_from {
+                storage.backup_config(&format!("v{old_version}"), &toml_content)?;
+                storage.save_config(&toml::to_string_pretty(&config)?)?;
+                println!("Migrated config from schema version {old_version} to {CONFIG_SCHEMA_VERSION} (backup labelled v{old_version})");
+            }
+            config
+        }
+        None => {
+            let default_config = BotConfig::default();
+            storage.save_config(&toml::to_string_pretty(&default_config)?)?;
             default_config
         }
-    }
+    };
+    *CONFIG_CACHE.write().await = config;
+    Ok(())
 }
 
-pub async fn save_config_to_disk() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let config = CONFIG_CACHE.read().await;
-    let new_toml = toml::to_string_pretty(&*config)?;
-    fs::write(CONFIG_PATH, new_toml)?;
+/// Replaces `path` with `contents` so that a crash leaves either the old or the new
+/// file, never a truncated one: write a sibling temp file, fsync it, rename it over.
+pub fn write_atomic(path: impl AsRef<Path>, contents: &[u8]) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::File::open(parent)?.sync_all()?;
+    }
     Ok(())
 }
 
-pub async fn get_ticket_roles(guild_id: u64) -> Vec<u64> {
-    let config = CONFIG_CACHE.read().await;
-    if let Some(guild_table) = config.get(guild_id.to_string()).and_then(|v| v.as_table()) {
-        if let Some(roles) = guild_table.get("ticket_roles").and_then(|v| v.as_array()) {
-            return roles.iter()
-                .filter_map(|v| v.as_integer().map(|x| x as u64))
-                .collect();
-        }
+async fn write_config_snapshot() -> Result<(), ConfigError> {
+    CONFIG_DIRTY.store(false, Ordering::SeqCst);
+    let new_toml = {
+        let config = CONFIG_CACHE.read().await;
+        toml::to_string_pretty(&*config)?
+    };
+    let result = tokio::task::spawn_blocking(move || {
+        crate::storage::get().save_config(&new_toml)
+    }).await?;
+    if result.is_err() {
+        CONFIG_DIRTY.store(true, Ordering::SeqCst);
     }
-    Vec::new()
+    result
 }
 
-pub async fn add_ticrole(guild_id: u64, role_id: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    let guild_table = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .entry(guild_id.to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("Guild section should be a table");
-    let roles = guild_table
-        .entry("ticket_roles")
-        .or_insert(Value::Array(Vec::new()))
-        .as_array_mut()
-        .expect("ticket_roles should be an array");
-    if !roles.iter().any(|v| v.as_integer() == Some(role_id as i64)) {
-        roles.push(Value::Integer(role_id as i64));
+/// Starts the task that owns all config writes. Save requests that queue up
+/// while a write is in progress are answered by a single write, and unsaved changes
+/// are flushed every `CONFIG_AUTOSAVE_INTERVAL` seconds.
+pub fn start_config_writer() {
+    let (tx, mut rx) = mpsc::unbounded_channel::<oneshot::Sender<Result<(), String>>>();
+    if CONFIG_WRITER.set(tx).is_err() {
+        return;
     }
-    Ok(())
+    tokio::spawn(async move {
+        let mut autosave = tokio::time::interval(std::time::Duration::from_secs(CONFIG_AUTOSAVE_INTERVAL));
+        loop {
+            tokio::select! {
+                request = rx.recv() => {
+                    let Some(first) = request else { break };
+                    let mut waiting = vec![first];
+                    while let Ok(next) = rx.try_recv() {
+                        waiting.push(next);
+                    }
+                    let result = write_config_snapshot().await.map_err(|e| e.to_string());
+                    for reply in waiting {
+                        let _ = reply.send(result.clone());
+                    }
+                }
+                _ = autosave.tick() => {
+                    if CONFIG_DIRTY.load(Ordering::SeqCst) {
+                        if let Err(e) = write_config_snapshot().await {
+                            println!("!! Failed to autosave config: {e}");
+                        }
+                    }
+                }
+            }
+        }
+    });
 }
 
-pub async fn remove_ticrole(guild_id: u64, role_id: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    if let Some(guild_table) = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .get_mut(&guild_id.to_string())
-        .and_then(|v| v.as_table_mut())
-    {
-        if let Some(roles) = guild_table
-            .get_mut("ticket_roles")
-            .and_then(|v| v.as_array_mut())
-        {
-            roles.retain(|v| v.as_integer() != Some(role_id as i64));
+pub async fn save_config_to_disk() -> Result<(), ConfigError> {
+    let Some(writer) = CONFIG_WRITER.get() else {
+        return write_config_snapshot().await;
+    };
+    let (reply_tx, reply_rx) = oneshot::channel();
+    writer.send(reply_tx).map_err(|_| "Config writer has stopped")?;
+    Ok(reply_rx.await.map_err(|_| "Config writer has stopped")??)
+}
+
+pub async fn get_ticket_roles(guild_id: u64) -> Vec<u64> {
+    guild_config(guild_id).await.tickets.roles
+}
+
+pub async fn add_ticrole(guild_id: u64, role_id: u64) -> Result<Option<ConfigChange>, ConfigError> {
+    let (_, change) = update_guild_config(guild_id, &["tickets", "roles"], |guild| {
+        if !guild.tickets.roles.contains(&role_id) {
+            guild.tickets.roles.push(role_id);
         }
-    }
-    Ok(())
+    }).await?;
+    Ok(change)
+}
+
+pub async fn remove_ticrole(guild_id: u64, role_id: u64) -> Result<Option<ConfigChange>, ConfigError> {
+    let (_, change) = update_guild_config(guild_id, &["tickets", "roles"], |guild| {
+        guild.tickets.roles.retain(|id| *id != role_id);
+    }).await?;
+    Ok(change)
 }
 
 pub async fn get_ticket_category(guild_id: u64) -> Option<serenity::ChannelId> {
-    let config = CONFIG_CACHE.read().await;
-    config.get(guild_id.to_string())
-        .and_then(|v| v.as_table())
-        .and_then(|guild_table| guild_table.get("ticket_category"))
-        .and_then(|v| v.as_integer())
-        .map(|category_id| serenity::ChannelId::new(category_id as u64))
+    guild_config(guild_id).await.tickets.category.map(serenity::ChannelId::new)
 }
 
-pub async fn get_logging_channels() -> HashMap<String, i64> {
+pub async fn get_logging_channels() -> HashMap<u64, u64> {
     let config = CONFIG_CACHE.read().await;
-    config.as_table()
-        .map(|table| {
-            table.iter()
-                .filter_map(|(guild_id, v)| {
-                    v.as_table()
-                        .and_then(|guild_table| guild_table.get("logging_channel"))
-                        .and_then(|v| v.as_integer())
-                        .map(|channel_id| (guild_id.clone(), channel_id))
-                })
-                .collect()
-        })
-        .unwrap_or_default()
+    config.guilds.iter()
+        .filter_map(|(guild_id, guild)| Some((guild_id.parse().ok()?, guild.channels.default?)))
+        .collect()
 }
 
 pub fn get_ticket_template_path(guild_id: u64) -> String {
@@ -199,38 +722,28 @@ pub fn get_ticket_template_path(guild_id: u64) -> String {
 }
 
 pub async fn get_ticket_exempt_role(guild_id: u64) -> Option<u64> {
-    let config = CONFIG_CACHE.read().await;
-    config.get(guild_id.to_string())
-        .and_then(|v| v.as_table())
-        .and_then(|guild_table| guild_table.get("ticket_exempt_role"))
-        .and_then(|v| v.as_integer())
-        .map(|role_id| role_id as u64)
+    guild_config(guild_id).await.tickets.exempt_role
 }
 
-pub async fn set_ticket_exempt_role(guild_id: u64, role_id: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    let guild_table = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .entry(guild_id.to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("Guild section should be a table");
-    guild_table.insert("ticket_exempt_role".to_owned().to_string(), Value::Integer(role_id as i64));
-    Ok(())
+pub async fn set_ticket_exempt_role(guild_id: u64, role_id: u64) -> Result<Option<ConfigChange>, ConfigError> {
+    let (_, change) = update_guild_config(guild_id, &["tickets", "exempt_role"], |guild| {
+        guild.tickets.exempt_role = Some(role_id);
+    }).await?;
+    Ok(change)
 }
 
-pub async fn set_ticket_category(guild_id: u64, category_id: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    let guild_table = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .entry(guild_id.to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("Guild section should be a table");
-    guild_table.insert("ticket_category".to_owned().to_string(), Value::Integer(category_id as i64));
-    Ok(())
+pub async fn remove_ticket_exempt_role(guild_id: u64) -> Result<Option<ConfigChange>, ConfigError> {
+    let (_, change) = update_guild_config(guild_id, &["tickets", "exempt_role"], |guild| {
+        guild.tickets.exempt_role = None;
+    }).await?;
+    Ok(change)
+}
+
+pub async fn set_ticket_category(guild_id: u64, category_id: u64) -> Result<Option<ConfigChange>, ConfigError> {
+    let (_, change) = update_guild_config(guild_id, &["tickets", "category"], |guild| {
+        guild.tickets.category = Some(category_id);
+    }).await?;
+    Ok(change)
 }
 
 pub async fn add_react_role(
@@ -239,260 +752,264 @@ pub async fn add_react_role(
     message_id: u64,
     emoji: String,
     role_id: u64,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    let guild_table = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .entry(guild_id.to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("Guild section should be a table");
-    let react_roles_table = guild_table
-        .entry("react_roles".to_owned().to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("react_roles should be a table");
-    let message_entry = react_roles_table
-        .entry(message_id.to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("message entry should be a table");
-    message_entry.insert("channel_id".to_owned().to_string(), Value::Integer(channel_id as i64));
-    let roles_map = message_entry
-        .entry("roles".to_owned().to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("roles map should be a table");
-    roles_map.insert(emoji, Value::Integer(role_id as i64));
-    Ok(())
+) -> Result<Option<ConfigChange>, ConfigError> {
+    let message_key = message_id.to_string();
+    let (_, change) = update_guild_config(guild_id, &["react_roles", &message_key], |guild| {
+        let entry = guild.react_roles.entry(message_key.clone()).or_default();
+        entry.channel_id = channel_id;
+        entry.roles.insert(emoji, role_id);
+    }).await?;
+    Ok(change)
 }
 
 pub async fn remove_react_role(
     guild_id: u64,
     message_id: u64,
     emoji: &str,
-) -> Result<Option<u64>, Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    let guild_table = match config.get_mut(guild_id.to_string()).and_then(|g| g.as_table_mut()) {
-        Some(table) => table,
-        None => return Ok(None),
-    };
-    let react_roles_table = match guild_table.get_mut("react_roles").and_then(|rr| rr.as_table_mut()) {
-        Some(table) => table,
-        None => return Ok(None),
-    };
-    let message_entry = match react_roles_table.get_mut(&message_id.to_string()).and_then(|m| m.as_table_mut()) {
-        Some(table) => table,
-        None => return Ok(None),
-    };
-    let roles_map = match message_entry.get_mut("roles").and_then(|r| r.as_table_mut()) {
-        Some(table) => table,
-        None => return Ok(None),
-    };
-    let removed_role_id = roles_map.remove(emoji).and_then(|v| v.as_integer().map(|id| id as u64));
-    if roles_map.is_empty() {
-        react_roles_table.remove(&message_id.to_string());
-    }
-    Ok(removed_role_id)
+) -> Result<Option<(u64, ConfigChange)>, ConfigError> {
+    let message_key = message_id.to_string();
+    let (removed_role_id, change) = update_guild_config(guild_id, &["react_roles", &message_key], |guild| {
+        let entry = guild.react_roles.get_mut(&message_key)?;
+        let role_id = entry.roles.remove(emoji)?;
+        if entry.roles.is_empty() {
+            guild.react_roles.remove(&message_key);
+        }
+        Some(role_id)
+    }).await?;
+    Ok(removed_role_id.zip(change))
 }
 
 pub async fn get_react_role(guild_id: u64, message_id: u64, emoji: &str) -> Option<u64> {
-    let config = CONFIG_CACHE.read().await;
-    config
-        .get(guild_id.to_string())
-        .and_then(|g| g.as_table())
-        .and_then(|guild_table| guild_table.get("react_roles"))
-        .and_then(|rr| rr.as_table())
-        .and_then(|messages| messages.get(&message_id.to_string()))
-        .and_then(|entry| entry.as_table())
-        .and_then(|message_entry| message_entry.get("roles"))
-        .and_then(|r| r.as_table())
-        .and_then(|roles_map| roles_map.get(emoji))
-        .and_then(|v| v.as_integer())
-        .map(|role_id| role_id as u64)
+    guild_config(guild_id).await
+        .react_roles
+        .get(&message_id.to_string())
+        .and_then(|entry| entry.roles.get(emoji).copied())
 }
 
 pub async fn prune_dead_react_roles(
     http: &serenity::Http,
     guild_id: u64,
-) -> Result<Vec<u64>, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(Vec<u64>, Option<ConfigChange>), ConfigError> {
     let mut pruned_ids = Vec::new();
-    let mut config = CONFIG_CACHE.write().await;
-
-    let guild_table = match config.get_mut(guild_id.to_string()).and_then(|g| g.as_table_mut()) {
-        Some(table) => table,
-        None => return Ok(pruned_ids),
-    };
-    let react_roles_table = match guild_table.get_mut("react_roles").and_then(|rr| rr.as_table_mut()) {
-        Some(table) => table,
-        None => return Ok(pruned_ids),
-    };
-    let mut dead_message_ids = Vec::new();
-    for (message_id_str, entry_value) in react_roles_table.iter() {
-        if let Some(entry_table) = entry_value.as_table() {
-            if let (Some(message_id), Some(channel_id)) = (
-                message_id_str.parse::<u64>().ok(),
-                entry_table.get("channel_id").and_then(|v| v.as_integer()).map(|id| id as u64)
-            ) {
-                let channel = ChannelId::new(channel_id);
-                if channel.message(http, message_id).await.is_err() {
-                    println!("Pruning dead react-role config for message ID: {message_id}");
-                    dead_message_ids.push(message_id_str.clone());
-                    pruned_ids.push(message_id);
-                }
-            }
+    for (message_id_str, entry) in guild_config(guild_id).await.react_roles {
+        let Ok(message_id) = message_id_str.parse::<u64>() else {
+            continue;
+        };
+        let channel = ChannelId::new(entry.channel_id);
+        if channel.message(http, message_id).await.is_err() {
+            println!("Pruning dead react-role config for message ID: {message_id}");
+            pruned_ids.push(message_id);
         }
     }
-    for id in dead_message_ids {
-        react_roles_table.remove(&id);
+    if pruned_ids.is_empty() {
+        return Ok((pruned_ids, None));
     }
-    Ok(pruned_ids)
+    let (_, change) = update_guild_config(guild_id, &["react_roles"], |guild| {
+        for message_id in &pruned_ids {
+            guild.react_roles.remove(&message_id.to_string());
+        }
+    }).await?;
+    Ok((pruned_ids, change))
 }
 
-pub async fn add_custom_stat(guild_id: u64, stat_name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    let guild_table = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .entry(guild_id.to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("Guild section should be a table");
-    let stats = guild_table
-        .entry("custom_stats")
-        .or_insert(Value::Array(Vec::new()))
-        .as_array_mut()
-        .expect("custom_stats should be an array");
-        
-    if !stats.iter().any(|v| v.as_str() == Some(stat_name)) {
-        stats.push(Value::String(stat_name.to_lowercase()));
-    }
-    Ok(())
+pub async fn add_custom_stat(guild_id: u64, stat_name: &str) -> Result<Option<ConfigChange>, ConfigError> {
+    let stat_lower = stat_name.to_lowercase();
+    let (_, change) = update_guild_config(guild_id, &["custom_stats", &stat_lower], |guild| {
+        guild.custom_stats.entry(stat_lower.clone()).or_default();
+    }).await?;
+    Ok(change)
 }
 
-pub async fn remove_custom_stat(guild_id: u64, stat_name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    if let Some(guild_table) = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .get_mut(&guild_id.to_string())
-        .and_then(|v| v.as_table_mut())
-    {
-        if let Some(stats) = guild_table
-            .get_mut("custom_stats")
-            .and_then(|v| v.as_array_mut())
-        {
-            let stat_lower = stat_name.to_lowercase();
-            stats.retain(|v| v.as_str() != Some(&stat_lower));
-        }
-    }
-    Ok(())
+pub async fn remove_custom_stat(guild_id: u64, stat_name: &str) -> Result<Option<ConfigChange>, ConfigError> {
+    let stat_lower = stat_name.to_lowercase();
+    let (_, change) = update_guild_config(guild_id, &["custom_stats", &stat_lower], |guild| {
+        guild.custom_stats.remove(&stat_lower);
+    }).await?;
+    Ok(change)
 }
 
 pub async fn get_custom_stats(guild_id: u64) -> Vec<String> {
-    let config = CONFIG_CACHE.read().await;
-    if let Some(guild_table) = config.get(guild_id.to_string()).and_then(|v| v.as_table()) {
-        if let Some(stats) = guild_table.get("custom_stats").and_then(|v| v.as_array()) {
-            return stats.iter()
-                .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                .collect();
-        }
-    }
-    Vec::new()
+    guild_config(guild_id).await.custom_stats.into_keys().collect()
 }
 
-pub async fn add_countdown_ending(guild_id: u64, name: &str, message: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    let guild_table = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .entry(guild_id.to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("Guild section should be a table");
-    let endings = guild_table
-        .entry("countdown_endings".to_owned())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("countdown_endings should be a table");
-    endings.insert(name.to_lowercase(), Value::String(message.to_string()));
-    Ok(())
+pub async fn get_stat_definition(guild_id: u64, stat_name: &str) -> Option<StatDefinition> {
+    guild_config(guild_id).await.custom_stats.remove(stat_name)
 }
 
-pub async fn remove_countdown_ending(guild_id: u64, name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    if let Some(guild_table) = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .get_mut(&guild_id.to_string())
-        .and_then(|v| v.as_table_mut())
-    {
-        if let Some(endings) = guild_table
-            .get_mut("countdown_endings")
-            .and_then(|v| v.as_table_mut())
-        {
-            endings.remove(&name.to_lowercase());
-        }
-    }
-    Ok(())
+pub async fn set_stat_definition(guild_id: u64, stat_name: &str, definition: StatDefinition) -> Result<Option<ConfigChange>, ConfigError> {
+    definition.validate()?;
+    let (_, change) = update_guild_config(guild_id, &["custom_stats", stat_name], |guild| {
+        guild.custom_stats.insert(stat_name.to_owned(), definition);
+    }).await?;
+    Ok(change)
+}
+
+pub async fn add_countdown_ending(guild_id: u64, name: &str, message: &str) -> Result<Option<ConfigChange>, ConfigError> {
+    let name = name.to_lowercase();
+    let (_, change) = update_guild_config(guild_id, &["countdown_endings", &name], |guild| {
+        guild.countdown_endings.insert(name.clone(), message.to_owned());
+    }).await?;
+    Ok(change)
+}
+
+pub async fn remove_countdown_ending(guild_id: u64, name: &str) -> Result<Option<ConfigChange>, ConfigError> {
+    let name = name.to_lowercase();
+    let (_, change) = update_guild_config(guild_id, &["countdown_endings", &name], |guild| {
+        guild.countdown_endings.remove(&name);
+    }).await?;
+    Ok(change)
 }
 
 pub async fn get_countdown_endings(guild_id: u64) -> HashMap<String, String> {
-    let config = CONFIG_CACHE.read().await;
-    let mut result = HashMap::new();
-    if let Some(guild_table) = config.get(guild_id.to_string()).and_then(|v| v.as_table()) {
-        if let Some(endings) = guild_table.get("countdown_endings").and_then(|v| v.as_table()) {
-            for (k, v) in endings {
-                if let Some(s) = v.as_str() {
-                    result.insert(k.clone(), s.to_string());
-                }
-            }
-        }
-    }
-    result
+    guild_config(guild_id).await.countdown_endings.into_iter().collect()
 }
 
-pub async fn set_max_open_tickets(guild_id: u64, limit: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    let guild_table = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .entry(guild_id.to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("Guild section should be a table");
-    guild_table.insert("max_open_tickets".to_string(), Value::Integer(limit as i64));
-    Ok(())
+pub async fn set_max_open_tickets(guild_id: u64, limit: u64) -> Result<Option<ConfigChange>, ConfigError> {
+    let (_, change) = update_guild_config(guild_id, &["tickets", "max_open"], |guild| {
+        guild.tickets.max_open = Some(limit);
+    }).await?;
+    Ok(change)
 }
 
 pub async fn get_max_open_tickets(guild_id: u64) -> Option<u64> {
-    let config = CONFIG_CACHE.read().await;
-    config.get(guild_id.to_string())
-        .and_then(|v| v.as_table())
-        .and_then(|t| t.get("max_open_tickets"))
-        .and_then(|v| v.as_integer())
-        .map(|i| i as u64)
+    guild_config(guild_id).await.tickets.max_open
 }
 
-pub async fn set_ticket_cooldown(guild_id: u64, seconds: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = CONFIG_CACHE.write().await;
-    let guild_table = config
-        .as_table_mut()
-        .expect("Root should be a table")
-        .entry(guild_id.to_string())
-        .or_insert(Value::Table(toml::value::Table::new()))
-        .as_table_mut()
-        .expect("Guild section should be a table");
-    guild_table.insert("ticket_cooldown".to_string(), Value::Integer(seconds as i64));
-    Ok(())
+pub async fn set_ticket_cooldown(guild_id: u64, seconds: u64) -> Result<Option<ConfigChange>, ConfigError> {
+    let (_, change) = update_guild_config(guild_id, &["tickets", "cooldown"], |guild| {
+        guild.tickets.cooldown = Some(seconds);
+    }).await?;
+    Ok(change)
 }
 
 pub async fn get_ticket_cooldown(guild_id: u64) -> Option<u64> {
-    let config = CONFIG_CACHE.read().await;
-    config.get(guild_id.to_string())
-        .and_then(|v| v.as_table())
-        .and_then(|t| t.get("ticket_cooldown"))
-        .and_then(|v| v.as_integer())
-        .map(|i| i as u64)
+    guild_config(guild_id).await.tickets.cooldown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_the_flat_layout() {
+        let (config, migrated_from) = BotConfig::parse(r#"
+            [123]
+            logging_channel = 1
+            mod_log_channel = 2
+            ticket_category = 3
+            ticket_roles = [4, 5]
+            max_open_tickets = 2
+            custom_stats = ["solves", "bugs"]
+        "#).unwrap();
+        assert_eq!(migrated_from, Some(0));
+        assert_eq!(config.schema_version, CONFIG_SCHEMA_VERSION);
+        let guild = &config.guilds["123"];
+        assert_eq!(guild.channels.default, Some(1));
+        assert_eq!(guild.channels.moderation, Some(2));
+        assert_eq!(guild.logging_channel_for(LogEventType::MemberJoinLeave), Some(1));
+        assert_eq!(guild.tickets.category, Some(3));
+        assert_eq!(guild.tickets.roles, vec![4, 5]);
+        assert_eq!(guild.tickets.max_open, Some(2));
+        assert_eq!(guild.custom_stats.keys().collect::<Vec<_>>(), ["bugs", "solves"]);
+        assert_eq!(guild.custom_stats["solves"], StatDefinition::default());
+    }
+
+    #[test]
+    fn migrates_from_an_intermediate_version() {
+        let (config, migrated_from) = BotConfig::parse(r#"
+            schema_version = 1
+            [123]
+            custom_stats = ["solves"]
+            [123.channels]
+            default = 1
+        "#).unwrap();
+        assert_eq!(migrated_from, Some(1));
+        let guild = &config.guilds["123"];
+        assert_eq!(guild.channels.default, Some(1));
+        assert!(guild.custom_stats.contains_key("solves"));
+    }
+
+    #[test]
+    fn leaves_current_configs_alone() {
+        let content = format!("schema_version = {CONFIG_SCHEMA_VERSION}\n[123.tickets]\ncooldown = 30\n");
+        let (config, migrated_from) = BotConfig::parse(&content).unwrap();
+        assert_eq!(migrated_from, None);
+        assert_eq!(config.guilds["123"].tickets.cooldown, Some(30));
+    }
+
+    #[test]
+    fn rejects_configs_it_cannot_migrate() {
+        let newer = format!("schema_version = {}\n", CONFIG_SCHEMA_VERSION + 1);
+        assert!(BotConfig::parse(&newer).unwrap_err().to_string().contains("newer than this build supports"));
+        assert!(BotConfig::parse("[123]\ncustom_stats = \"solves\"\n").unwrap_err().to_string().contains("guild 123"));
+        assert!(BotConfig::parse("[123]\nlogging_chanel = 1\n").is_err());
+        assert!(BotConfig::parse("[abc]\nlogging_channel = 1\n").unwrap_err().to_string().contains("not a guild ID"));
+    }
+
+    fn set_cooldown(guild: &mut GuildConfig, seconds: Option<u64>) -> Option<ConfigChange> {
+        guild.update(1, &["tickets", "cooldown"], |guild| guild.tickets.cooldown = seconds).unwrap().1
+    }
+
+    #[test]
+    fn applies_changes_in_order() {
+        let mut leader = GuildConfig::default();
+        let mut follower = GuildConfig::default();
+        let first = set_cooldown(&mut leader, Some(30)).unwrap();
+        assert_eq!(first.version, 1);
+        assert_eq!(follower.apply_change(&first).unwrap(), ChangeOutcome::Applied);
+        assert_eq!(follower, leader);
+        assert_eq!(follower.apply_change(&first).unwrap(), ChangeOutcome::Duplicate);
+
+        let removal = set_cooldown(&mut leader, None).unwrap();
+        assert_eq!(removal.value, None);
+        assert_eq!(follower.apply_change(&removal).unwrap(), ChangeOutcome::Applied);
+        assert_eq!(follower, leader);
+        assert_eq!(follower.apply_change(&first).unwrap(), ChangeOutcome::Duplicate);
+    }
+
+    #[test]
+    fn reports_gaps_and_divergence() {
+        let mut leader = GuildConfig::default();
+        let mut follower = GuildConfig::default();
+        set_cooldown(&mut leader, Some(30)).unwrap();
+        let second = set_cooldown(&mut leader, Some(60)).unwrap();
+        assert_eq!(follower.apply_change(&second).unwrap(), ChangeOutcome::Gap { expected: 1 });
+        assert_eq!(follower, GuildConfig::default());
+
+        // Both sides reached revision 1, but with different values.
+        let mut drifted = GuildConfig::default();
+        set_cooldown(&mut drifted, Some(10)).unwrap();
+        let mut leader = GuildConfig::default();
+        let change = set_cooldown(&mut leader, Some(30)).unwrap();
+        assert_eq!(drifted.apply_change(&change).unwrap(), ChangeOutcome::Diverged);
+    }
+
+    #[test]
+    fn unchanged_updates_keep_the_revision() {
+        let mut guild = GuildConfig::default();
+        set_cooldown(&mut guild, Some(30)).unwrap();
+        assert!(set_cooldown(&mut guild, Some(30)).is_none());
+        let (removed, change) = guild.update(1, &["custom_stats", "missing"], |guild| {
+            guild.custom_stats.remove("missing").is_some()
+        }).unwrap();
+        assert!(!removed);
+        assert!(change.is_none());
+        assert_eq!(guild.revision, 1);
+    }
+
+    #[test]
+    fn rejects_changes_that_fail_validation() {
+        let mut leader = GuildConfig::default();
+        assert!(leader.update(1, &["tickets", "max_open"], |guild| guild.tickets.max_open = Some(0)).is_err());
+        assert_eq!(leader, GuildConfig::default());
+        let change = ConfigChange {
+            guild_id: 1,
+            path: vec!["tickets".to_owned(), "max_open".to_owned()],
+            value: Some(Value::Integer(0)),
+            version: 1,
+        };
+        let mut follower = GuildConfig::default();
+        assert!(follower.apply_change(&change).is_err());
+        assert_eq!(follower, GuildConfig::default());
+    }
 }